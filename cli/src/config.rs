@@ -0,0 +1,34 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use anyhow::Context;
+use serde::Deserialize;
+use uuid::Uuid;
+
+/// One `[name]` table of the config file
+#[derive(Deserialize)]
+pub struct Profile {
+    pub url: String,
+    pub key_id: Uuid,
+    pub key: String,
+}
+
+/// `BUCKET_CONFIG` when set, `<config dir>/bucket/config.toml` otherwise
+fn config_path() -> anyhow::Result<PathBuf> {
+    if let Some(path) = env::var_os("BUCKET_CONFIG") {
+        return Ok(PathBuf::from(path));
+    }
+    let dir = dirs::config_dir().context("Could not determine config directory")?;
+    Ok(dir.join("bucket").join("config.toml"))
+}
+
+pub fn load(profile: &str) -> anyhow::Result<Profile> {
+    let path = config_path()?;
+    let contents = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    let mut profiles: HashMap<String, Profile> = toml::from_str(&contents)
+        .with_context(|| format!("Invalid config file {}", path.display()))?;
+    profiles
+        .remove(profile)
+        .with_context(|| format!("Profile `{profile}` not found in {}", path.display()))
+}
@@ -0,0 +1,4 @@
+//! Building blocks of the `bucket` command, shared with the tests of the server.
+pub mod config;
+pub mod sync;
+pub mod transfer;
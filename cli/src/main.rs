@@ -0,0 +1,140 @@
+use std::path::PathBuf;
+use anyhow::{bail, Context};
+use bucket_storage_client::{Client, FileEntry};
+use clap::{Parser, Subcommand};
+use futures::{stream, StreamExt, TryStreamExt};
+use indicatif::MultiProgress;
+use uuid::Uuid;
+use bucket_cli::{config, sync, transfer};
+
+/// Command-line client for bucket storage
+#[derive(Parser)]
+#[command(name = "bucket")]
+struct Cli {
+    /// Profile of the config file holding the server url and credentials
+    #[arg(long, env = "BUCKET_PROFILE", default_value = "default", global = true)]
+    profile: String,
+    /// Number of transfers running in parallel
+    #[arg(short, long, default_value_t = 4, global = true)]
+    jobs: usize,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Upload files, glob patterns are expanded
+    Upload {
+        #[arg(required = true)]
+        patterns: Vec<String>,
+    },
+    /// Download entries by id or name
+    Download {
+        #[arg(required = true)]
+        files: Vec<String>,
+        /// Directory the files are written to
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+    },
+    /// List bucket entries
+    List,
+    /// Delete entries by id or name
+    Delete {
+        #[arg(required = true)]
+        files: Vec<String>,
+    },
+    /// Mirror a local directory and its subdirectories into the bucket, entries named by their relative paths
+    Sync {
+        dir: PathBuf,
+        /// Delete bucket entries missing from the directory
+        #[arg(long)]
+        delete: bool,
+    },
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let profile = config::load(&cli.profile)?;
+    let client = Client::new(profile.url).with_credentials(profile.key_id, profile.key);
+    let progress = MultiProgress::new();
+    let jobs = cli.jobs.max(1);
+
+    match cli.command {
+        Command::Upload { patterns } => {
+            let paths = expand(&patterns)?;
+            stream::iter(paths)
+                .map(|path| {
+                    let (client, progress) = (&client, &progress);
+                    async move {
+                        let name = path.file_name().and_then(|name| name.to_str()).context("Invalid file name")?.to_string();
+                        let file_id = transfer::upload(client, &path, &name, transfer::bar(progress, &name)).await?;
+                        progress.println(format!("{file_id}\t{name}"))?;
+                        anyhow::Ok(())
+                    }
+                })
+                .buffer_unordered(jobs)
+                .try_collect::<Vec<_>>()
+                .await?;
+        }
+        Command::Download { files, output } => {
+            let entries = client.list().await?;
+            let targets = files.iter().map(|file| resolve(&entries, file)).collect::<anyhow::Result<Vec<_>>>()?;
+            stream::iter(targets)
+                .map(|entry| {
+                    let (client, progress, output) = (&client, &progress, &output);
+                    async move {
+                        let name = entry.file_name();
+                        transfer::download(client, entry.id, &output.join(&name), transfer::bar(progress, &name)).await
+                    }
+                })
+                .buffer_unordered(jobs)
+                .try_collect::<Vec<_>>()
+                .await?;
+        }
+        Command::List => {
+            for entry in client.list().await? {
+                println!("{}\t{}\t{}", entry.id, entry.size, entry.file_name());
+            }
+        }
+        Command::Delete { files } => {
+            let entries = client.list().await?;
+            for file in files {
+                let entry = resolve(&entries, &file)?;
+                client.delete(entry.id).await?;
+                println!("Deleted {}", entry.file_name());
+            }
+        }
+        Command::Sync { dir, delete } => {
+            let report = sync::sync(&client, &dir, delete, jobs, &progress).await?;
+            println!("{} uploaded, {} unchanged, {} deleted", report.uploaded, report.unchanged, report.deleted);
+        }
+    }
+    Ok(())
+}
+
+/// Expands glob patterns, keeping patterns that match nothing as literal paths
+fn expand(patterns: &[String]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        let matches = glob::glob(pattern)?.collect::<Result<Vec<_>, _>>()?;
+        if matches.is_empty() {
+            paths.push(PathBuf::from(pattern));
+        }
+        paths.extend(matches.into_iter().filter(|path| path.is_file()));
+    }
+    Ok(paths)
+}
+
+/// Finds the entry named by an id or by its file name
+fn resolve(entries: &[FileEntry], file: &str) -> anyhow::Result<FileEntry> {
+    let matching = match Uuid::parse_str(file) {
+        Ok(id) => entries.iter().filter(|entry| entry.id == id).collect::<Vec<_>>(),
+        Err(_) => entries.iter().filter(|entry| entry.file_name() == file).collect(),
+    };
+    match matching.as_slice() {
+        [entry] => Ok((*entry).clone()),
+        [] => bail!("`{file}` not found in bucket"),
+        _ => bail!("`{file}` matches several entries, use an id instead"),
+    }
+}
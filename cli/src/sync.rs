@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use bucket_storage_client::{Client, FileEntry};
+use futures::{stream, StreamExt, TryStreamExt};
+use indicatif::MultiProgress;
+use sha1::{Digest, Sha1};
+use crate::transfer;
+
+#[derive(Default)]
+pub struct SyncReport {
+    pub uploaded: usize,
+    pub unchanged: usize,
+    pub deleted: usize,
+}
+
+struct LocalFile {
+    path: PathBuf,
+    name: String,
+    checksum: String,
+}
+
+/// Same digest the server stores in `files.checksum`
+fn checksum(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha1::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Regular files below `dir` named by their path relative to it, with `/` between segments like entry paths.
+/// Symbolic links and names that are not UTF-8 are skipped.
+async fn local_files(dir: &Path) -> anyhow::Result<Vec<LocalFile>> {
+    let mut files = Vec::new();
+    let mut pending = vec![(dir.to_path_buf(), String::new())];
+    while let Some((dir, prefix)) = pending.pop() {
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let Some(name) = entry.file_name().to_str().map(|name| format!("{prefix}{name}")) else {
+                continue;
+            };
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                pending.push((entry.path(), format!("{name}/")));
+                continue;
+            }
+            if !file_type.is_file() {
+                continue;
+            }
+            let path = entry.path();
+            let checksum = tokio::task::spawn_blocking({
+                let path = path.clone();
+                move || checksum(&path)
+            }).await??;
+            files.push(LocalFile { path, name, checksum });
+        }
+    }
+    Ok(files)
+}
+
+/// Mirrors the files below `dir` into the bucket, each one at its path relative to `dir`.
+/// Files whose path and checksum already match a bucket entry are skipped, changed files replace the entries at their path
+/// and, with `delete`, entries at paths missing locally are removed.
+pub async fn sync(client: &Client, dir: &Path, delete: bool, jobs: usize, progress: &MultiProgress) -> anyhow::Result<SyncReport> {
+    let mut report = SyncReport::default();
+    let mut remote: HashMap<String, Vec<FileEntry>> = HashMap::new();
+    for entry in client.list().await? {
+        remote.entry(entry.file_name()).or_default().push(entry);
+    }
+
+    let mut uploads = Vec::new();
+    for file in local_files(dir).await? {
+        let existing = remote.remove(&file.name).unwrap_or_default();
+        if existing.iter().any(|entry| entry.checksum == file.checksum) {
+            report.unchanged += 1;
+            continue;
+        }
+        uploads.push((file, existing));
+    }
+
+    report.uploaded = uploads.len();
+    stream::iter(uploads)
+        .map(|(file, replaced)| async move {
+            let bar = transfer::bar(progress, &file.name);
+            let entry_id = transfer::upload(client, &file.path, &file.name, bar).await?;
+            // Versioned buckets keep the upload as the latest version of the entry at the same path
+            for entry in replaced.into_iter().filter(|entry| entry.id != entry_id) {
+                client.delete(entry.id).await?;
+            }
+            anyhow::Ok(())
+        })
+        .buffer_unordered(jobs)
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    if delete {
+        for entry in remote.into_values().flatten() {
+            client.delete(entry.id).await?;
+            report.deleted += 1;
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,50 @@
+use std::path::Path;
+use bucket_storage_client::Client;
+use futures::{StreamExt, TryStreamExt};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use reqwest::Body;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+pub fn bar(progress: &MultiProgress, name: &str) -> ProgressBar {
+    let style = ProgressStyle::with_template("{msg:30!} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec}")
+        .expect("Invalid progress template")
+        .progress_chars("=> ");
+    progress.add(ProgressBar::new(0).with_style(style).with_message(name.to_string()))
+}
+
+pub async fn upload(client: &Client, path: &Path, name: &str, bar: ProgressBar) -> anyhow::Result<Uuid> {
+    let file = File::open(path).await?;
+    let length = file.metadata().await?.len();
+    bar.set_length(length);
+
+    let progress = bar.clone();
+    let stream = ReaderStream::new(file).inspect_ok(move |chunk| progress.inc(chunk.len() as u64));
+    let file_id = client.upload(name, Body::wrap_stream(stream), length).await?;
+    bar.finish();
+    Ok(file_id)
+}
+
+pub async fn download(client: &Client, file_id: Uuid, target: &Path, bar: ProgressBar) -> anyhow::Result<()> {
+    let res = client.download(file_id).await?;
+    if let Some(length) = res.content_length() {
+        bar.set_length(length);
+    }
+
+    // Entry names are paths, nested ones land in the matching directories below the output
+    if let Some(parent) = target.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut file = File::create(target).await?;
+    let mut stream = Box::pin(res.stream());
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        bar.inc(chunk.len() as u64);
+    }
+    file.flush().await?;
+    bar.finish();
+    Ok(())
+}
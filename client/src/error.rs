@@ -0,0 +1,45 @@
+use reqwest::StatusCode;
+use thiserror::Error;
+use crate::models::ErrorResponse;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+    #[error("Not found: {0}")]
+    NotFound(String),
+    #[error("Server error: {0}")]
+    Server(String),
+    #[error("{status}: {message}")]
+    Api {
+        status: StatusCode,
+        message: String,
+    },
+}
+
+impl Error {
+    /// Maps the status and the `errorInfo` of a failed response
+    pub fn from_response(status: StatusCode, body: ErrorResponse) -> Self {
+        let message = body.error_info;
+        match status {
+            StatusCode::BAD_REQUEST => Self::BadRequest(message),
+            StatusCode::NOT_FOUND => Self::NotFound(message),
+            status if status.is_server_error() => Self::Server(message),
+            status => Self::Api { status, message },
+        }
+    }
+
+    /// Connection failures, timeouts and 5xx responses are worth another attempt
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Http(e) => e.is_connect() || e.is_timeout(),
+            Error::Server(_) => true,
+            Error::Api { status, .. } => *status == StatusCode::TOO_MANY_REQUESTS,
+            _ => false,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,479 @@
+//! Typed client for the bucket storage HTTP API
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::time::Duration;
+use base64::Engine;
+use bytes::Bytes;
+use futures_util::{Stream, TryStreamExt};
+use reqwest::multipart::{Form, Part};
+use reqwest::{Body, RequestBuilder, Response};
+use reqwest::header::HeaderMap;
+use uuid::Uuid;
+
+mod error;
+pub mod models;
+
+pub use error::{Error, Result};
+pub use models::{ArchiveFormat, ArchiveRequest, BatchDelete, BatchFetch, BatchMetadata, BatchResult, DeliveryQuery, DeliveryStatus, EntryDestination, ErrorResponse, FileEntry, FileVersion, FolderListing, FolderMove, ImageFit, ImageFormat, ImageQuery, IssuedKey, MediaInfo, MediaKind, SearchHit, SearchQuery, SearchResults, TrashEntry, UploadKey, Webhook, WebhookDelivery, WebhookEvent, WebhookPayload, WebhookRequest};
+
+/// Exponential backoff applied to transient failures of reads, see [`Idempotent`]
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self { max_retries: 0, ..Self::default() }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff.saturating_mul(2u32.saturating_pow(attempt)).min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Whether sending a request again has no effect beyond the first attempt.
+/// Only reads are retried, anything changing the bucket may have been applied by an attempt that timed out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Idempotent {
+    Yes,
+    No,
+}
+
+/// Every route of the server is nested under this prefix
+const API_PREFIX: &str = "/api";
+/// Prefix of the headers carrying metadata of a file
+const META_PREFIX: &str = "x-meta-";
+/// Header carrying the media properties of a file as JSON
+const MEDIA_HEADER: &str = "x-media";
+/// Credentials of the second bucket of copies and moves between buckets
+const DESTINATION_AUTHORIZATION: &str = "X-Destination-Authorization";
+
+#[derive(Clone)]
+struct Credentials {
+    key_id: Uuid,
+    key: String,
+}
+
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    url: String,
+    credentials: Option<Credentials>,
+    retry: RetryPolicy,
+}
+
+impl Client {
+    /// Client without credentials, only [`Client::issue_key`] and public uploads work until
+    /// [`Client::with_credentials`] is applied
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            url: url.into().trim_end_matches('/').to_string(),
+            credentials: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_credentials(mut self, key_id: Uuid, key: impl Into<String>) -> Self {
+        self.credentials = Some(Credentials { key_id, key: key.into() });
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    fn api(&self, path: &str) -> String {
+        format!("{}{API_PREFIX}{path}", self.url)
+    }
+
+    fn authorized(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.credentials {
+            Some(credentials) => request.basic_auth(credentials.key_id, Some(&credentials.key)),
+            None => request,
+        }
+    }
+
+    async fn send_once(request: RequestBuilder) -> Result<Response> {
+        let res = request.send().await?;
+        let status = res.status();
+        if status.is_success() {
+            return Ok(res);
+        }
+        let body = res.json::<ErrorResponse>().await.unwrap_or_else(|_| ErrorResponse {
+            error_info: status.canonical_reason().unwrap_or("Unknown error").to_string(),
+        });
+        Err(Error::from_response(status, body))
+    }
+
+    /// Retries idempotent requests on transient failures, others and requests with streaming bodies,
+    /// which cannot be cloned, are sent exactly once
+    async fn send(&self, request: RequestBuilder, idempotent: Idempotent) -> Result<Response> {
+        let request = self.authorized(request);
+        let mut attempt = 0;
+        loop {
+            let retry = request.try_clone().filter(|_| idempotent == Idempotent::Yes && attempt < self.retry.max_retries);
+            let Some(retry) = retry else {
+                return Self::send_once(request).await;
+            };
+            match Self::send_once(retry).await {
+                Err(e) if e.is_transient() => {
+                    tokio::time::sleep(self.retry.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// `GET /key`, creates a new bucket and returns its first key
+    pub async fn issue_key(&self) -> Result<IssuedKey> {
+        let res = self.send(self.http.get(self.api("/key")), Idempotent::No).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `GET /key/verify`
+    pub async fn verify_key(&self) -> Result<()> {
+        self.send(self.http.get(self.api("/key/verify")), Idempotent::Yes).await?;
+        Ok(())
+    }
+
+    /// `GET /files`
+    pub async fn list(&self) -> Result<Vec<FileEntry>> {
+        let res = self.send(self.http.get(self.api("/files")), Idempotent::Yes).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `GET /files?tag=:key::value`, entries whose metadata holds the pair
+    pub async fn list_tagged(&self, key: &str, value: &str) -> Result<Vec<FileEntry>> {
+        let res = self.send(self.http.get(self.api("/files")).query(&[("tag", format!("{key}:{value}"))]), Idempotent::Yes).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `GET /files?media=:filter`, entries whose media properties meet every condition like `kind=image,width>2000`
+    pub async fn list_media(&self, filter: &str) -> Result<Vec<FileEntry>> {
+        let res = self.send(self.http.get(self.api("/files")).query(&[("media", filter)]), Idempotent::Yes).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `GET /search`
+    pub async fn search(&self, query: &SearchQuery) -> Result<SearchResults> {
+        let res = self.send(self.http.get(self.api("/search")).query(query), Idempotent::Yes).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `GET /files?prefix=:prefix`, every entry below the folder
+    pub async fn list_prefix(&self, prefix: &str) -> Result<Vec<FileEntry>> {
+        let res = self.send(self.http.get(self.api("/files")).query(&[("prefix", prefix)]), Idempotent::Yes).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `GET /folders?prefix=:prefix`, entries and folders directly below `prefix`
+    pub async fn folder(&self, prefix: &str) -> Result<FolderListing> {
+        let res = self.send(self.http.get(self.api("/folders")).query(&[("prefix", prefix)]), Idempotent::Yes).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `POST /folders/move`, returns the number of entries moved
+    pub async fn move_folder(&self, from: impl Into<String>, to: impl Into<String>) -> Result<usize> {
+        let body = FolderMove { from: from.into(), to: to.into() };
+        let res = self.send(self.http.post(self.api("/folders/move")).json(&body), Idempotent::No).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `DELETE /folders?prefix=:prefix`, returns the number of entries deleted
+    pub async fn delete_folder(&self, prefix: &str) -> Result<usize> {
+        let res = self.send(self.http.delete(self.api("/folders")).query(&[("prefix", prefix)]), Idempotent::No).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `GET /upload/key`, issues an id that allows uploads without credentials
+    pub async fn upload_key(&self) -> Result<UploadKey> {
+        let res = self.send(self.http.get(self.api("/upload/key")), Idempotent::No).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `POST /upload` of a single file, `length` is the exact size of `body`
+    pub async fn upload(&self, file_name: impl Into<String>, body: impl Into<Body>, length: u64) -> Result<Uuid> {
+        let request = self.http.post(self.api("/upload"));
+        self.upload_form(request, file_name.into(), body.into(), length).await
+    }
+
+    /// `POST /upload?expires_in=:secs`, the file is deleted once `expires_in` passed
+    pub async fn upload_expiring(&self, file_name: impl Into<String>, body: impl Into<Body>, length: u64, expires_in: Duration) -> Result<Uuid> {
+        let request = self.http.post(self.api("/upload")).query(&[("expires_in", expires_in.as_secs())]);
+        self.upload_form(request, file_name.into(), body.into(), length).await
+    }
+
+    /// `POST /upload` with `metadata` sent as `X-Meta-<key>` headers
+    pub async fn upload_with_metadata(&self, file_name: impl Into<String>, body: impl Into<Body>, length: u64, metadata: &BTreeMap<String, String>) -> Result<Uuid> {
+        let mut request = self.http.post(self.api("/upload"));
+        for (key, value) in metadata {
+            request = request.header(format!("{META_PREFIX}{key}"), value);
+        }
+        self.upload_form(request, file_name.into(), body.into(), length).await
+    }
+
+    /// `POST /upload?extract=true` of a ZIP, tar or tar.gz file, returns the ids of the extracted entries
+    pub async fn upload_archive(&self, file_name: impl Into<String>, body: impl Into<Body>, length: u64) -> Result<Vec<Uuid>> {
+        let part = Part::stream_with_length(body.into(), length).file_name(file_name.into());
+        let request = self.http.post(self.api("/upload")).query(&[("extract", true)]);
+        let res = self.send(request.multipart(Form::new().part("file", part)), Idempotent::No).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `POST /upload/:upload_id`
+    pub async fn upload_with_key(&self, upload_id: Uuid, file_name: impl Into<String>, body: impl Into<Body>, length: u64) -> Result<Uuid> {
+        let request = self.http.post(self.api(&format!("/upload/{upload_id}")));
+        self.upload_form(request, file_name.into(), body.into(), length).await
+    }
+
+    async fn upload_form(&self, request: RequestBuilder, file_name: String, body: Body, length: u64) -> Result<Uuid> {
+        let part = Part::stream_with_length(body, length).file_name(file_name);
+        let res = self.send(request.multipart(Form::new().part("file", part)), Idempotent::No).await?;
+        let ids: Vec<Uuid> = res.json().await?;
+        ids.into_iter().next().ok_or(Error::BadRequest("Server did not accept the file".to_string()))
+    }
+
+    /// `GET /download/:file_id`
+    pub async fn download(&self, file_id: Uuid) -> Result<Download> {
+        let res = self.send(self.http.get(self.api(&format!("/download/{file_id}"))), Idempotent::Yes).await?;
+        Ok(Download { response: res })
+    }
+
+    /// `POST /archive`, the entries packed into one archive streamed as it is built
+    pub async fn download_archive(&self, request: &ArchiveRequest) -> Result<Download> {
+        let res = self.send(self.http.post(self.api("/archive")).json(request), Idempotent::Yes).await?;
+        Ok(Download { response: res })
+    }
+
+    /// `GET /images/:file_id`, the image resized or converted as `query` asks
+    pub async fn image(&self, file_id: Uuid, query: &ImageQuery) -> Result<Download> {
+        let res = self.send(self.http.get(self.api(&format!("/images/{file_id}"))).query(query), Idempotent::Yes).await?;
+        Ok(Download { response: res })
+    }
+
+    /// `HEAD /download/:file_id`, metadata of the file without its contents
+    pub async fn metadata(&self, file_id: Uuid) -> Result<BTreeMap<String, String>> {
+        let res = self.send(self.http.head(self.api(&format!("/download/{file_id}"))), Idempotent::Yes).await?;
+        Ok(metadata_from_headers(res.headers()))
+    }
+
+    /// `HEAD /download/:file_id`, properties read from the contents at upload
+    pub async fn media(&self, file_id: Uuid) -> Result<Option<MediaInfo>> {
+        let res = self.send(self.http.head(self.api(&format!("/download/{file_id}"))), Idempotent::Yes).await?;
+        Ok(media_from_headers(res.headers()))
+    }
+
+    /// `PATCH /files/:file_id/metadata`, `None` removes the key, returns the metadata after the change
+    pub async fn update_metadata(&self, file_id: Uuid, changes: &BTreeMap<String, Option<String>>) -> Result<BTreeMap<String, String>> {
+        let res = self.send(self.http.patch(self.api(&format!("/files/{file_id}/metadata"))).json(changes), Idempotent::No).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `GET /download/:file_id` of the bytes in `range` only
+    pub async fn download_range(&self, file_id: Uuid, range: Range<u64>) -> Result<Download> {
+        let request = self.http
+            .get(self.api(&format!("/download/{file_id}")))
+            .header(reqwest::header::RANGE, format!("bytes={}-{}", range.start, range.end.saturating_sub(1)));
+        let res = self.send(request, Idempotent::Yes).await?;
+        Ok(Download { response: res })
+    }
+
+    /// `GET /download/:file_id?version=:version_id` of an older version
+    pub async fn download_version(&self, file_id: Uuid, version_id: Uuid) -> Result<Download> {
+        let request = self.http.get(self.api(&format!("/download/{file_id}"))).query(&[("version", version_id)]);
+        let res = self.send(request, Idempotent::Yes).await?;
+        Ok(Download { response: res })
+    }
+
+    /// `GET /files/:file_id/versions`
+    pub async fn versions(&self, file_id: Uuid) -> Result<Vec<FileVersion>> {
+        let res = self.send(self.http.get(self.api(&format!("/files/{file_id}/versions"))), Idempotent::Yes).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `POST /files/:file_id/versions/:version_id/restore`, returns the id of the version created from it
+    pub async fn restore_version(&self, file_id: Uuid, version_id: Uuid) -> Result<Uuid> {
+        let res = self.send(self.http.post(self.api(&format!("/files/{file_id}/versions/{version_id}/restore"))), Idempotent::No).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `DELETE /files/:file_id/versions/:version_id`, removes the version permanently
+    pub async fn delete_version(&self, file_id: Uuid, version_id: Uuid) -> Result<()> {
+        self.send(self.http.delete(self.api(&format!("/files/{file_id}/versions/{version_id}"))), Idempotent::No).await?;
+        Ok(())
+    }
+
+    /// `POST /files/:file_id/move` within the bucket
+    pub async fn rename(&self, file_id: Uuid, path: impl Into<String>) -> Result<()> {
+        self.transfer("move", file_id, None, Some(path.into())).await?;
+        Ok(())
+    }
+
+    /// `POST /files/:file_id/copy` within the bucket, returns the id of the copy
+    pub async fn copy(&self, file_id: Uuid, path: impl Into<String>) -> Result<Uuid> {
+        self.transfer("copy", file_id, None, Some(path.into())).await
+    }
+
+    /// `POST /files/:file_id/copy` into the bucket of `destination`, keeping the path when `path` is `None`
+    pub async fn copy_to(&self, file_id: Uuid, destination: &Client, path: Option<String>) -> Result<Uuid> {
+        self.transfer("copy", file_id, Some(destination), path).await
+    }
+
+    /// `POST /files/:file_id/move` into the bucket of `destination`, returns the id of the entry there
+    pub async fn move_to(&self, file_id: Uuid, destination: &Client, path: Option<String>) -> Result<Uuid> {
+        self.transfer("move", file_id, Some(destination), path).await
+    }
+
+    async fn transfer(&self, action: &str, file_id: Uuid, destination: Option<&Client>, path: Option<String>) -> Result<Uuid> {
+        let mut request = self.http.post(self.api(&format!("/files/{file_id}/{action}"))).json(&EntryDestination { path });
+        if let Some(credentials) = destination.and_then(|client| client.credentials.as_ref()) {
+            let encoded = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", credentials.key_id, credentials.key));
+            request = request.header(DESTINATION_AUTHORIZATION, format!("Basic {encoded}"));
+        }
+        let res = self.send(request, Idempotent::No).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `GET /trash`
+    pub async fn trash(&self) -> Result<Vec<TrashEntry>> {
+        let res = self.send(self.http.get(self.api("/trash")), Idempotent::Yes).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `POST /trash/:file_id/restore`
+    pub async fn restore(&self, file_id: Uuid) -> Result<()> {
+        self.send(self.http.post(self.api(&format!("/trash/{file_id}/restore"))), Idempotent::No).await?;
+        Ok(())
+    }
+
+    /// `DELETE /trash/:file_id`, removes the deleted file permanently
+    pub async fn purge(&self, file_id: Uuid) -> Result<()> {
+        self.send(self.http.delete(self.api(&format!("/trash/{file_id}"))), Idempotent::No).await?;
+        Ok(())
+    }
+
+    /// `GET /delete/:file_id`
+    pub async fn delete(&self, file_id: Uuid) -> Result<()> {
+        self.send(self.http.get(self.api(&format!("/delete/{file_id}"))), Idempotent::No).await?;
+        Ok(())
+    }
+
+    /// `POST /batch/delete`, deletes every entry like [`Client::delete`] and reports each one
+    pub async fn delete_many(&self, ids: &[Uuid]) -> Result<Vec<BatchResult>> {
+        self.batch_delete(&BatchDelete { ids: Some(ids.to_vec()), prefix: None }).await
+    }
+
+    /// `POST /batch/delete` for every entry below `prefix`
+    pub async fn delete_prefix(&self, prefix: &str) -> Result<Vec<BatchResult>> {
+        self.batch_delete(&BatchDelete { ids: None, prefix: Some(prefix.to_string()) }).await
+    }
+
+    async fn batch_delete(&self, body: &BatchDelete) -> Result<Vec<BatchResult>> {
+        let res = self.send(self.http.post(self.api("/batch/delete")).json(body), Idempotent::No).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `POST /batch/metadata/fetch`, the metadata of every entry found and a 404 result for the others
+    pub async fn metadata_many(&self, ids: &[Uuid]) -> Result<Vec<BatchResult>> {
+        let body = BatchFetch { ids: ids.to_vec() };
+        let res = self.send(self.http.post(self.api("/batch/metadata/fetch")).json(&body), Idempotent::Yes).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `POST /batch/metadata`, applies the same changes to every entry and reports each one
+    pub async fn update_metadata_many(&self, ids: &[Uuid], changes: &BTreeMap<String, Option<String>>) -> Result<Vec<BatchResult>> {
+        let body = BatchMetadata { ids: ids.to_vec(), changes: changes.clone() };
+        let res = self.send(self.http.post(self.api("/batch/metadata")).json(&body), Idempotent::No).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `POST /webhooks`, the returned webhook carries the secret signing its deliveries
+    pub async fn create_webhook(&self, url: impl Into<String>, events: &[WebhookEvent]) -> Result<Webhook> {
+        let body = WebhookRequest { url: url.into(), events: events.to_vec() };
+        let res = self.send(self.http.post(self.api("/webhooks")).json(&body), Idempotent::No).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `GET /webhooks`
+    pub async fn webhooks(&self) -> Result<Vec<Webhook>> {
+        let res = self.send(self.http.get(self.api("/webhooks")), Idempotent::Yes).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `DELETE /webhooks/:webhook_id`
+    pub async fn delete_webhook(&self, webhook_id: Uuid) -> Result<()> {
+        self.send(self.http.delete(self.api(&format!("/webhooks/{webhook_id}"))), Idempotent::No).await?;
+        Ok(())
+    }
+
+    /// `GET /webhooks/:webhook_id/deliveries`, newest first
+    pub async fn webhook_deliveries(&self, webhook_id: Uuid, query: &DeliveryQuery) -> Result<Vec<WebhookDelivery>> {
+        let res = self.send(self.http.get(self.api(&format!("/webhooks/{webhook_id}/deliveries"))).query(query), Idempotent::Yes).await?;
+        Ok(res.json().await?)
+    }
+
+    /// `POST /webhooks/:webhook_id/deliveries/:delivery_id/retry`, sends a dead or failing delivery again right away, delivered ones are refused
+    pub async fn retry_delivery(&self, webhook_id: Uuid, delivery_id: Uuid) -> Result<WebhookDelivery> {
+        let res = self.send(self.http.post(self.api(&format!("/webhooks/{webhook_id}/deliveries/{delivery_id}/retry"))), Idempotent::No).await?;
+        Ok(res.json().await?)
+    }
+}
+
+fn metadata_from_headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+    headers.iter()
+        .filter_map(|(name, value)| Some((name.as_str().strip_prefix(META_PREFIX)?.to_string(), value.to_str().ok()?.to_string())))
+        .collect()
+}
+
+fn media_from_headers(headers: &HeaderMap) -> Option<MediaInfo> {
+    serde_json::from_slice(headers.get(MEDIA_HEADER)?.as_bytes()).ok()
+}
+
+/// Body of a successful download, not yet read
+pub struct Download {
+    response: Response,
+}
+
+impl Download {
+    pub fn content_length(&self) -> Option<u64> {
+        self.response.content_length()
+    }
+
+    /// Pairs sent as `X-Meta-<key>` headers
+    pub fn metadata(&self) -> BTreeMap<String, String> {
+        metadata_from_headers(self.response.headers())
+    }
+
+    /// Properties sent in the `X-Media` header
+    pub fn media(&self) -> Option<MediaInfo> {
+        media_from_headers(self.response.headers())
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.response.headers().get(reqwest::header::CONTENT_TYPE)?.to_str().ok()
+    }
+
+    pub fn stream(self) -> impl Stream<Item = Result<Bytes>> {
+        self.response.bytes_stream().map_err(Error::from)
+    }
+
+    pub async fn bytes(self) -> Result<Bytes> {
+        Ok(self.response.bytes().await?)
+    }
+}
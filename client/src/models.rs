@@ -0,0 +1,501 @@
+//! Request and response bodies shared by the server and the client
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// Body of every non-successful response
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct ErrorResponse {
+    pub error_info: String,
+}
+
+/// Response of `GET /key`, the only time the plain key is returned
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct IssuedKey {
+    pub id: Uuid,
+    pub key: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct UploadKey {
+    pub upload_id: Uuid,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct FileEntry {
+    pub id: Uuid,
+    pub name: String,
+    pub extension: Option<String>,
+    pub size: i64,
+    pub checksum: String,
+    /// User-defined pairs of the latest version, keys are lowercase
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    /// Properties read from the contents at upload, missing for unrecognized formats
+    #[serde(default)]
+    pub media: Option<MediaInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="lowercase")]
+pub enum MediaKind {
+    Image,
+    Document,
+    Text,
+    Audio,
+    Video,
+}
+
+/// Properties of a file read from its contents, only the ones its kind has are set
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct MediaInfo {
+    pub kind: MediaKind,
+    /// Format of the contents like `jpeg`, `pdf` or `mp3`
+    pub format: String,
+    /// Width of images in pixels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    /// Height of images in pixels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// Pages of documents
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pages: Option<u32>,
+    /// Encoding of texts like `utf-8` or `windows-1252`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    /// Duration of audio and video in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    /// EXIF fields of images by tag name, empty in buckets stripping them
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub exif: BTreeMap<String, String>,
+}
+
+/// Version of an entry in a bucket with versioning enabled, newest first in listings
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct FileVersion {
+    pub id: Uuid,
+    /// Version returned by downloads without a version id
+    pub latest: bool,
+    /// Delete marker hiding the older versions, without contents
+    pub deleted: bool,
+    pub size: Option<i64>,
+    pub checksum: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = DateTime))]
+    pub created_at: OffsetDateTime,
+}
+
+/// Deleted entry that can be restored until `expires_at`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct TrashEntry {
+    pub id: Uuid,
+    pub name: String,
+    pub extension: Option<String>,
+    pub size: i64,
+    #[serde(with = "time::serde::rfc3339")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = DateTime))]
+    pub deleted_at: OffsetDateTime,
+    /// When the purger removes the entry permanently
+    #[serde(with = "time::serde::rfc3339")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = DateTime))]
+    pub expires_at: OffsetDateTime,
+}
+
+/// Response of `GET /folders`, one level of the bucket below `prefix`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct FolderListing {
+    pub prefix: String,
+    /// Folders directly below the prefix, each ending with the delimiter
+    pub common_prefixes: Vec<String>,
+    /// Entries directly below the prefix
+    pub entries: Vec<FileEntry>,
+}
+
+/// Body of `POST /folders/move`, renaming `from` to `to` moves everything below it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct FolderMove {
+    pub from: String,
+    pub to: String,
+}
+
+/// Body of `POST /files/:file_id/copy` and `POST /files/:file_id/move`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct EntryDestination {
+    /// New path with the same extension, the path of the source when missing
+    pub path: Option<String>,
+}
+
+/// Query of `GET /search`, every set condition must hold
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+pub struct SearchQuery {
+    /// Case-insensitive substring of the path, results are ranked by similarity to it
+    pub q: Option<String>,
+    pub extension: Option<String>,
+    /// Smallest size in bytes
+    pub min_size: Option<i64>,
+    /// Largest size in bytes
+    pub max_size: Option<i64>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>, format = DateTime))]
+    pub uploaded_after: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    #[cfg_attr(feature = "openapi", param(value_type = Option<String>, format = DateTime))]
+    pub uploaded_before: Option<OffsetDateTime>,
+    /// `key:value` pair the metadata must hold
+    pub tag: Option<String>,
+    /// Results per page, 50 by default and at most 1000
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Response of `GET /search`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct SearchResults {
+    /// Matches over all pages, 0 on pages past the last one
+    pub total: i64,
+    pub hits: Vec<SearchHit>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct SearchHit {
+    pub entry: FileEntry,
+    /// Similarity of the path to the searched text between 0 and 1, 0 without one
+    pub score: f32,
+    #[serde(with = "time::serde::rfc3339")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = DateTime))]
+    pub uploaded_at: OffsetDateTime,
+}
+
+/// Body of `POST /batch/delete`, either the ids or the prefix of the entries to delete
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct BatchDelete {
+    pub ids: Option<Vec<Uuid>>,
+    /// Folder whose entries are deleted, like `DELETE /folders`
+    pub prefix: Option<String>,
+}
+
+/// Body of `POST /batch/metadata/fetch`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct BatchFetch {
+    pub ids: Vec<Uuid>,
+}
+
+/// Body of `POST /batch/metadata`, the same changes applied to every entry
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct BatchMetadata {
+    pub ids: Vec<Uuid>,
+    /// Values to set, `null` removes the key
+    pub changes: BTreeMap<String, Option<String>>,
+}
+
+/// Outcome of one entry of a batch
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct BatchResult {
+    pub id: Uuid,
+    /// Status code the entry would have gotten on its own
+    pub status: u16,
+    pub error: Option<String>,
+    /// Metadata of the entry, only returned by `POST /batch/metadata/fetch`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<BTreeMap<String, String>>,
+}
+
+impl BatchResult {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+}
+
+/// Body of `POST /archive`, the whole bucket when neither ids nor a prefix are given
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct ArchiveRequest {
+    pub ids: Option<Vec<Uuid>>,
+    /// Folder whose entries are archived
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub format: ArchiveFormat,
+}
+
+/// How an image is fitted into the requested width and height
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="lowercase")]
+pub enum ImageFit {
+    /// Scaled to fit inside, keeping the aspect ratio
+    #[default]
+    Contain,
+    /// Scaled to fill both and cropped around the center
+    Cover,
+    /// Stretched to both exactly
+    Fill,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="lowercase")]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl ImageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Webp => "webp",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Webp => "image/webp",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+            "webp" => Some(ImageFormat::Webp),
+            _ => None,
+        }
+    }
+}
+
+/// Query of `GET /images/:file_id`, the stored image as it is when nothing is set
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+pub struct ImageQuery {
+    /// Width in pixels, derived from the aspect ratio when missing, rounded up to the next size the server generates
+    pub width: Option<u32>,
+    /// Height in pixels, derived from the aspect ratio when missing, rounded up like the width
+    pub height: Option<u32>,
+    /// `contain` by default, `cover` and `fill` need both dimensions
+    pub fit: Option<ImageFit>,
+    /// Format of the stored image by default
+    pub format: Option<ImageFormat>,
+    /// Version to read instead of the latest one
+    pub version: Option<Uuid>,
+}
+
+impl FileEntry {
+    /// Name with the extension joined back, as it was uploaded
+    pub fn file_name(&self) -> String {
+        match &self.extension {
+            Some(ext) => format!("{}.{ext}", self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum WebhookEvent {
+    /// A file was uploaded, copied, restored from the trash or restored as a new version
+    #[serde(rename = "object.created")]
+    ObjectCreated,
+    /// An entry was deleted, moved to the trash or hidden by a delete marker
+    #[serde(rename = "object.deleted")]
+    ObjectDeleted,
+    /// An entry was deleted by its expiry or a lifecycle rule
+    #[serde(rename = "object.expired")]
+    ObjectExpired,
+    /// An upload brought the bucket above 90% of its quota
+    #[serde(rename = "quota.warning")]
+    QuotaWarning,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::ObjectCreated => "object.created",
+            WebhookEvent::ObjectDeleted => "object.deleted",
+            WebhookEvent::ObjectExpired => "object.expired",
+            WebhookEvent::QuotaWarning => "quota.warning",
+        }
+    }
+}
+
+impl FromStr for WebhookEvent {
+    type Err = String;
+
+    fn from_str(event: &str) -> Result<Self, Self::Err> {
+        [WebhookEvent::ObjectCreated, WebhookEvent::ObjectDeleted, WebhookEvent::ObjectExpired, WebhookEvent::QuotaWarning]
+            .into_iter()
+            .find(|known| known.as_str() == event)
+            .ok_or_else(|| format!("Unknown webhook event {event}"))
+    }
+}
+
+/// Body of `POST /webhooks`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct WebhookRequest {
+    /// `http` or `https` endpoint receiving the deliveries as `POST` requests
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+}
+
+/// Endpoint notified of events of the bucket
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct Webhook {
+    pub id: Uuid,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    /// Key of the HMAC-SHA256 in `X-Webhook-Signature`, only returned when the webhook is created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = DateTime))]
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="lowercase")]
+pub enum DeliveryStatus {
+    /// Waiting for its next attempt
+    Pending,
+    /// Acknowledged with a 2xx response
+    Delivered,
+    /// Failed every attempt, sent again only when retried
+    Dead,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Dead => "dead",
+        }
+    }
+}
+
+impl FromStr for DeliveryStatus {
+    type Err = String;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        [DeliveryStatus::Pending, DeliveryStatus::Delivered, DeliveryStatus::Dead]
+            .into_iter()
+            .find(|known| known.as_str() == status)
+            .ok_or_else(|| format!("Unknown delivery status {status}"))
+    }
+}
+
+/// Query of `GET /webhooks/:webhook_id/deliveries`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+pub struct DeliveryQuery {
+    pub status: Option<DeliveryStatus>,
+    /// Deliveries per page, 50 by default and at most 1000
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Event sent to a webhook and the outcome of sending it, newest first in listings
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub event: WebhookEvent,
+    pub status: DeliveryStatus,
+    pub attempts: i32,
+    /// Status of the response to the last attempt, missing when no response came
+    pub last_status_code: Option<u16>,
+    pub last_error: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = DateTime))]
+    pub next_attempt_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = DateTime))]
+    pub created_at: OffsetDateTime,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>, format = DateTime))]
+    pub delivered_at: Option<OffsetDateTime>,
+    pub payload: WebhookPayload,
+}
+
+/// JSON body of a delivery
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all="camelCase")]
+pub struct WebhookPayload {
+    /// Id of the delivery, the same for every attempt so receivers can drop duplicates
+    pub id: Uuid,
+    pub event: WebhookEvent,
+    pub bucket_id: Uuid,
+    #[serde(with = "time::serde::rfc3339")]
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = DateTime))]
+    pub occurred_at: OffsetDateTime,
+    /// Entry id, name, extension, size and checksum of object events, used and quota bytes of quota warnings
+    #[cfg_attr(feature = "openapi", schema(value_type = Object))]
+    pub data: serde_json::Value,
+}
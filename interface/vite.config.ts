@@ -9,7 +9,6 @@ export default defineConfig({
       "/api": {
         target: "http://127.0.0.1:3001",
         changeOrigin: true,
-        rewrite: (path) => path.replace(/^\/api/, ""),
       },
     },
   },
@@ -0,0 +1,3 @@
+ALTER TABLE files DROP COLUMN data_key_id;
+ALTER TABLE buckets DROP COLUMN data_key_id;
+DROP TABLE data_keys;
@@ -0,0 +1,8 @@
+CREATE TABLE data_keys (
+    id UUID DEFAULT gen_random_uuid(),
+    wrapped_key BYTEA NOT NULL,
+    PRIMARY KEY (id)
+);
+
+ALTER TABLE buckets ADD COLUMN data_key_id UUID REFERENCES data_keys(id);
+ALTER TABLE files ADD COLUMN data_key_id UUID REFERENCES data_keys(id);
@@ -0,0 +1,3 @@
+ALTER TABLE files DROP COLUMN stored_size;
+ALTER TABLE files DROP COLUMN codec;
+ALTER TABLE buckets DROP COLUMN compression;
@@ -0,0 +1,11 @@
+ALTER TABLE buckets ADD COLUMN compression TEXT NOT NULL DEFAULT 'auto' CHECK (compression IN ('auto', 'always', 'never'));
+
+ALTER TABLE files ADD COLUMN codec TEXT NOT NULL DEFAULT 'identity' CHECK (codec IN ('identity', 'zstd'));
+ALTER TABLE files ADD COLUMN stored_size BIGINT NOT NULL DEFAULT 0;
+
+-- Existing blobs are unencoded, encrypted ones carry a 19 byte header and a 16 byte tag per 64 KiB chunk
+UPDATE files
+SET stored_size = size + CASE
+    WHEN data_key_id IS NULL THEN 0
+    ELSE 19 + GREATEST((size + 65535) / 65536, 1) * 16
+END;
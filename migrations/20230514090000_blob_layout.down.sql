@@ -0,0 +1,3 @@
+-- Blobs already moved by `bucket-admin relayout` are not reachable through the flat layout anymore
+DROP INDEX files_blob_idx;
+ALTER TABLE files DROP COLUMN blob;
@@ -0,0 +1,3 @@
+-- NULL while the blob is still stored flat as `<id>.<extension>`
+ALTER TABLE files ADD COLUMN blob TEXT;
+CREATE INDEX files_blob_idx ON files (blob);
@@ -0,0 +1,4 @@
+DROP INDEX files_verified_at_idx;
+ALTER TABLE files DROP COLUMN scrub_error;
+ALTER TABLE files DROP COLUMN corrupted_at;
+ALTER TABLE files DROP COLUMN verified_at;
@@ -0,0 +1,5 @@
+ALTER TABLE files ADD COLUMN verified_at TIMESTAMPTZ;
+ALTER TABLE files ADD COLUMN corrupted_at TIMESTAMPTZ;
+-- Why the last verification could not read the file, for failures that do not tell about its contents
+ALTER TABLE files ADD COLUMN scrub_error TEXT;
+CREATE INDEX files_verified_at_idx ON files (verified_at NULLS FIRST);
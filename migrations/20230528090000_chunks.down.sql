@@ -0,0 +1,4 @@
+DROP TABLE file_chunks;
+DROP TABLE chunks;
+ALTER TABLE files DROP COLUMN chunked;
+ALTER TABLE buckets DROP COLUMN chunking;
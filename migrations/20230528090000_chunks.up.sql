@@ -0,0 +1,28 @@
+ALTER TABLE buckets ADD COLUMN chunking BOOLEAN NOT NULL DEFAULT false;
+-- Chunked files have no blob of their own, their contents are the chunks listed in `file_chunks`
+ALTER TABLE files ADD COLUMN chunked BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE chunks (
+    id UUID DEFAULT gen_random_uuid(),
+    -- Hex SHA-256 of the plaintext, chunks are shared between files sealed by the same data key
+    digest TEXT NOT NULL,
+    data_key_id UUID REFERENCES data_keys(id),
+    codec TEXT NOT NULL DEFAULT 'identity' CHECK (codec IN ('identity', 'zstd')),
+    size BIGINT NOT NULL,
+    stored_size BIGINT NOT NULL,
+    blob TEXT NOT NULL,
+    -- Number of `file_chunks` rows pointing at the chunk
+    refs BIGINT NOT NULL CHECK (refs >= 0),
+    PRIMARY KEY (id)
+);
+CREATE UNIQUE INDEX chunks_digest_idx ON chunks (digest, COALESCE(data_key_id, '00000000-0000-0000-0000-000000000000'));
+CREATE INDEX chunks_blob_idx ON chunks (blob);
+
+CREATE TABLE file_chunks (
+    file_id UUID NOT NULL REFERENCES files(id) ON DELETE CASCADE,
+    position INT NOT NULL,
+    chunk_id UUID NOT NULL REFERENCES chunks(id),
+    -- Offset of the chunk in the file
+    start BIGINT NOT NULL,
+    PRIMARY KEY (file_id, position)
+);
@@ -0,0 +1,18 @@
+-- Only the latest version of every entry survives, one per file and bucket
+DELETE FROM bucket_files WHERE NOT latest OR file_id IS NULL;
+DELETE FROM bucket_files a USING bucket_files b
+WHERE a.bucket_id = b.bucket_id AND a.file_id = b.file_id AND a.created_at > b.created_at;
+
+DROP INDEX bucket_files_file_id_idx;
+DROP INDEX bucket_files_name_idx;
+DROP INDEX bucket_files_entry_idx;
+DROP INDEX bucket_files_latest_idx;
+ALTER TABLE bucket_files ALTER COLUMN file_id SET NOT NULL;
+ALTER TABLE bucket_files DROP COLUMN latest;
+ALTER TABLE bucket_files DROP COLUMN created_at;
+ALTER TABLE bucket_files DROP COLUMN entry_id;
+ALTER TABLE bucket_files DROP CONSTRAINT bucket_files_pkey;
+ALTER TABLE bucket_files DROP COLUMN id;
+ALTER TABLE bucket_files ADD PRIMARY KEY (bucket_id, file_id);
+
+ALTER TABLE buckets DROP COLUMN versioning;
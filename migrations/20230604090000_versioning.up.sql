@@ -0,0 +1,19 @@
+ALTER TABLE buckets ADD COLUMN versioning BOOLEAN NOT NULL DEFAULT false;
+
+-- Every row is a version of an entry, versions of one entry share `entry_id`
+ALTER TABLE bucket_files DROP CONSTRAINT bucket_files_pkey;
+ALTER TABLE bucket_files ADD COLUMN id UUID DEFAULT gen_random_uuid();
+ALTER TABLE bucket_files ADD PRIMARY KEY (id);
+-- Entries stored before versioning keep the id of their file, which is what clients were given
+ALTER TABLE bucket_files ADD COLUMN entry_id UUID;
+UPDATE bucket_files SET entry_id = file_id;
+ALTER TABLE bucket_files ALTER COLUMN entry_id SET NOT NULL;
+ALTER TABLE bucket_files ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT clock_timestamp();
+ALTER TABLE bucket_files ADD COLUMN latest BOOLEAN NOT NULL DEFAULT true;
+-- NULL for delete markers, which hide the entry while keeping its older versions
+ALTER TABLE bucket_files ALTER COLUMN file_id DROP NOT NULL;
+
+CREATE UNIQUE INDEX bucket_files_latest_idx ON bucket_files (bucket_id, entry_id) WHERE latest;
+CREATE INDEX bucket_files_entry_idx ON bucket_files (bucket_id, entry_id, created_at);
+CREATE INDEX bucket_files_name_idx ON bucket_files (bucket_id, name);
+CREATE INDEX bucket_files_file_id_idx ON bucket_files (file_id);
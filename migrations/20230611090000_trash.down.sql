@@ -0,0 +1,3 @@
+DROP INDEX bucket_files_trashed_at_idx;
+ALTER TABLE bucket_files DROP COLUMN trashed_at;
+ALTER TABLE buckets DROP COLUMN trash_retention_secs;
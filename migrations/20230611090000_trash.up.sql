@@ -0,0 +1,5 @@
+-- Seconds deleted entries stay restorable in the trash, 0 deletes them right away so the trash is opt-in
+ALTER TABLE buckets ADD COLUMN trash_retention_secs BIGINT NOT NULL DEFAULT 0 CHECK (trash_retention_secs >= 0);
+-- Set on every version of an entry moved to the trash
+ALTER TABLE bucket_files ADD COLUMN trashed_at TIMESTAMPTZ;
+CREATE INDEX bucket_files_trashed_at_idx ON bucket_files (trashed_at) WHERE trashed_at IS NOT NULL;
@@ -0,0 +1,3 @@
+DROP INDEX bucket_files_expires_at_idx;
+ALTER TABLE bucket_files DROP COLUMN expires_at;
+DROP TABLE lifecycle_rules;
@@ -0,0 +1,15 @@
+-- Entries of the bucket matching every set condition are deleted once their latest version is older than `expire_after_days`
+CREATE TABLE lifecycle_rules (
+    id UUID DEFAULT gen_random_uuid(),
+    bucket_id UUID NOT NULL REFERENCES buckets(id) ON DELETE CASCADE,
+    prefix TEXT,
+    extension TEXT,
+    expire_after_days INT NOT NULL CHECK (expire_after_days > 0),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (id)
+);
+CREATE INDEX lifecycle_rules_bucket_id_idx ON lifecycle_rules (bucket_id);
+
+-- Expiry requested at upload, independent of the rules of the bucket
+ALTER TABLE bucket_files ADD COLUMN expires_at TIMESTAMPTZ;
+CREATE INDEX bucket_files_expires_at_idx ON bucket_files (expires_at) WHERE expires_at IS NOT NULL;
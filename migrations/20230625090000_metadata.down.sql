@@ -0,0 +1,2 @@
+DROP INDEX bucket_files_metadata_idx;
+ALTER TABLE bucket_files DROP COLUMN metadata;
@@ -0,0 +1,3 @@
+-- User-defined key/value pairs of the version, string values only
+ALTER TABLE bucket_files ADD COLUMN metadata JSONB NOT NULL DEFAULT '{}';
+CREATE INDEX bucket_files_metadata_idx ON bucket_files USING GIN (metadata jsonb_path_ops);
@@ -0,0 +1,2 @@
+DROP INDEX bucket_files_created_at_idx;
+DROP INDEX bucket_files_name_trgm_idx;
@@ -0,0 +1,4 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+-- Substring and similarity matches on entry paths
+CREATE INDEX bucket_files_name_trgm_idx ON bucket_files USING GIN (name gin_trgm_ops);
+CREATE INDEX bucket_files_created_at_idx ON bucket_files (bucket_id, created_at) WHERE latest;
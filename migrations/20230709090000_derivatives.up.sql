@@ -0,0 +1,14 @@
+-- Resized or converted versions of stored images, generated on first request and kept until their source file is deleted
+CREATE TABLE derivatives (
+    id UUID DEFAULT gen_random_uuid(),
+    file_id UUID NOT NULL REFERENCES files(id) ON DELETE CASCADE,
+    -- Canonical form of the requested transformation
+    variant TEXT NOT NULL,
+    format TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    blob TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    UNIQUE (file_id, variant)
+);
+CREATE INDEX derivatives_blob_idx ON derivatives (blob);
@@ -0,0 +1,3 @@
+ALTER TABLE buckets DROP COLUMN strip_exif;
+DROP INDEX files_media_idx;
+ALTER TABLE files DROP COLUMN media;
@@ -0,0 +1,5 @@
+-- Properties read from the contents at upload, NULL for unrecognized formats and files stored before
+ALTER TABLE files ADD COLUMN media JSONB;
+CREATE INDEX files_media_idx ON files USING GIN (media jsonb_path_ops);
+-- EXIF, XMP and textual metadata of images are removed before they are stored
+ALTER TABLE buckets ADD COLUMN strip_exif BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,7 @@
+DROP TRIGGER bucket_files_usage ON bucket_files;
+DROP FUNCTION count_bucket_usage;
+ALTER TABLE buckets DROP COLUMN used_bytes;
+ALTER TABLE buckets DROP COLUMN quota_warned;
+ALTER TABLE buckets DROP COLUMN quota_bytes;
+DROP TABLE webhook_deliveries;
+DROP TABLE webhooks;
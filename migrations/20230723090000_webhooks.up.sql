@@ -0,0 +1,62 @@
+CREATE TABLE webhooks (
+    id UUID DEFAULT gen_random_uuid(),
+    bucket_id UUID NOT NULL REFERENCES buckets(id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    -- Key of the HMAC signing the deliveries, kept as issued since every delivery needs it
+    secret TEXT NOT NULL,
+    events TEXT[] NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (id)
+);
+CREATE INDEX webhooks_bucket_idx ON webhooks (bucket_id);
+
+-- Outbox of events, rows are written in the transaction of the change they report
+CREATE TABLE webhook_deliveries (
+    id UUID DEFAULT gen_random_uuid(),
+    webhook_id UUID NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+    event TEXT NOT NULL,
+    -- Details of the event, sent with the id, event, bucket and time of the row
+    data JSONB NOT NULL,
+    -- Dead deliveries ran out of attempts and are only sent again when retried explicitly
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'delivered', 'dead')),
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_status_code INT,
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    delivered_at TIMESTAMPTZ,
+    PRIMARY KEY (id)
+);
+CREATE INDEX webhook_deliveries_due_idx ON webhook_deliveries (next_attempt_at) WHERE status = 'pending';
+CREATE INDEX webhook_deliveries_webhook_idx ON webhook_deliveries (webhook_id, created_at);
+
+-- Size the bucket is expected to stay within, uploads are not rejected past it
+ALTER TABLE buckets ADD COLUMN quota_bytes BIGINT CHECK (quota_bytes > 0);
+-- Whether the usage was above the warning threshold at the last upload, so crossing it warns once
+ALTER TABLE buckets ADD COLUMN quota_warned BOOLEAN NOT NULL DEFAULT false;
+-- Size of the files referenced by versions of the bucket, only counted while the bucket has a quota
+-- so writes to other buckets never lock their bucket row
+ALTER TABLE buckets ADD COLUMN used_bytes BIGINT NOT NULL DEFAULT 0;
+
+CREATE FUNCTION count_bucket_usage() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'UPDATE' AND OLD.bucket_id = NEW.bucket_id AND OLD.file_id IS NOT DISTINCT FROM NEW.file_id THEN
+        RETURN NULL;
+    END IF;
+    IF TG_OP IN ('UPDATE', 'DELETE') AND OLD.file_id IS NOT NULL THEN
+        UPDATE buckets
+        SET used_bytes = used_bytes - COALESCE((SELECT size FROM files WHERE id = OLD.file_id), 0)
+        WHERE id = OLD.bucket_id AND quota_bytes IS NOT NULL;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') AND NEW.file_id IS NOT NULL THEN
+        UPDATE buckets
+        SET used_bytes = used_bytes + COALESCE((SELECT size FROM files WHERE id = NEW.file_id), 0)
+        WHERE id = NEW.bucket_id AND quota_bytes IS NOT NULL;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER bucket_files_usage
+AFTER INSERT OR DELETE OR UPDATE OF bucket_id, file_id ON bucket_files
+FOR EACH ROW EXECUTE FUNCTION count_bucket_usage();
@@ -0,0 +1,340 @@
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::anyhow;
+use serde::Serialize;
+use sqlx::{PgPool, query};
+use tracing::{debug, warn};
+use uuid::Uuid;
+use crate::{chunks, images};
+use crate::errors::AppError;
+use crate::files::blob_shared;
+use crate::store::{checksum_stream, Store, StoreFile};
+use crate::store::replication::Repair;
+use crate::store::crypto::MasterKey;
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all="camelCase")]
+pub struct GcReport {
+    pub unreferenced_files: Vec<Uuid>,
+    pub orphaned_blobs: Vec<PathBuf>,
+    pub unused_data_keys: Vec<Uuid>,
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all="camelCase")]
+pub struct FsckReport {
+    pub checked: usize,
+    pub missing: Vec<Uuid>,
+    pub corrupted: Vec<Uuid>,
+    pub orphaned: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all="camelCase")]
+pub struct RelayoutReport {
+    pub moved: usize,
+    pub missing: Vec<Uuid>,
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all="camelCase")]
+pub struct RepairReport {
+    pub checked: usize,
+    pub restored: Vec<Uuid>,
+    pub lost: Vec<Uuid>,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.corrupted.is_empty() && self.orphaned.is_empty()
+    }
+}
+
+/// Removes `files` rows no bucket references and blobs without a `files` row.
+/// Blobs of uploads that are still in flight have no committed row yet, so run it while uploads are quiet.
+pub async fn gc(pool: &PgPool, store: &Store, dry_run: bool) -> Result<GcReport, AppError> {
+    let mut report = GcReport::default();
+
+    let unreferenced = query!(r#"
+    SELECT id, extension, blob, chunked
+    FROM files
+    WHERE NOT EXISTS (SELECT 1 FROM bucket_files WHERE bucket_files.file_id = files.id)
+    "#).fetch_all(pool).await?;
+
+    for rec in unreferenced {
+        if !dry_run {
+            let file = StoreFile::new(rec.id, rec.extension).with_blob(rec.blob);
+            let mut transaction = pool.begin().await?;
+            let mut released = chunks::release(&mut transaction, rec.id).await?;
+            released.extend(images::release(&mut transaction, rec.id).await?);
+            query!(r#"
+            DELETE FROM files
+            WHERE id = $1
+            "#, rec.id).execute(&mut transaction).await?;
+            let mut unshared = Vec::new();
+            for file in released.into_iter().chain((!rec.chunked).then_some(file)) {
+                if !blob_shared(&mut transaction, &file).await? {
+                    unshared.push(file);
+                }
+            }
+            transaction.commit().await?;
+            for file in unshared {
+                remove_blob(store, &file.path()).await?;
+            }
+        }
+        report.unreferenced_files.push(rec.id);
+    }
+
+    for path in orphaned_blobs(pool, store).await? {
+        if !dry_run {
+            remove_blob(store, &path).await?;
+        }
+        report.orphaned_blobs.push(path);
+    }
+
+    let unused_data_keys = query!(r#"
+    SELECT id
+    FROM data_keys
+    WHERE NOT EXISTS (SELECT 1 FROM buckets WHERE buckets.data_key_id = data_keys.id)
+    AND NOT EXISTS (SELECT 1 FROM files WHERE files.data_key_id = data_keys.id)
+    AND NOT EXISTS (SELECT 1 FROM chunks WHERE chunks.data_key_id = data_keys.id)
+    "#).fetch_all(pool).await?;
+
+    for rec in unused_data_keys {
+        if !dry_run {
+            query!(r#"
+            DELETE FROM data_keys
+            WHERE id = $1
+            "#, rec.id).execute(pool).await?;
+        }
+        report.unused_data_keys.push(rec.id);
+    }
+
+    debug!("Garbage collection finished: {report:?}");
+    Ok(report)
+}
+
+/// Checks that every `files` row has its blob or chunks and that their contents still match `files.checksum`
+pub async fn fsck(pool: &PgPool, store: &Store) -> Result<FsckReport, AppError> {
+    let mut report = FsckReport::default();
+
+    let files = query!(r#"
+    SELECT files.id, files.extension, files.checksum, files.codec, files.blob, files.chunked, data_keys.wrapped_key AS "wrapped_key?"
+    FROM files
+    LEFT JOIN data_keys ON data_keys.id = files.data_key_id
+    "#).fetch_all(pool).await?;
+
+    for rec in files {
+        report.checked += 1;
+        let codec = rec.codec.parse().map_err(|e: String| anyhow!(e))?;
+        let file = StoreFile::new(rec.id, rec.extension).with_data_key(rec.wrapped_key).with_codec(codec).with_blob(rec.blob);
+        let checksum = match rec.chunked {
+            true => checksum_stream(chunks::stream(pool, store, rec.id, None).await?).await,
+            false => store.checksum(&file).await,
+        };
+        match checksum {
+            Ok(checksum) if checksum == rec.checksum => {}
+            Ok(checksum) => {
+                warn!("File {} checksum mismatch: expected {}, found {checksum}", rec.id, rec.checksum);
+                report.corrupted.push(rec.id);
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                warn!("File {} is missing from the store", rec.id);
+                report.missing.push(rec.id);
+            }
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                warn!("File {} cannot be decrypted: {e}", rec.id);
+                report.corrupted.push(rec.id);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    report.orphaned = orphaned_blobs(pool, store).await?;
+    Ok(report)
+}
+
+/// Re-wraps every data key with `new` inside one transaction, blobs are left untouched.
+/// The store must still be configured with the current master key.
+pub async fn rotate_master_key(pool: &PgPool, store: &Store, new: &MasterKey) -> Result<usize, AppError> {
+    let mut transaction = pool.begin().await?;
+    let data_keys = query!(r#"
+    SELECT id, wrapped_key
+    FROM data_keys
+    FOR UPDATE
+    "#).fetch_all(&mut transaction).await?;
+
+    let count = data_keys.len();
+    for rec in data_keys {
+        let wrapped_key = store.rewrap_data_key(&rec.wrapped_key, new)?;
+        query!(r#"
+        UPDATE data_keys
+        SET wrapped_key = $1
+        WHERE id = $2
+        "#, wrapped_key, rec.id).execute(&mut transaction).await?;
+    }
+
+    transaction.commit().await?;
+    debug!("Re-wrapped {count} data keys");
+    Ok(count)
+}
+
+/// Moves blobs of the flat `<id>.<extension>` layout to their sharded address in batches of `batch` files.
+/// Each blob is linked to its new path before `files.blob` points at it, and the flat paths are only
+/// removed after `grace` has passed, so downloads that resolved the old path meanwhile still find it.
+pub async fn relayout(pool: &PgPool, store: &Store, batch: i64, grace: Duration) -> Result<RelayoutReport, AppError> {
+    let mut report = RelayoutReport::default();
+    let mut moved = Vec::new();
+    let mut last_id = Uuid::nil();
+
+    loop {
+        let files = query!(r#"
+        SELECT id, extension
+        FROM files
+        WHERE blob IS NULL AND NOT chunked AND id > $1
+        ORDER BY id
+        LIMIT $2
+        "#, last_id, batch).fetch_all(pool).await?;
+        let Some(last) = files.last() else {
+            break;
+        };
+        last_id = last.id;
+
+        for rec in files {
+            let file = StoreFile::new(rec.id, rec.extension);
+            let blob = match store.relocate(&file).await {
+                Ok(blob) => blob,
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    warn!("File {} is missing from the store", rec.id);
+                    report.missing.push(rec.id);
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            query!(r#"
+            UPDATE files
+            SET blob = $1
+            WHERE id = $2 AND blob IS NULL
+            "#, blob, rec.id).execute(pool).await?;
+            moved.push(file);
+        }
+        debug!("Relocated {} blobs so far", moved.len());
+    }
+
+    tokio::time::sleep(grace).await;
+    for file in &moved {
+        match store.remove_legacy(file).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+
+    report.moved = moved.len();
+    debug!("Relayout finished: {report:?}");
+    Ok(report)
+}
+
+async fn orphaned_blobs(pool: &PgPool, store: &Store) -> Result<Vec<PathBuf>, AppError> {
+    let mut known = query!(r#"
+    SELECT id, extension, blob
+    FROM files
+    WHERE NOT chunked
+    "#).fetch_all(pool).await?.into_iter()
+        .map(|rec| StoreFile::new(rec.id, rec.extension).with_blob(rec.blob).path())
+        .collect::<HashSet<_>>();
+    known.extend(query!(r#"
+    SELECT id, blob
+    FROM chunks
+    "#).fetch_all(pool).await?.into_iter().map(|rec| StoreFile::new(rec.id, None).with_blob(Some(rec.blob)).path()));
+    known.extend(query!(r#"
+    SELECT id, blob
+    FROM derivatives
+    "#).fetch_all(pool).await?.into_iter().map(|rec| StoreFile::new(rec.id, None).with_blob(Some(rec.blob)).path()));
+
+    Ok(store.blobs().await?.into_iter().filter(|path| !known.contains(path)).collect())
+}
+
+async fn remove_blob(store: &Store, path: &Path) -> Result<(), AppError> {
+    match store.remove_path(path).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Restores missing or corrupted replicas of every sharded blob, once per blob shared by several files.
+/// Chunks and image derivatives are reported by their own id.
+pub async fn repair(pool: &PgPool, store: &Store) -> Result<RepairReport, AppError> {
+    let mut report = RepairReport::default();
+    let mut repaired = HashSet::new();
+    let mut last_id = Uuid::nil();
+
+    loop {
+        let files = query!(r#"
+        SELECT id, extension, blob AS "blob!"
+        FROM files
+        WHERE blob IS NOT NULL AND id > $1
+        ORDER BY id
+        LIMIT 1000
+        "#, last_id).fetch_all(pool).await?;
+        let Some(last) = files.last() else {
+            break;
+        };
+        last_id = last.id;
+
+        for rec in files {
+            if !repaired.insert(rec.blob.clone()) {
+                continue;
+            }
+            let file = StoreFile::new(rec.id, rec.extension).with_blob(Some(rec.blob));
+            report.checked += 1;
+            match store.repair(&file).await? {
+                Repair::Healthy => {}
+                Repair::Restored { .. } => report.restored.push(rec.id),
+                Repair::Lost => {
+                    warn!("File {} has no intact replica left", rec.id);
+                    report.lost.push(rec.id);
+                }
+            }
+        }
+    }
+
+    let mut last_id = Uuid::nil();
+    loop {
+        let blobs = query!(r#"
+        SELECT id AS "id!", blob AS "blob!"
+        FROM (
+            SELECT id, blob FROM chunks
+            UNION ALL
+            SELECT id, blob FROM derivatives
+        ) AS blobs
+        WHERE id > $1
+        ORDER BY id
+        LIMIT 1000
+        "#, last_id).fetch_all(pool).await?;
+        let Some(last) = blobs.last() else {
+            break;
+        };
+        last_id = last.id;
+
+        for rec in blobs {
+            if !repaired.insert(rec.blob.clone()) {
+                continue;
+            }
+            let blob = StoreFile::new(rec.id, None).with_blob(Some(rec.blob));
+            report.checked += 1;
+            match store.repair(&blob).await? {
+                Repair::Healthy => {}
+                Repair::Restored { .. } => report.restored.push(rec.id),
+                Repair::Lost => {
+                    warn!("Chunk or derivative {} has no intact replica left", rec.id);
+                    report.lost.push(rec.id);
+                }
+            }
+        }
+    }
+
+    debug!("Repair finished: {report:?}");
+    Ok(report)
+}
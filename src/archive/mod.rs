@@ -0,0 +1,219 @@
+//! Downloading many entries as one ZIP or tar.gz archive.
+//!
+//! The archive is written by a task into a bounded pipe while the response reads from it,
+//! entries are copied one buffer at a time straight from the store, so memory stays the same for any amount of data.
+//! ZIP entries are stored without compression and use ZIP64 fields, so entries and archives may exceed 4 GiB.
+//! A failure after the response started aborts the body instead of ending a truncated archive cleanly.
+use std::collections::HashSet;
+use std::io;
+use anyhow::anyhow;
+use async_compression::tokio::write::GzipEncoder;
+use async_zip::{Compression, ZipDateTimeBuilder, ZipEntryBuilder};
+use async_zip::tokio::write::ZipFileWriter;
+use axum::extract::State;
+use axum::{debug_handler, Json, Router};
+use axum::body::StreamBody;
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::post;
+use bucket_storage_client::models::{ArchiveFormat, ArchiveRequest};
+use futures::{AsyncWriteExt as _, StreamExt, TryStreamExt};
+use sqlx::{PgPool, query};
+use time::OffsetDateTime;
+use tokio::io::{AsyncWriteExt, DuplexStream};
+use tokio_tar::{Builder, EntryType, Header};
+use tokio_util::io::{ReaderStream, StreamReader};
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+use crate::AppState;
+use crate::auth::Claims;
+use crate::batch::MAX_IDS;
+use crate::chunks;
+use crate::errors::AppError;
+use crate::files::paths::{normalize_path, normalize_prefix};
+use crate::store::{ByteStream, Store, StoreFile};
+use crate::store::compression::Codec;
+
+/// Bytes buffered between the task writing the archive and the response
+const PIPE_SIZE: usize = 64 * 1024;
+/// Number of entries left out because they failed integrity verification
+pub const SKIPPED_ENTRIES: &str = "x-skipped-entries";
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/archive", post(archive))
+}
+
+/// Latest version of a live entry and where to read it from
+struct ArchiveEntry {
+    path: String,
+    file: StoreFile,
+    file_id: Uuid,
+    size: u64,
+    chunked: bool,
+    uploaded_at: OffsetDateTime,
+}
+
+#[utoipa::path(
+    post,
+    path = "/archive",
+    tag = "files",
+    request_body = ArchiveRequest,
+    responses(
+        (status = 200, description = "Archive of the entries at their paths, corrupted entries are left out and counted in `X-Skipped-Entries`", content_type = "application/octet-stream"),
+        (status = 400, description = "Both ids and prefix, too many ids or invalid prefix", body = ErrorResponse),
+        (status = 404, description = "An id or the folder does not exist", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+#[debug_handler(state = AppState)]
+async fn archive(claims: Claims, State(pool): State<PgPool>, State(store): State<Store>, Json(body): Json<ArchiveRequest>) -> Result<impl IntoResponse, AppError> {
+    if body.ids.is_some() && body.prefix.is_some() {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, "Either ids or a prefix can be given"));
+    }
+    if body.ids.as_ref().is_some_and(|ids| ids.len() > MAX_IDS) {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, format!("At most {MAX_IDS} ids are allowed")));
+    }
+    let prefix = normalize_prefix(body.prefix.as_deref().unwrap_or_default())?;
+
+    let rows = query!(r#"
+    SELECT bucket_files.entry_id, bucket_files.name, bucket_files.created_at, files.id, files.extension, files.size,
+        files.codec, files.blob, files.chunked, files.corrupted_at, data_keys.wrapped_key AS "wrapped_key?"
+    FROM bucket_files
+    JOIN files ON files.id = bucket_files.file_id
+    LEFT JOIN data_keys ON data_keys.id = files.data_key_id
+    WHERE bucket_id = $1 AND latest AND trashed_at IS NULL
+    AND ($2::UUID[] IS NULL OR bucket_files.entry_id = ANY($2))
+    AND starts_with(bucket_files.name, $3)
+    ORDER BY bucket_files.name, bucket_files.created_at
+    "#, claims.bucket_id, body.ids.as_deref(), prefix).fetch_all(&pool).await?;
+
+    if let Some(ids) = &body.ids {
+        let found = rows.iter().map(|rec| rec.entry_id).collect::<HashSet<_>>();
+        if let Some(missing) = ids.iter().find(|id| !found.contains(id)) {
+            return Err(AppError::expected(StatusCode::NOT_FOUND, format!("File {missing} not found")));
+        }
+    }
+    if !prefix.is_empty() && rows.is_empty() {
+        return Err(AppError::expected(StatusCode::NOT_FOUND, "Folder not found"));
+    }
+
+    let mut taken = HashSet::new();
+    let mut skipped = 0;
+    let mut entries = Vec::with_capacity(rows.len());
+    for rec in rows {
+        if rec.corrupted_at.is_some() {
+            warn!("Leaving corrupted {} out of the archive", rec.entry_id);
+            skipped += 1;
+            continue;
+        }
+        // Names stored before paths were validated could escape the extraction directory
+        let name = normalize_path(&rec.name).unwrap_or_else(|_| rec.entry_id.to_string());
+        let codec: Codec = rec.codec.parse().map_err(|e: String| anyhow!(e))?;
+        entries.push(ArchiveEntry {
+            path: unique_path(&mut taken, &name, rec.extension.as_deref()),
+            file: StoreFile::new(rec.id, rec.extension).with_data_key(rec.wrapped_key).with_codec(codec).with_blob(rec.blob),
+            file_id: rec.id,
+            size: rec.size as u64,
+            chunked: rec.chunked,
+            uploaded_at: rec.created_at,
+        });
+    }
+    debug!("Archiving {} entries of bucket {} as {:?}", entries.len(), claims.bucket_id, body.format);
+
+    let (reader, writer) = tokio::io::duplex(PIPE_SIZE);
+    let format = body.format;
+    let task = tokio::spawn(async move {
+        let res = match format {
+            ArchiveFormat::Zip => write_zip(&pool, &store, entries, writer).await,
+            ArchiveFormat::TarGz => write_tar_gz(&pool, &store, entries, writer).await,
+        };
+        if let Err(e) = &res {
+            error!("Writing archive failed: {e}");
+        }
+        res
+    });
+    // The pipe ends when the task drops its half, a failed task then turns into an error of the body
+    let failure = futures::stream::once(async move {
+        match task.await {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(Err(io::Error::other(e.to_string()))),
+            Err(e) => Some(Err(io::Error::other(e))),
+        }
+    }).filter_map(futures::future::ready);
+    let body = StreamBody::new(ReaderStream::new(reader).chain(failure));
+
+    let mut headers = HeaderMap::new();
+    let content_type = match format {
+        ArchiveFormat::Zip => "application/zip",
+        ArchiveFormat::TarGz => "application/gzip",
+    };
+    headers.append(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.append(CONTENT_DISPOSITION, format!("attachment; filename=\"archive.{}\"", format.extension()).parse().unwrap());
+    headers.append(HeaderName::from_static(SKIPPED_ENTRIES), skipped.into());
+    Ok((headers, body))
+}
+
+/// Appends ` (n)` to the file name until no earlier entry of the archive has the path
+fn unique_path(taken: &mut HashSet<String>, name: &str, extension: Option<&str>) -> String {
+    let join = |name: &str| match extension {
+        Some(ext) => format!("{name}.{ext}"),
+        None => name.to_string(),
+    };
+    let mut path = join(name);
+    let mut n = 1;
+    while taken.contains(&path) {
+        path = join(&format!("{name} ({n})"));
+        n += 1;
+    }
+    taken.insert(path.clone());
+    path
+}
+
+async fn open(pool: &PgPool, store: &Store, entry: &ArchiveEntry) -> Result<ByteStream, AppError> {
+    Ok(match entry.chunked {
+        true => chunks::stream(pool, store, entry.file_id, None).await?,
+        false => store.stream(&entry.file, None).await?,
+    })
+}
+
+async fn write_zip(pool: &PgPool, store: &Store, entries: Vec<ArchiveEntry>, writer: DuplexStream) -> Result<(), AppError> {
+    let mut zip = ZipFileWriter::with_tokio(writer);
+    for entry in &entries {
+        let time = entry.uploaded_at;
+        let modified = ZipDateTimeBuilder::new()
+            .year(time.year()).month(u8::from(time.month()).into()).day(time.day().into())
+            .hour(time.hour().into()).minute(time.minute().into()).second(time.second().into())
+            .build();
+        let header = ZipEntryBuilder::new(entry.path.clone().into(), Compression::Stored)
+            .last_modification_date(modified)
+            .unix_permissions(0o644);
+
+        let mut stream = open(pool, store, entry).await?;
+        let mut entry_writer = zip.write_entry_stream(header).await.map_err(anyhow::Error::from)?;
+        while let Some(bytes) = stream.try_next().await? {
+            entry_writer.write_all(&bytes).await?;
+        }
+        entry_writer.close().await.map_err(anyhow::Error::from)?;
+    }
+    let mut writer = zip.close().await.map_err(anyhow::Error::from)?.into_inner();
+    writer.shutdown().await?;
+    Ok(())
+}
+
+async fn write_tar_gz(pool: &PgPool, store: &Store, entries: Vec<ArchiveEntry>, writer: DuplexStream) -> Result<(), AppError> {
+    let mut tar = Builder::new(GzipEncoder::new(writer));
+    for entry in &entries {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(entry.size);
+        header.set_mode(0o644);
+        header.set_mtime(entry.uploaded_at.unix_timestamp().max(0) as u64);
+
+        let stream = open(pool, store, entry).await?;
+        tar.append_data(&mut header, &entry.path, StreamReader::new(stream)).await?;
+    }
+    tar.into_inner().await?.shutdown().await?;
+    Ok(())
+}
@@ -9,12 +9,13 @@ use axum::{async_trait, Json, Router};
 use axum::response::IntoResponse;
 use axum::routing::get;
 use base64::Engine;
+use bucket_storage_client::models::IssuedKey;
 use rand::thread_rng;
-use serde_json::json;
-use sqlx::{PgPool, query};
+use sqlx::{PgPool, Postgres, query, Transaction};
 use sqlx::types::Uuid;
 use tracing::debug;
 use crate::AppState;
+use crate::buckets::create_bucket;
 use crate::errors::AppError;
 
 
@@ -24,31 +25,63 @@ pub fn router() -> Router<AppState> {
         .route("/key/verify", get(verify_key))
 }
 
+#[utoipa::path(
+    get,
+    path = "/key/verify",
+    tag = "auth",
+    responses(
+        (status = 200, description = "Key is valid", body = String),
+        (status = 400, description = "Missing or invalid credentials", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
 async fn verify_key(claims: Claims) -> impl IntoResponse {
     debug!("Verified key with access to bucket: {}", claims.bucket_id);
     "Authorized access"
 }
 
-async fn issue_key(State(pool): State<PgPool>) -> Result<impl IntoResponse, AppError> {
+#[utoipa::path(
+    get,
+    path = "/key",
+    tag = "auth",
+    responses(
+        (status = 200, description = "New bucket with its first key", body = IssuedKey),
+    ),
+)]
+async fn issue_key(State(pool): State<PgPool>) -> Result<Json<IssuedKey>, AppError> {
     let mut transaction = pool.begin().await?;
 
-    let bucket_name = "bucket";
-    let bucket_id = query!(r#"
-    INSERT INTO buckets (name)
-    VALUES ($1)
-    RETURNING id
-    "#, bucket_name).fetch_one(&mut transaction).await?.id;
+    let bucket_id = create_bucket(&mut transaction, "bucket").await?;
+    let (key_id, key) = create_key(&mut transaction, bucket_id).await?;
+
+    transaction.commit().await?;
+    debug!("Issued new bucket key");
+    Ok(Json(IssuedKey { id: key_id, key }))
+}
 
+/// Generates a new key for the bucket and stores its hash, returning the key id and the plain key
+pub async fn create_key(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid) -> Result<(Uuid, String), AppError> {
     let key = Uuid::new_v4().to_string();
     let key_id = query!(r#"
     INSERT INTO bucket_keys (key, bucket_id)
     VALUES ($1, $2)
     RETURNING id
-    "#, ArgonHash::hash(&key)?, bucket_id).fetch_one(&mut transaction).await?.id;
+    "#, ArgonHash::hash(&key)?, bucket_id).fetch_one(&mut *transaction).await?.id;
 
-    transaction.commit().await?;
-    debug!("Issued new bucket key");
-    Ok(Json(json!({"id": key_id, "key": key})))
+    Ok((key_id, key))
+}
+
+pub async fn revoke_key(pool: &PgPool, key_id: Uuid) -> Result<(), AppError> {
+    let res = query!(r#"
+    DELETE FROM bucket_keys
+    WHERE id = $1
+    "#, key_id).execute(pool).await?;
+
+    if res.rows_affected() == 0 {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, "Key does not exists"));
+    }
+    debug!("Revoked key {key_id}");
+    Ok(())
 }
 
 pub struct Claims {
@@ -71,6 +104,31 @@ impl <S>FromRequestParts<S> for Claims
     }
 }
 
+/// Bucket of the credentials in `X-Destination-Authorization`, required to write into a second bucket
+pub struct DestinationClaims(pub Option<Claims>);
+
+pub const DESTINATION_AUTHORIZATION: &str = "x-destination-authorization";
+
+#[async_trait]
+impl <S>FromRequestParts<S> for DestinationClaims
+    where S: Send + Sync, PgPool: FromRef<S>
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(authorization) = parts.headers.get(DESTINATION_AUTHORIZATION) else {
+            return Ok(Self(None));
+        };
+        let authorization = authorization.to_str()
+            .map_err(|_| AppError::expected(StatusCode::BAD_REQUEST, "`X-Destination-Authorization` header contains invalid characters"))?;
+        let credentials = parse_basic(authorization, "X-Destination-Authorization")?;
+        let pool = PgPool::from_ref(state);
+        let bucket_id = verify_credentials(&pool, &credentials).await?;
+
+        Ok(Self(Some(Claims { key_id: credentials.key_id, bucket_id })))
+    }
+}
+
 pub struct ArgonHash;
 
 impl ArgonHash {
@@ -123,14 +181,17 @@ pub async fn verify_credentials(pool: &PgPool, credentials: &Credentials) -> Res
 
 pub async fn get_auth_parts(parts: &mut Parts) -> Result<Credentials, AppError>{
     let authorization = get_auth_header(parts)?;
+    parse_basic(authorization, AUTHORIZATION.as_str())
+}
+
+fn parse_basic(authorization: &str, header: &str) -> Result<Credentials, AppError> {
     let split = authorization.split_once(' ');
     match split {
-        Some((name, contents)) if name == "Basic" => {
+        Some(("Basic", contents)) => {
             Ok(decode(contents)?)
         }
-        _ => Err(AppError::expected(StatusCode::BAD_REQUEST, "`Authorization` header must be for basic authentication"))
+        _ => Err(AppError::expected(StatusCode::BAD_REQUEST, format!("`{header}` header must be for basic authentication")))
     }
-
 }
 
 fn decode(input: &str) -> Result<Credentials, AppError> {
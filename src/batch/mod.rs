@@ -0,0 +1,156 @@
+//! Deleting, reading and tagging the metadata of many entries in one request.
+//!
+//! Entries are handled in chunks of [`CHUNK_SIZE`], each in its own transaction, so a large batch
+//! never holds its locks for long. A failure of one entry is reported in its [`BatchResult`]
+//! and leaves the others alone. Blobs of deleted entries are removed once their chunk committed.
+use std::collections::HashMap;
+use axum::extract::State;
+use axum::{debug_handler, Json, Router};
+use axum::http::StatusCode;
+use axum::routing::post;
+use bucket_storage_client::models::{BatchDelete, BatchFetch, BatchMetadata, BatchResult};
+use sqlx::{PgPool, query};
+use sqlx::types::Json as JsonColumn;
+use tracing::debug;
+use uuid::Uuid;
+use crate::AppState;
+use crate::auth::Claims;
+use crate::errors::AppError;
+use crate::files::{delete_file, remove_released};
+use crate::files::paths::normalize_prefix;
+use crate::metadata::{change_metadata, normalize_changes, Metadata};
+use crate::store::Store;
+
+/// Entries handled by one transaction
+pub const CHUNK_SIZE: usize = 500;
+/// Upper bound of ids in one request
+pub const MAX_IDS: usize = 10_000;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/batch/delete", post(delete))
+        .route("/batch/metadata", post(update_metadata))
+        .route("/batch/metadata/fetch", post(fetch_metadata))
+}
+
+#[utoipa::path(
+    post,
+    path = "/batch/delete",
+    tag = "files",
+    request_body = BatchDelete,
+    responses(
+        (status = 200, description = "Outcome of every entry, deleted like single files", body = [BatchResult]),
+        (status = 400, description = "Neither or both of ids and prefix, too many ids or invalid prefix", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+#[debug_handler(state = AppState)]
+async fn delete(claims: Claims, State(pool): State<PgPool>, State(store): State<Store>, Json(body): Json<BatchDelete>) -> Result<Json<Vec<BatchResult>>, AppError> {
+    let ids = match (body.ids, body.prefix) {
+        (Some(ids), None) => checked_ids(ids)?,
+        (None, Some(prefix)) => {
+            let prefix = normalize_prefix(&prefix)?;
+            if prefix.is_empty() {
+                return Err(AppError::expected(StatusCode::BAD_REQUEST, "Cannot delete the root of the bucket"));
+            }
+            query!(r#"
+            SELECT entry_id
+            FROM bucket_files
+            WHERE bucket_id = $1 AND latest AND file_id IS NOT NULL AND trashed_at IS NULL AND starts_with(name, $2)
+            ORDER BY name
+            "#, claims.bucket_id, prefix).fetch_all(&pool).await?.into_iter().map(|rec| rec.entry_id).collect()
+        }
+        _ => return Err(AppError::expected(StatusCode::BAD_REQUEST, "Either ids or a prefix is required")),
+    };
+
+    let mut results = Vec::with_capacity(ids.len());
+    for chunk in ids.chunks(CHUNK_SIZE) {
+        let mut transaction = pool.begin().await?;
+        let mut released = Vec::new();
+        for &id in chunk {
+            // Expected errors are raised before anything is written, the transaction stays usable
+            let res = delete_file(&mut transaction, claims.bucket_id, id).await;
+            results.push(batch_result(id, res.map(|files| released.extend(files)))?);
+        }
+        transaction.commit().await?;
+        remove_released(&store, released).await;
+    }
+    debug!("Deleted {} of {} entries", results.iter().filter(|res| res.is_success()).count(), results.len());
+    Ok(Json(results))
+}
+
+#[utoipa::path(
+    post,
+    path = "/batch/metadata/fetch",
+    tag = "files",
+    request_body = BatchFetch,
+    responses(
+        (status = 200, description = "Metadata of every entry, or its status code when it does not exist", body = [BatchResult]),
+        (status = 400, description = "Too many ids", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+async fn fetch_metadata(claims: Claims, State(pool): State<PgPool>, Json(body): Json<BatchFetch>) -> Result<Json<Vec<BatchResult>>, AppError> {
+    let ids = checked_ids(body.ids)?;
+
+    let mut results = Vec::with_capacity(ids.len());
+    for chunk in ids.chunks(CHUNK_SIZE) {
+        let found = query!(r#"
+        SELECT entry_id, metadata AS "metadata: JsonColumn<Metadata>"
+        FROM bucket_files
+        WHERE bucket_id = $1 AND entry_id = ANY($2) AND latest AND file_id IS NOT NULL AND trashed_at IS NULL
+        "#, claims.bucket_id, chunk).fetch_all(&pool).await?
+            .into_iter().map(|rec| (rec.entry_id, rec.metadata.0)).collect::<HashMap<_, _>>();
+
+        results.extend(chunk.iter().map(|&id| match found.get(&id) {
+            Some(metadata) => BatchResult { id, status: StatusCode::OK.as_u16(), error: None, metadata: Some(metadata.clone()) },
+            None => BatchResult { id, status: StatusCode::NOT_FOUND.as_u16(), error: Some("File not found".to_string()), metadata: None },
+        }));
+    }
+    Ok(Json(results))
+}
+
+#[utoipa::path(
+    post,
+    path = "/batch/metadata",
+    tag = "files",
+    request_body = BatchMetadata,
+    responses(
+        (status = 200, description = "Outcome of every entry", body = [BatchResult]),
+        (status = 400, description = "Too many ids or invalid key", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+async fn update_metadata(claims: Claims, State(pool): State<PgPool>, Json(body): Json<BatchMetadata>) -> Result<Json<Vec<BatchResult>>, AppError> {
+    let ids = checked_ids(body.ids)?;
+    let changes = normalize_changes(body.changes)?;
+
+    let mut results = Vec::with_capacity(ids.len());
+    for chunk in ids.chunks(CHUNK_SIZE) {
+        let mut transaction = pool.begin().await?;
+        for &id in chunk {
+            // Missing entries and exceeded limits fail before the update
+            let res = change_metadata(&mut transaction, claims.bucket_id, id, &changes).await;
+            results.push(batch_result(id, res.map(|_| ()))?);
+        }
+        transaction.commit().await?;
+    }
+    debug!("Updated metadata of {} of {} entries", results.iter().filter(|res| res.is_success()).count(), results.len());
+    Ok(Json(results))
+}
+
+fn checked_ids(ids: Vec<Uuid>) -> Result<Vec<Uuid>, AppError> {
+    if ids.len() > MAX_IDS {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, format!("At most {MAX_IDS} ids are allowed")));
+    }
+    Ok(ids)
+}
+
+/// Turns an expected error into a failed entry, unexpected ones still fail the whole request
+fn batch_result(id: Uuid, res: Result<(), AppError>) -> Result<BatchResult, AppError> {
+    match res {
+        Ok(()) => Ok(BatchResult { id, status: StatusCode::OK.as_u16(), error: None, metadata: None }),
+        Err(AppError::Expected { code, message }) => Ok(BatchResult { id, status: code.as_u16(), error: Some(message), metadata: None }),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,350 @@
+use std::path::PathBuf;
+use anyhow::anyhow;
+use std::process::ExitCode;
+use std::time::Duration;
+use clap::{Parser, Subcommand};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use dotenv::dotenv;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use uuid::Uuid;
+use bucket_storage::{admin, auth, buckets, lifecycle, migrate, scrub, trash, webhooks, AppState};
+use bucket_storage::errors::AppError;
+use bucket_storage::store::compression::Compression;
+use bucket_storage::store::crypto::MasterKey;
+
+/// Administration of buckets, keys and the blob store, bypassing the HTTP API
+#[derive(Parser)]
+#[command(name = "bucket-admin")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Manage buckets
+    #[command(subcommand)]
+    Bucket(BucketCommand),
+    /// Manage bucket keys
+    #[command(subcommand)]
+    Key(KeyCommand),
+    /// Manage rules deleting old entries
+    #[command(subcommand)]
+    Lifecycle(LifecycleCommand),
+    /// Show entry count and size per bucket
+    Usage { bucket_id: Option<Uuid> },
+    /// Remove unreferenced files and orphaned blobs
+    Gc {
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Verify that every stored blob exists and matches its checksum
+    Fsck,
+    /// Re-verify blob checksums now, recording corrupted files
+    Scrub {
+        /// Bytes hashed per second
+        #[arg(long, default_value_t = 16 * 1024 * 1024)]
+        rate: u64,
+    },
+    /// Show when files were last verified and which ones are corrupted or unreadable
+    ScrubStatus,
+    /// Run pending database migrations
+    Migrate,
+    /// Export a bucket into a directory
+    Export { bucket_id: Uuid, dir: PathBuf },
+    /// Import a bucket from an exported directory
+    Import { dir: PathBuf },
+    /// Move blobs stored flat as `<id>.<extension>` into the sharded layout, safe while the server runs
+    Relayout {
+        /// Files relocated per database query
+        #[arg(long, default_value_t = 1000)]
+        batch: i64,
+        /// Seconds to keep the flat copies around for downloads that already resolved them
+        #[arg(long, default_value_t = 30)]
+        grace: u64,
+    },
+    /// Restore missing or corrupted blob replicas across the store roots
+    Repair,
+    /// Remove deleted files whose trash retention passed
+    Purge,
+    /// Send the webhook deliveries that are due now
+    Deliver,
+    /// Print a new random master key in the form expected by `MASTER_KEY`
+    GenerateMasterKey,
+    /// Re-wrap all data keys with the master key stored in `new_key_file`
+    RotateMasterKey { new_key_file: PathBuf },
+}
+
+#[derive(Subcommand)]
+enum BucketCommand {
+    Create { name: String },
+    List,
+    Delete { bucket_id: Uuid },
+    /// Choose which future uploads of the bucket are compressed
+    Compression {
+        bucket_id: Uuid,
+        #[arg(value_enum)]
+        compression: Compression,
+    },
+    /// Keep older versions of overwritten and deleted files of the bucket
+    Versioning {
+        bucket_id: Uuid,
+        #[arg(action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
+    /// Split future uploads of the bucket into chunks shared with similar files
+    Chunking {
+        bucket_id: Uuid,
+        #[arg(action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
+    /// Remove EXIF and other embedded metadata from images uploaded to the bucket in the future
+    StripExif {
+        bucket_id: Uuid,
+        #[arg(action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
+    /// Keep deleted files of the bucket restorable for `secs` seconds, 0 deletes them right away
+    TrashRetention { bucket_id: Uuid, secs: u64 },
+    /// Warn webhooks of the bucket once it fills 90 percent of `bytes`, no quota without it
+    Quota { bucket_id: Uuid, bytes: Option<u64> },
+}
+
+#[derive(Subcommand)]
+enum KeyCommand {
+    Issue { bucket_id: Uuid },
+    Revoke { key_id: Uuid },
+}
+
+#[derive(Subcommand)]
+enum LifecycleCommand {
+    /// Delete entries of the bucket whose latest version is older than `days`
+    Add {
+        bucket_id: Uuid,
+        days: i32,
+        /// Only entries whose name starts with it
+        #[arg(long)]
+        prefix: Option<String>,
+        /// Only entries with this extension
+        #[arg(long)]
+        extension: Option<String>,
+    },
+    List { bucket_id: Option<Uuid> },
+    Remove { rule_id: Uuid },
+    /// Delete the entries expired by the rules or by the expiry set at upload
+    Apply {
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    dotenv().ok();
+
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "bucket_storage=warn".into()),
+        )
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .init();
+
+    let cli = Cli::parse();
+    let app_state = AppState::from_env().await;
+    match run(cli.command, &app_state).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(command: Command, app_state: &AppState) -> Result<ExitCode, AppError> {
+    let AppState { pool, store, webhooks: webhook_config } = app_state;
+    match command {
+        Command::Bucket(BucketCommand::Create { name }) => {
+            let mut transaction = pool.begin().await?;
+            let bucket_id = buckets::create_bucket(&mut transaction, &name).await?;
+            transaction.commit().await?;
+            println!("{bucket_id}");
+        }
+        Command::Bucket(BucketCommand::List) => {
+            for bucket in buckets::list_buckets(pool).await? {
+                println!("{}\t{}\t{} keys\t{} compression\tchunking {}\tversioning {}\ttrash {}s\tstrip exif {}\tquota {}", bucket.id, bucket.name, bucket.keys, bucket.compression, bucket.chunking, bucket.versioning, bucket.trash_retention_secs, bucket.strip_exif, bucket.quota_bytes.map_or("none".to_string(), |bytes| format!("{bytes} bytes")));
+            }
+        }
+        Command::Bucket(BucketCommand::Delete { bucket_id }) => {
+            buckets::delete_bucket(pool, store, bucket_id).await?;
+            println!("Deleted bucket {bucket_id}");
+        }
+        Command::Bucket(BucketCommand::Compression { bucket_id, compression }) => {
+            buckets::set_compression(pool, bucket_id, compression).await?;
+            println!("Compression of bucket {bucket_id} set to {compression}");
+        }
+        Command::Bucket(BucketCommand::TrashRetention { bucket_id, secs }) => {
+            buckets::set_trash_retention(pool, bucket_id, Duration::from_secs(secs)).await?;
+            println!("Trash retention of bucket {bucket_id} set to {secs}s");
+        }
+        Command::Bucket(BucketCommand::Versioning { bucket_id, enabled }) => {
+            buckets::set_versioning(pool, bucket_id, enabled).await?;
+            println!("Versioning of bucket {bucket_id} set to {enabled}");
+        }
+        Command::Bucket(BucketCommand::Chunking { bucket_id, enabled }) => {
+            buckets::set_chunking(pool, bucket_id, enabled).await?;
+            println!("Chunking of bucket {bucket_id} set to {enabled}");
+        }
+        Command::Bucket(BucketCommand::StripExif { bucket_id, enabled }) => {
+            buckets::set_strip_exif(pool, bucket_id, enabled).await?;
+            println!("EXIF stripping of bucket {bucket_id} set to {enabled}");
+        }
+        Command::Bucket(BucketCommand::Quota { bucket_id, bytes }) => {
+            buckets::set_quota(pool, bucket_id, bytes).await?;
+            match bytes {
+                Some(bytes) => println!("Quota of bucket {bucket_id} set to {bytes} bytes"),
+                None => println!("Quota of bucket {bucket_id} removed"),
+            }
+        }
+        Command::Key(KeyCommand::Issue { bucket_id }) => {
+            let mut transaction = pool.begin().await?;
+            let (key_id, key) = auth::create_key(&mut transaction, bucket_id).await?;
+            transaction.commit().await?;
+            println!("id: {key_id}\nkey: {key}");
+        }
+        Command::Key(KeyCommand::Revoke { key_id }) => {
+            auth::revoke_key(pool, key_id).await?;
+            println!("Revoked key {key_id}");
+        }
+        Command::Lifecycle(LifecycleCommand::Add { bucket_id, days, prefix, extension }) => {
+            let rule_id = lifecycle::add_rule(pool, bucket_id, prefix, extension, days).await?;
+            println!("{rule_id}");
+        }
+        Command::Lifecycle(LifecycleCommand::List { bucket_id }) => {
+            for rule in lifecycle::rules(pool, bucket_id).await? {
+                let prefix = rule.prefix.as_deref().unwrap_or("*");
+                let extension = rule.extension.as_deref().unwrap_or("*");
+                println!("{}\t{}\tprefix {prefix}\textension {extension}\t{} days", rule.id, rule.bucket_id, rule.expire_after_days);
+            }
+        }
+        Command::Lifecycle(LifecycleCommand::Remove { rule_id }) => {
+            lifecycle::remove_rule(pool, rule_id).await?;
+            println!("Removed rule {rule_id}");
+        }
+        Command::Lifecycle(LifecycleCommand::Apply { dry_run }) => {
+            let report = lifecycle::apply(pool, store, dry_run).await?;
+            for entry in &report.expired {
+                println!("{}\t{}\t{}{}", entry.bucket_id, entry.entry_id, entry.name, entry.extension.as_ref().map(|ext| format!(".{ext}")).unwrap_or_default());
+            }
+            let verb = if dry_run { "Would expire" } else { "Expired" };
+            println!("{verb} {} entries", report.expired.len());
+        }
+        Command::Usage { bucket_id } => {
+            for usage in buckets::usage(pool, bucket_id).await? {
+                println!("{}\t{}\t{} files\t{} bytes\t{} stored", usage.id, usage.name, usage.files, usage.bytes, usage.stored_bytes);
+            }
+        }
+        Command::Gc { dry_run } => {
+            let report = admin::gc(pool, store, dry_run).await?;
+            let verb = if dry_run { "Would remove" } else { "Removed" };
+            println!("{verb} {} unreferenced files, {} orphaned blobs and {} unused data keys", report.unreferenced_files.len(), report.orphaned_blobs.len(), report.unused_data_keys.len());
+        }
+        Command::Fsck => {
+            let report = admin::fsck(pool, store).await?;
+            println!("Checked {} files", report.checked);
+            for id in &report.missing {
+                println!("missing\t{id}");
+            }
+            for id in &report.corrupted {
+                println!("corrupted\t{id}");
+            }
+            for path in &report.orphaned {
+                println!("orphaned\t{}", path.display());
+            }
+            if !report.is_clean() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Scrub { rate } => {
+            let report = scrub::scrub(pool, store, rate).await?;
+            println!("Verified {} files, {} bytes", report.checked, report.bytes);
+            for id in &report.corrupted {
+                println!("corrupted\t{id}");
+            }
+            for id in &report.repaired {
+                println!("repaired\t{id}");
+            }
+            for id in &report.failed {
+                println!("unreadable\t{id}");
+            }
+            if !report.corrupted.is_empty() || !report.failed.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::ScrubStatus => {
+            let status = scrub::status(pool).await?;
+            let format = |at: Option<OffsetDateTime>| at.and_then(|at| at.format(&Rfc3339).ok()).unwrap_or_else(|| "never".to_string());
+            println!("{} files, {} never verified", status.files, status.unverified);
+            println!("oldest verification\t{}", format(status.oldest_verification));
+            println!("latest verification\t{}", format(status.latest_verification));
+            for id in &status.corrupted {
+                println!("corrupted\t{id}");
+            }
+            for id in &status.failed {
+                println!("unreadable\t{id}");
+            }
+        }
+        Command::Migrate => {
+            migrate(pool).await.map_err(anyhow::Error::from)?;
+            println!("Migrations applied");
+        }
+        Command::Export { bucket_id, dir } => {
+            let count = buckets::export_bucket(pool, store, bucket_id, &dir).await?;
+            println!("Exported {count} files to {}", dir.display());
+        }
+        Command::Import { dir } => {
+            let bucket_id = buckets::import_bucket(pool, store, &dir).await?;
+            println!("{bucket_id}");
+        }
+        Command::Relayout { batch, grace } => {
+            let report = admin::relayout(pool, store, batch, Duration::from_secs(grace)).await?;
+            println!("Moved {} blobs", report.moved);
+            for id in &report.missing {
+                println!("missing\t{id}");
+            }
+        }
+        Command::Repair => {
+            let report = admin::repair(pool, store).await?;
+            println!("Checked {} blobs over {} replicas", report.checked, store.replicas());
+            for id in &report.restored {
+                println!("restored\t{id}");
+            }
+            for id in &report.lost {
+                println!("lost\t{id}");
+            }
+            if !report.lost.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Purge => {
+            let report = trash::purge(pool, store).await?;
+            println!("Purged {} entries", report.entries);
+        }
+        Command::Deliver => {
+            let report = webhooks::deliver(pool, &webhooks::DeliveryClient::new(*webhook_config)).await?;
+            println!("Delivered {}, failed {}, dead {}", report.delivered, report.failed, report.dead);
+        }
+        Command::GenerateMasterKey => {
+            println!("{}", MasterKey::generate().to_base64());
+        }
+        Command::RotateMasterKey { new_key_file } => {
+            let new_key = tokio::fs::read_to_string(&new_key_file).await?;
+            let new_key = MasterKey::from_base64(&new_key).map_err(|e| anyhow!(e))?;
+            let count = admin::rotate_master_key(pool, store, &new_key).await?;
+            println!("Re-wrapped {count} data keys, switch the server to the new master key now");
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,396 @@
+use std::path::Path;
+use std::time::Duration;
+use anyhow::anyhow;
+use axum::body::Bytes;
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, query, Transaction};
+use tokio::fs;
+use tokio_util::io::StreamReader;
+use tracing::debug;
+use uuid::Uuid;
+use crate::chunks;
+use crate::errors::AppError;
+use crate::files::{release_files, remove_released, save_file};
+use crate::store::{checksum, Store, StoreFile};
+use crate::store::compression::Compression;
+
+const MANIFEST_NAME: &str = "manifest.json";
+const BLOBS_DIR: &str = "blobs";
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all="camelCase")]
+pub struct BucketInfo {
+    pub id: Uuid,
+    pub name: String,
+    pub keys: i64,
+    pub compression: String,
+    pub chunking: bool,
+    pub versioning: bool,
+    pub trash_retention_secs: i64,
+    pub strip_exif: bool,
+    pub quota_bytes: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all="camelCase")]
+pub struct BucketUsage {
+    pub id: Uuid,
+    pub name: String,
+    pub files: i64,
+    pub bytes: i64,
+    /// Bytes the blobs of the bucket take in the store after compression and encryption
+    pub stored_bytes: i64,
+}
+
+/// Layout of `manifest.json` written by [`export_bucket`]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all="camelCase")]
+struct Manifest {
+    name: String,
+    files: Vec<ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all="camelCase")]
+struct ManifestEntry {
+    name: String,
+    extension: Option<String>,
+    checksum: String,
+    size: i64,
+}
+
+pub async fn create_bucket(transaction: &mut Transaction<'_, Postgres>, name: &str) -> Result<Uuid, AppError> {
+    let bucket_id = query!(r#"
+    INSERT INTO buckets (name)
+    VALUES ($1)
+    RETURNING id
+    "#, name).fetch_one(&mut *transaction).await?.id;
+
+    debug!("Created bucket {bucket_id}");
+    Ok(bucket_id)
+}
+
+/// Data key new blobs of the bucket are encrypted with, created on first use.
+/// Returns `None` when the store has no master key and blobs are kept in plaintext.
+pub async fn bucket_data_key(transaction: &mut Transaction<'_, Postgres>, store: &Store, bucket_id: Uuid) -> Result<Option<(Uuid, Vec<u8>)>, AppError> {
+    if !store.is_encrypted() {
+        return Ok(None);
+    }
+
+    let rec = query!(r#"
+    SELECT data_keys.id, data_keys.wrapped_key
+    FROM buckets
+    JOIN data_keys ON data_keys.id = buckets.data_key_id
+    WHERE buckets.id = $1
+    "#, bucket_id).fetch_optional(&mut *transaction).await?;
+    if let Some(rec) = rec {
+        return Ok(Some((rec.id, rec.wrapped_key)));
+    }
+
+    let wrapped_key = store.new_data_key()?;
+    let data_key_id = query!(r#"
+    INSERT INTO data_keys (wrapped_key)
+    VALUES ($1)
+    RETURNING id
+    "#, wrapped_key).fetch_one(&mut *transaction).await?.id;
+
+    query!(r#"
+    UPDATE buckets
+    SET data_key_id = $1
+    WHERE id = $2
+    "#, data_key_id, bucket_id).execute(&mut *transaction).await?;
+
+    debug!("Created data key for bucket {bucket_id}");
+    Ok(Some((data_key_id, wrapped_key)))
+}
+
+/// Compression policy applied to new blobs of the bucket
+pub async fn bucket_compression(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid) -> Result<Compression, AppError> {
+    let rec = query!(r#"
+    SELECT compression
+    FROM buckets
+    WHERE id = $1
+    "#, bucket_id).fetch_optional(&mut *transaction).await?.ok_or(AppError::expected(StatusCode::BAD_REQUEST, "Bucket does not exists"))?;
+
+    Ok(rec.compression.parse().map_err(|e: String| anyhow!(e))?)
+}
+
+/// Changes the policy for future uploads, stored blobs keep their codec
+pub async fn set_compression(pool: &PgPool, bucket_id: Uuid, compression: Compression) -> Result<(), AppError> {
+    let res = query!(r#"
+    UPDATE buckets
+    SET compression = $1
+    WHERE id = $2
+    "#, compression.as_str(), bucket_id).execute(pool).await?;
+
+    if res.rows_affected() == 0 {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, "Bucket does not exists"));
+    }
+    debug!("Set compression of bucket {bucket_id} to {compression}");
+    Ok(())
+}
+
+/// Whether new files of the bucket are split into deduplicated chunks
+pub async fn bucket_chunking(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid) -> Result<bool, AppError> {
+    let rec = query!(r#"
+    SELECT chunking
+    FROM buckets
+    WHERE id = $1
+    "#, bucket_id).fetch_optional(&mut *transaction).await?.ok_or(AppError::expected(StatusCode::BAD_REQUEST, "Bucket does not exists"))?;
+
+    Ok(rec.chunking)
+}
+
+/// Turns chunking on or off for future uploads, stored files keep their layout
+pub async fn set_chunking(pool: &PgPool, bucket_id: Uuid, chunking: bool) -> Result<(), AppError> {
+    let res = query!(r#"
+    UPDATE buckets
+    SET chunking = $1
+    WHERE id = $2
+    "#, chunking, bucket_id).execute(pool).await?;
+
+    if res.rows_affected() == 0 {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, "Bucket does not exists"));
+    }
+    debug!("Set chunking of bucket {bucket_id} to {chunking}");
+    Ok(())
+}
+
+/// Whether writes to an existing name of the bucket add a version and deletes leave a marker
+pub async fn bucket_versioning(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid) -> Result<bool, AppError> {
+    let rec = query!(r#"
+    SELECT versioning
+    FROM buckets
+    WHERE id = $1
+    "#, bucket_id).fetch_optional(&mut *transaction).await?.ok_or(AppError::expected(StatusCode::BAD_REQUEST, "Bucket does not exists"))?;
+
+    Ok(rec.versioning)
+}
+
+/// Turns versioning on or off, existing versions are kept either way
+pub async fn set_versioning(pool: &PgPool, bucket_id: Uuid, versioning: bool) -> Result<(), AppError> {
+    let res = query!(r#"
+    UPDATE buckets
+    SET versioning = $1
+    WHERE id = $2
+    "#, versioning, bucket_id).execute(pool).await?;
+
+    if res.rows_affected() == 0 {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, "Bucket does not exists"));
+    }
+    debug!("Set versioning of bucket {bucket_id} to {versioning}");
+    Ok(())
+}
+
+/// Whether images uploaded to the bucket have their EXIF and other embedded metadata removed
+pub async fn bucket_strip_exif(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid) -> Result<bool, AppError> {
+    let rec = query!(r#"
+    SELECT strip_exif
+    FROM buckets
+    WHERE id = $1
+    "#, bucket_id).fetch_optional(&mut *transaction).await?.ok_or(AppError::expected(StatusCode::BAD_REQUEST, "Bucket does not exists"))?;
+
+    Ok(rec.strip_exif)
+}
+
+/// Turns stripping of image metadata on or off for future uploads, stored files are left as they are
+pub async fn set_strip_exif(pool: &PgPool, bucket_id: Uuid, strip_exif: bool) -> Result<(), AppError> {
+    let res = query!(r#"
+    UPDATE buckets
+    SET strip_exif = $1
+    WHERE id = $2
+    "#, strip_exif, bucket_id).execute(pool).await?;
+
+    if res.rows_affected() == 0 {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, "Bucket does not exists"));
+    }
+    debug!("Set EXIF stripping of bucket {bucket_id} to {strip_exif}");
+    Ok(())
+}
+
+/// Sets the size past whose 90 percent uploads send a quota warning to webhooks, `None` removes it.
+/// The quota only warns, uploads above it still succeed.
+/// Usage is counted once here and kept up to date by a trigger while the quota is set,
+/// setting it again recounts writes that were in flight meanwhile.
+pub async fn set_quota(pool: &PgPool, bucket_id: Uuid, quota_bytes: Option<u64>) -> Result<(), AppError> {
+    let quota_bytes = quota_bytes.map(|bytes| i64::try_from(bytes).ok().filter(|bytes| *bytes > 0)
+        .ok_or(AppError::expected(StatusCode::BAD_REQUEST, "Quota must be positive"))).transpose()?;
+    let res = query!(r#"
+    UPDATE buckets
+    SET quota_bytes = $1, quota_warned = false, used_bytes = CASE WHEN $1::BIGINT IS NULL THEN 0 ELSE (
+        SELECT COALESCE(SUM(files.size), 0)
+        FROM bucket_files
+        JOIN files ON files.id = bucket_files.file_id
+        WHERE bucket_files.bucket_id = buckets.id
+    ) END
+    WHERE id = $2
+    "#, quota_bytes, bucket_id).execute(pool).await?;
+
+    if res.rows_affected() == 0 {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, "Bucket does not exists"));
+    }
+    debug!("Set quota of bucket {bucket_id} to {quota_bytes:?}");
+    Ok(())
+}
+
+/// How long deleted entries of the bucket stay in the trash, zero when they are removed right away
+pub async fn bucket_trash_retention(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid) -> Result<Duration, AppError> {
+    let rec = query!(r#"
+    SELECT trash_retention_secs
+    FROM buckets
+    WHERE id = $1
+    "#, bucket_id).fetch_optional(&mut *transaction).await?.ok_or(AppError::expected(StatusCode::BAD_REQUEST, "Bucket does not exists"))?;
+
+    Ok(Duration::from_secs(rec.trash_retention_secs as u64))
+}
+
+/// Changes the retention of the trash, entries already in it expire by the new retention
+pub async fn set_trash_retention(pool: &PgPool, bucket_id: Uuid, retention: Duration) -> Result<(), AppError> {
+    let res = query!(r#"
+    UPDATE buckets
+    SET trash_retention_secs = $1
+    WHERE id = $2
+    "#, retention.as_secs() as i64, bucket_id).execute(pool).await?;
+
+    if res.rows_affected() == 0 {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, "Bucket does not exists"));
+    }
+    debug!("Set trash retention of bucket {bucket_id} to {retention:?}");
+    Ok(())
+}
+
+pub async fn list_buckets(pool: &PgPool) -> Result<Vec<BucketInfo>, AppError> {
+    let buckets = query!(r#"
+    SELECT buckets.id, buckets.name, buckets.compression, buckets.chunking, buckets.versioning, buckets.trash_retention_secs, buckets.strip_exif, buckets.quota_bytes, COUNT(bucket_keys.id) AS "keys!"
+    FROM buckets
+    LEFT JOIN bucket_keys ON bucket_keys.bucket_id = buckets.id
+    GROUP BY buckets.id
+    ORDER BY buckets.name
+    "#).fetch_all(pool).await?;
+
+    Ok(buckets.into_iter().map(|rec| BucketInfo { id: rec.id, name: rec.name, keys: rec.keys, compression: rec.compression, chunking: rec.chunking, versioning: rec.versioning, trash_retention_secs: rec.trash_retention_secs, strip_exif: rec.strip_exif, quota_bytes: rec.quota_bytes }).collect())
+}
+
+/// Per bucket entry count, logical and stored size, every bucket when `bucket_id` is `None`
+pub async fn usage(pool: &PgPool, bucket_id: Option<Uuid>) -> Result<Vec<BucketUsage>, AppError> {
+    let usage = query!(r#"
+    SELECT buckets.id, buckets.name, COUNT(files.id) AS "files!", COALESCE(SUM(files.size), 0)::BIGINT AS "bytes!", COALESCE(SUM(files.stored_size), 0)::BIGINT AS "stored_bytes!"
+    FROM buckets
+    LEFT JOIN bucket_files ON bucket_files.bucket_id = buckets.id
+    LEFT JOIN files ON files.id = bucket_files.file_id
+    WHERE $1::UUID IS NULL OR buckets.id = $1
+    GROUP BY buckets.id
+    ORDER BY buckets.name
+    "#, bucket_id).fetch_all(pool).await?;
+
+    Ok(usage.into_iter().map(|rec| BucketUsage { id: rec.id, name: rec.name, files: rec.files, bytes: rec.bytes, stored_bytes: rec.stored_bytes }).collect())
+}
+
+async fn bucket_name(pool: &PgPool, bucket_id: Uuid) -> Result<String, AppError> {
+    let rec = query!(r#"
+    SELECT name
+    FROM buckets
+    WHERE id = $1
+    "#, bucket_id).fetch_optional(pool).await?.ok_or(AppError::expected(StatusCode::BAD_REQUEST, "Bucket does not exists"))?;
+
+    Ok(rec.name)
+}
+
+/// Drops every version of every entry, releasing files no other bucket references, then its keys and the bucket itself
+pub async fn delete_bucket(pool: &PgPool, store: &Store, bucket_id: Uuid) -> Result<(), AppError> {
+    bucket_name(pool, bucket_id).await?;
+    let mut transaction = pool.begin().await?;
+
+    let versions = query!(r#"
+    DELETE FROM bucket_files
+    WHERE bucket_id = $1
+    RETURNING file_id
+    "#, bucket_id).fetch_all(&mut transaction).await?;
+
+    let released = release_files(&mut transaction, versions.into_iter().filter_map(|rec| rec.file_id)).await?;
+
+    query!(r#"
+    DELETE FROM upload_keys
+    WHERE bucket_id = $1
+    "#, bucket_id).execute(&mut transaction).await?;
+
+    query!(r#"
+    DELETE FROM bucket_keys
+    WHERE bucket_id = $1
+    "#, bucket_id).execute(&mut transaction).await?;
+
+    query!(r#"
+    DELETE FROM buckets
+    WHERE id = $1
+    "#, bucket_id).execute(&mut transaction).await?;
+
+    transaction.commit().await?;
+    remove_released(store, released).await;
+    debug!("Deleted bucket {bucket_id}");
+    Ok(())
+}
+
+/// Writes `manifest.json` and one decrypted blob per distinct checksum of the latest versions into `dir`
+pub async fn export_bucket(pool: &PgPool, store: &Store, bucket_id: Uuid, dir: &Path) -> Result<usize, AppError> {
+    let name = bucket_name(pool, bucket_id).await?;
+    let entries = query!(r#"
+    SELECT files.id, bucket_files.name, files.extension, files.checksum, files.size, files.codec, files.blob, files.chunked, data_keys.wrapped_key AS "wrapped_key?"
+    FROM bucket_files
+    JOIN files ON files.id = bucket_files.file_id
+    LEFT JOIN data_keys ON data_keys.id = files.data_key_id
+    WHERE bucket_id = $1 AND latest AND trashed_at IS NULL
+    ORDER BY bucket_files.name
+    "#, bucket_id).fetch_all(pool).await?;
+
+    let blobs = dir.join(BLOBS_DIR);
+    fs::create_dir_all(&blobs).await?;
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let target = blobs.join(&entry.checksum);
+        if !fs::try_exists(&target).await? {
+            let codec = entry.codec.parse().map_err(|e: String| anyhow!(e))?;
+            let file = StoreFile::new(entry.id, entry.extension.clone()).with_data_key(entry.wrapped_key).with_codec(codec).with_blob(entry.blob);
+            let stream = match entry.chunked {
+                true => chunks::stream(pool, store, entry.id, None).await?,
+                false => store.stream(&file, None).await?,
+            };
+            let mut source = StreamReader::new(stream);
+            let mut target = fs::File::create(&target).await?;
+            tokio::io::copy(&mut source, &mut target).await?;
+        }
+        files.push(ManifestEntry {
+            name: entry.name,
+            extension: entry.extension,
+            checksum: entry.checksum,
+            size: entry.size,
+        });
+    }
+
+    let count = files.len();
+    let manifest = serde_json::to_vec_pretty(&Manifest { name, files }).map_err(anyhow::Error::from)?;
+    fs::write(dir.join(MANIFEST_NAME), manifest).await?;
+    debug!("Exported {count} files from bucket {bucket_id}");
+    Ok(count)
+}
+
+/// Recreates a bucket from a directory produced by [`export_bucket`], returning the new bucket id
+pub async fn import_bucket(pool: &PgPool, store: &Store, dir: &Path) -> Result<Uuid, AppError> {
+    let manifest = fs::read(dir.join(MANIFEST_NAME)).await?;
+    let manifest: Manifest = serde_json::from_slice(&manifest).map_err(anyhow::Error::from)?;
+
+    let mut transaction = pool.begin().await?;
+    let bucket_id = create_bucket(&mut transaction, &manifest.name).await?;
+    for entry in manifest.files {
+        let bytes = Bytes::from(fs::read(dir.join(BLOBS_DIR).join(&entry.checksum)).await?);
+        if checksum(&bytes) != entry.checksum {
+            return Err(AppError::Unexpected(anyhow!("Checksum mismatch for exported file {}", entry.name)));
+        }
+        save_file(&mut transaction, store, bucket_id, &entry.name, entry.extension, bytes).await?;
+    }
+    transaction.commit().await?;
+
+    debug!("Imported bucket {bucket_id}");
+    Ok(bucket_id)
+}
@@ -0,0 +1,166 @@
+//! Chunked storage for buckets with chunking enabled.
+//!
+//! Files are split by [`chunking::split`] and every distinct chunk is stored once per data key as its own blob,
+//! so successive versions of a large file only add the chunks around their edits.
+//! `chunks.refs` counts the `file_chunks` rows pointing at a chunk, which is dropped with its blob at zero.
+use std::collections::HashSet;
+use std::ops::Range;
+use anyhow::anyhow;
+use axum::body::Bytes;
+use futures::{stream, StreamExt, TryStreamExt};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, query, Transaction};
+use tracing::debug;
+use uuid::Uuid;
+use crate::errors::AppError;
+use crate::files;
+use crate::store::{ByteStream, Store, StoreFile};
+use crate::store::chunking;
+use crate::store::compression::Codec;
+
+/// Stores the chunks of `bytes` as the contents of the already inserted file `file_id`.
+/// Returns the stored size of all its chunks, shared ones included.
+pub async fn save_chunks(transaction: &mut Transaction<'_, Postgres>, store: &Store, file_id: Uuid, data_key: Option<(Uuid, Vec<u8>)>, codec: Codec, bytes: Bytes) -> Result<u64, AppError> {
+    let data_key_id = data_key.as_ref().map(|(id, _)| *id);
+    let wrapped_key = data_key.map(|(_, wrapped_key)| wrapped_key);
+    let mut start = 0;
+    let mut stored_size = 0;
+    let mut new_chunks = 0;
+
+    for (position, chunk) in chunking::split(&bytes).into_iter().enumerate() {
+        let digest = format!("{:x}", Sha256::digest(&chunk));
+        let existing = query!(r#"
+        UPDATE chunks
+        SET refs = refs + 1
+        WHERE digest = $1 AND data_key_id IS NOT DISTINCT FROM $2
+        RETURNING id, stored_size
+        "#, digest, data_key_id).fetch_optional(&mut *transaction).await?;
+
+        let (chunk_id, size) = match existing {
+            Some(rec) => (rec.id, rec.stored_size),
+            None => {
+                let file = StoreFile::new(Uuid::new_v4(), None).with_data_key(wrapped_key.clone()).with_codec(codec);
+                let saved = store.save(&file, chunk.clone()).await?;
+                // A concurrent upload may have stored the same chunk in the meantime
+                let rec = query!(r#"
+                INSERT INTO chunks (id, digest, data_key_id, codec, size, stored_size, blob, refs)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, 1)
+                ON CONFLICT (digest, COALESCE(data_key_id, '00000000-0000-0000-0000-000000000000'))
+                DO UPDATE SET refs = chunks.refs + 1
+                RETURNING id, stored_size, blob
+                "#, file.id, digest, data_key_id, saved.codec.as_str(), chunk.len() as i64, saved.stored_size as i64, saved.blob).fetch_one(&mut *transaction).await?;
+                if rec.id != file.id && rec.blob != saved.blob {
+                    store.remove(&file.with_blob(Some(saved.blob))).await?;
+                }
+                new_chunks += 1;
+                (rec.id, rec.stored_size)
+            }
+        };
+
+        query!(r#"
+        INSERT INTO file_chunks (file_id, position, chunk_id, start)
+        VALUES ($1, $2, $3, $4)
+        "#, file_id, position as i32, chunk_id, start).execute(&mut *transaction).await?;
+        start += chunk.len() as i64;
+        stored_size += size as u64;
+    }
+
+    debug!("Stored file {file_id} with {new_chunks} new chunks");
+    Ok(stored_size)
+}
+
+/// Stores the chunks of `bytes` again for the file `file_id`, whose contents they are, replacing the blobs of its chunks.
+/// The chunks stay shared with other files, which get their lost chunks back as well.
+pub async fn restore_chunks(transaction: &mut Transaction<'_, Postgres>, store: &Store, file_id: Uuid, wrapped_key: Option<Vec<u8>>, bytes: Bytes) -> Result<(), AppError> {
+    let parts = query!(r#"
+    SELECT chunks.id, chunks.codec, chunks.blob
+    FROM file_chunks
+    JOIN chunks ON chunks.id = file_chunks.chunk_id
+    WHERE file_chunks.file_id = $1
+    ORDER BY file_chunks.position
+    "#, file_id).fetch_all(&mut *transaction).await?;
+
+    let mut restored = HashSet::new();
+    for (part, chunk) in parts.into_iter().zip(chunking::split(&bytes)) {
+        if !restored.insert(part.id) {
+            continue;
+        }
+        let codec = part.codec.parse().map_err(|e: String| anyhow!(e))?;
+        let file = StoreFile::new(part.id, None).with_data_key(wrapped_key.clone()).with_codec(codec).with_blob(Some(part.blob));
+        let saved = store.save(&file, chunk).await?;
+        query!(r#"
+        UPDATE chunks
+        SET codec = $1, stored_size = $2, blob = $3
+        WHERE id = $4
+        "#, saved.codec.as_str(), saved.stored_size as i64, saved.blob, part.id).execute(&mut *transaction).await?;
+        if file.blob.as_ref() != Some(&saved.blob) {
+            files::remove_stale(store, &file).await?;
+        }
+    }
+
+    debug!("Restored {} chunks of file {file_id}", restored.len());
+    Ok(())
+}
+
+/// Streams the plaintext of a chunked file, limited to `range` when given.
+/// Chunks are opened one after another while the stream is consumed.
+pub async fn stream(pool: &PgPool, store: &Store, file_id: Uuid, range: Option<Range<u64>>) -> Result<ByteStream, AppError> {
+    let range = range.unwrap_or(0..u64::MAX);
+    let parts = query!(r#"
+    SELECT file_chunks.start, chunks.id, chunks.size, chunks.codec, chunks.blob, data_keys.wrapped_key AS "wrapped_key?"
+    FROM file_chunks
+    JOIN chunks ON chunks.id = file_chunks.chunk_id
+    LEFT JOIN data_keys ON data_keys.id = chunks.data_key_id
+    WHERE file_chunks.file_id = $1 AND file_chunks.start < $3 AND file_chunks.start + chunks.size > $2
+    ORDER BY file_chunks.position
+    "#, file_id, range.start as i64, range.end.min(i64::MAX as u64) as i64).fetch_all(pool).await?;
+
+    let parts = parts.into_iter().map(|rec| {
+        let codec = rec.codec.parse().map_err(|e: String| anyhow!(e))?;
+        let chunk = StoreFile::new(rec.id, None).with_data_key(rec.wrapped_key).with_codec(codec).with_blob(Some(rec.blob));
+        let start = rec.start as u64;
+        let within = range.start.saturating_sub(start)..range.end.saturating_sub(start).min(rec.size as u64);
+        Ok((chunk, within))
+    }).collect::<Result<Vec<_>, AppError>>()?;
+
+    let store = store.clone();
+    let stream = stream::iter(parts)
+        .then(move |(chunk, within)| {
+            let store = store.clone();
+            async move { store.stream(&chunk, Some(within)).await }
+        })
+        .try_flatten();
+    Ok(Box::pin(stream))
+}
+
+/// Drops the chunk references of a file, to be called before its row is deleted.
+/// Returns the chunks no file references anymore, their rows are deleted and their blobs left to the caller.
+pub async fn release(transaction: &mut Transaction<'_, Postgres>, file_id: Uuid) -> Result<Vec<StoreFile>, AppError> {
+    let released = query!(r#"
+    UPDATE chunks
+    SET refs = chunks.refs - used.count
+    FROM (
+        SELECT chunk_id, COUNT(*) AS "count"
+        FROM file_chunks
+        WHERE file_id = $1
+        GROUP BY chunk_id
+    ) AS used
+    WHERE chunks.id = used.chunk_id
+    RETURNING chunks.id, chunks.refs
+    "#, file_id).fetch_all(&mut *transaction).await?;
+
+    query!(r#"
+    DELETE FROM file_chunks
+    WHERE file_id = $1
+    "#, file_id).execute(&mut *transaction).await?;
+
+    let unused: Vec<Uuid> = released.into_iter().filter(|rec| rec.refs == 0).map(|rec| rec.id).collect();
+    let removed = query!(r#"
+    DELETE FROM chunks
+    WHERE id = ANY($1) AND refs = 0
+    RETURNING id, blob
+    "#, &unused[..]).fetch_all(&mut *transaction).await?;
+
+    debug!("Released {} chunks of file {file_id}", removed.len());
+    Ok(removed.into_iter().map(|rec| StoreFile::new(rec.id, None).with_blob(Some(rec.blob))).collect())
+}
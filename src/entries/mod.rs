@@ -0,0 +1,156 @@
+//! Renaming, copying and moving single entries, within a bucket or into another one.
+//!
+//! Writing into another bucket needs its credentials in `X-Destination-Authorization` on top of the usual ones.
+//! Copies reference the same `files` row as the source, the file and its blob or chunks stay
+//! until the last `bucket_files` row referencing it is removed, so copying stores nothing new.
+use axum::extract::{Path, State};
+use axum::{Json, Router};
+use axum::http::StatusCode;
+use axum::routing::post;
+use bucket_storage_client::models::{EntryDestination, WebhookEvent};
+use sqlx::{PgPool, Postgres, query, Transaction};
+use sqlx::types::Json as JsonColumn;
+use tracing::debug;
+use uuid::Uuid;
+use crate::AppState;
+use crate::auth::{Claims, DestinationClaims};
+use crate::errors::AppError;
+use crate::files::{add_to_bucket, split_file_name};
+use crate::files::paths::normalize_path;
+use crate::metadata::{Metadata, set_metadata};
+use crate::webhooks;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/files/:file_id/copy", post(copy))
+        .route("/files/:file_id/move", post(move_entry))
+}
+
+/// Latest version of a live entry
+struct Source {
+    name: String,
+    extension: Option<String>,
+    file_id: Uuid,
+    metadata: Metadata,
+}
+
+#[utoipa::path(
+    post,
+    path = "/files/{file_id}/copy",
+    tag = "files",
+    params(
+        ("file_id" = Uuid, Path, description = "Id of the file in the bucket"),
+        ("X-Destination-Authorization" = Option<String>, Header, description = "Basic credentials of the bucket to copy into, the same bucket when missing"),
+    ),
+    request_body = EntryDestination,
+    responses(
+        (status = 200, description = "Id of the entry holding the copy", body = Uuid),
+        (status = 400, description = "Invalid path, changed extension or wrong destination credentials", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+async fn copy(claims: Claims, DestinationClaims(destination): DestinationClaims, State(pool): State<PgPool>, Path(entry_id): Path<Uuid>, Json(body): Json<EntryDestination>) -> Result<Json<Uuid>, AppError> {
+    let target_bucket = destination.map_or(claims.bucket_id, |claims| claims.bucket_id);
+    let mut transaction = pool.begin().await?;
+    let source = source(&mut transaction, claims.bucket_id, entry_id).await?;
+    let name = target_name(&source, body.path.as_deref())?;
+
+    // Written like an upload of the same contents, a versioned destination adds a version of the entry at the path
+    let copy = add_to_bucket(&mut transaction, target_bucket, &name, source.extension.as_deref(), source.file_id).await?;
+    set_metadata(&mut transaction, target_bucket, copy, &source.metadata).await?;
+    webhooks::enqueue_created(&mut transaction, target_bucket, copy).await?;
+    transaction.commit().await?;
+    debug!("Copied {entry_id} to {copy} in bucket {target_bucket}");
+    Ok(Json(copy))
+}
+
+#[utoipa::path(
+    post,
+    path = "/files/{file_id}/move",
+    tag = "files",
+    params(
+        ("file_id" = Uuid, Path, description = "Id of the file in the bucket"),
+        ("X-Destination-Authorization" = Option<String>, Header, description = "Basic credentials of the bucket to move into, renames within the same bucket when missing"),
+    ),
+    request_body = EntryDestination,
+    responses(
+        (status = 200, description = "Id of the moved entry, a new one when the id is taken in the destination", body = Uuid),
+        (status = 400, description = "Invalid path, changed extension or wrong destination credentials", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+        (status = 409, description = "Another entry already exists at the destination path", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+async fn move_entry(claims: Claims, DestinationClaims(destination): DestinationClaims, State(pool): State<PgPool>, Path(entry_id): Path<Uuid>, Json(body): Json<EntryDestination>) -> Result<Json<Uuid>, AppError> {
+    let target_bucket = destination.map_or(claims.bucket_id, |claims| claims.bucket_id);
+    let mut transaction = pool.begin().await?;
+    let source = source(&mut transaction, claims.bucket_id, entry_id).await?;
+    let name = target_name(&source, body.path.as_deref())?;
+
+    // Versions of the moved entry cannot be merged into another one, the path has to be free
+    let rec = query!(r#"
+    SELECT EXISTS (
+        SELECT 1
+        FROM bucket_files
+        JOIN files ON files.id = bucket_files.file_id
+        WHERE bucket_id = $1 AND name = $2 AND files.extension IS NOT DISTINCT FROM $3 AND latest AND trashed_at IS NULL
+            AND NOT (bucket_id = $4 AND entry_id = $5)
+    ) AS "taken!"
+    "#, target_bucket, name, source.extension, claims.bucket_id, entry_id).fetch_one(&mut transaction).await?;
+    if rec.taken {
+        return Err(AppError::expected(StatusCode::CONFLICT, "Another entry already exists at the destination path"));
+    }
+
+    let target_entry = match target_bucket == claims.bucket_id {
+        true => entry_id,
+        false => {
+            let rec = query!(r#"
+            SELECT EXISTS (SELECT 1 FROM bucket_files WHERE bucket_id = $1 AND entry_id = $2) AS "taken!"
+            "#, target_bucket, entry_id).fetch_one(&mut transaction).await?;
+            if rec.taken { Uuid::new_v4() } else { entry_id }
+        }
+    };
+
+    // Every version moves along, so restoring an older one keeps the entry at its new path
+    query!(r#"
+    UPDATE bucket_files
+    SET bucket_id = $3, entry_id = $4, name = $5
+    WHERE bucket_id = $1 AND entry_id = $2
+    "#, claims.bucket_id, entry_id, target_bucket, target_entry, name).execute(&mut transaction).await?;
+
+    // Renames keep the object, between buckets it leaves one and appears in the other
+    if target_bucket != claims.bucket_id {
+        let data = serde_json::json!({ "entryId": entry_id, "name": source.name, "extension": source.extension });
+        webhooks::enqueue(&mut transaction, claims.bucket_id, WebhookEvent::ObjectDeleted, data).await?;
+        webhooks::enqueue_created(&mut transaction, target_bucket, target_entry).await?;
+    }
+    transaction.commit().await?;
+    debug!("Moved {entry_id} to {target_entry} in bucket {target_bucket}");
+    Ok(Json(target_entry))
+}
+
+async fn source(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid, entry_id: Uuid) -> Result<Source, AppError> {
+    let rec = query!(r#"
+    SELECT bucket_files.name, files.extension, files.id, bucket_files.metadata AS "metadata: JsonColumn<Metadata>"
+    FROM bucket_files
+    JOIN files ON files.id = bucket_files.file_id
+    WHERE bucket_id = $1 AND entry_id = $2 AND latest AND trashed_at IS NULL
+    FOR UPDATE OF bucket_files
+    "#, bucket_id, entry_id).fetch_optional(&mut *transaction).await?.ok_or(AppError::expected(StatusCode::NOT_FOUND, "File not found"))?;
+
+    Ok(Source { name: rec.name, extension: rec.extension, file_id: rec.id, metadata: rec.metadata.0 })
+}
+
+/// Name of the entry at `path`, which keeps the source path when missing.
+/// The extension belongs to the shared file, so it cannot change.
+fn target_name(source: &Source, path: Option<&str>) -> Result<String, AppError> {
+    let Some(path) = path else {
+        return Ok(source.name.clone());
+    };
+    let (name, extension) = split_file_name(&normalize_path(path)?);
+    if extension != source.extension {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, "Changing the extension of a file is not supported"));
+    }
+    Ok(name)
+}
@@ -2,7 +2,7 @@ use axum::extract::multipart::MultipartError;
 use axum::Json;
 use axum::response::{IntoResponse, Response};
 use reqwest::StatusCode;
-use serde::Serialize;
+use bucket_storage_client::models::ErrorResponse;
 use sqlx::Error;
 use thiserror::Error;
 use tracing::{error};
@@ -41,12 +41,6 @@ impl IntoResponse for AppError {
     }
 }
 
-#[derive(Serialize)]
-#[serde(rename_all="camelCase")]
-struct ErrorResponse {
-    error_info: String,
-}
-
 impl From<sqlx::Error> for AppError {
     fn from(e: Error) -> Self {
         Self::Unexpected(anyhow::Error::from(e))
@@ -0,0 +1,178 @@
+//! Expanding uploaded ZIP and tar archives into entries of the bucket.
+//!
+//! Every regular member is saved through [`save_file`] like an uploaded file, at its path inside the archive
+//! below the folder of the archive itself. Directories, links and other special members are skipped.
+//! Declared sizes are not trusted, members are read up to what is left of the limits and at most [`MAX_MEMBER_SIZE`],
+//! so an archive expanding beyond them fails without holding more than one capped member in memory.
+use async_compression::tokio::bufread::GzipDecoder;
+use async_zip::base::read::mem::ZipFileReader;
+use axum::body::Bytes;
+use axum::http::StatusCode;
+use futures::{AsyncReadExt as _, StreamExt};
+use sqlx::{Postgres, Transaction};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_tar::Archive;
+use tracing::debug;
+use uuid::Uuid;
+use crate::errors::AppError;
+use crate::store::Store;
+use super::paths::normalize_path;
+use super::{save_file, split_file_name};
+
+/// Upper bound of members in one archive, skipped ones included
+pub const MAX_ENTRIES: usize = 10_000;
+/// Upper bound of all members together in bytes
+pub const MAX_EXTRACTED_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+/// Upper bound of the extracted size relative to the size of the archive
+pub const MAX_RATIO: u64 = 100;
+/// Extracted size always allowed regardless of the ratio, small archives of text compress well
+pub const RATIO_FLOOR: u64 = 1024 * 1024;
+/// Upper bound of a single member in bytes, members are buffered whole before they are stored
+pub const MAX_MEMBER_SIZE: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    /// Recognizes archives by the extension of their file name
+    pub fn detect(name: &str, extension: Option<&str>) -> Option<Self> {
+        match extension? {
+            "zip" => Some(ArchiveKind::Zip),
+            "tar" => Some(ArchiveKind::Tar),
+            "tgz" => Some(ArchiveKind::TarGz),
+            "gz" if name.ends_with(".tar") => Some(ArchiveKind::TarGz),
+            _ => None,
+        }
+    }
+}
+
+/// What the archive may still expand to
+struct Budget {
+    limit: u64,
+    extracted: u64,
+    entries: usize,
+}
+
+impl Budget {
+    fn new(archive_len: u64) -> Self {
+        let limit = archive_len.saturating_mul(MAX_RATIO).clamp(RATIO_FLOOR, MAX_EXTRACTED_SIZE);
+        Self { limit, extracted: 0, entries: 0 }
+    }
+
+    fn remaining(&self) -> u64 {
+        self.limit - self.extracted
+    }
+
+    fn entry(&mut self) -> Result<(), AppError> {
+        self.entries += 1;
+        if self.entries > MAX_ENTRIES {
+            return Err(AppError::expected(StatusCode::PAYLOAD_TOO_LARGE, format!("Archive holds more than {MAX_ENTRIES} entries")));
+        }
+        Ok(())
+    }
+
+    /// Bytes of the next member read at most, one more tells that it exceeds the limits
+    fn member_limit(&self) -> u64 {
+        self.remaining().min(MAX_MEMBER_SIZE)
+    }
+
+    fn spend_member(&mut self, path: &str, len: u64) -> Result<(), AppError> {
+        if len > MAX_MEMBER_SIZE {
+            return Err(AppError::expected(StatusCode::PAYLOAD_TOO_LARGE, format!("Entry {path} of the archive is larger than {MAX_MEMBER_SIZE} bytes")));
+        }
+        self.spend(len)
+    }
+
+    fn spend(&mut self, len: u64) -> Result<(), AppError> {
+        if len > self.remaining() {
+            return Err(AppError::expected(StatusCode::PAYLOAD_TOO_LARGE, format!("Archive expands beyond {} bytes", self.limit)));
+        }
+        self.extracted += len;
+        Ok(())
+    }
+}
+
+/// Saves every member of the archive named `archive_name` and returns the ids of their entries
+pub async fn save_archive(transaction: &mut Transaction<'_, Postgres>, store: &Store, bucket_id: Uuid, archive_name: &str, kind: ArchiveKind, archive: Bytes) -> Result<Vec<Uuid>, AppError> {
+    let folder = match archive_name.rsplit_once('/') {
+        Some((folder, _)) => format!("{folder}/"),
+        None => String::new(),
+    };
+    let mut budget = Budget::new(archive.len() as u64);
+    let members = match kind {
+        ArchiveKind::Zip => save_zip(transaction, store, bucket_id, &folder, &mut budget, archive).await?,
+        ArchiveKind::Tar => save_tar(transaction, store, bucket_id, &folder, &mut budget, &archive[..]).await?,
+        ArchiveKind::TarGz => save_tar(transaction, store, bucket_id, &folder, &mut budget, GzipDecoder::new(&archive[..])).await?,
+    };
+    debug!("Extracted {} entries of {} bytes from {archive_name}", members.len(), budget.extracted);
+    Ok(members)
+}
+
+async fn save_zip(transaction: &mut Transaction<'_, Postgres>, store: &Store, bucket_id: Uuid, folder: &str, budget: &mut Budget, archive: Bytes) -> Result<Vec<Uuid>, AppError> {
+    let zip = ZipFileReader::new(archive.into()).await.map_err(invalid_archive)?;
+    let mut members = Vec::new();
+    for index in 0..zip.file().entries().len() {
+        budget.entry()?;
+        let entry = &zip.file().entries()[index];
+        // Symbolic links keep their target as contents
+        let link = entry.unix_permissions().is_some_and(|mode| mode & 0o170000 == 0o120000);
+        if entry.dir().map_err(invalid_archive)? || link {
+            continue;
+        }
+        let path = member_path(folder, entry.filename().as_str().map_err(invalid_archive)?)?;
+
+        let mut reader = zip.reader_with_entry(index).await.map_err(invalid_archive)?;
+        let mut bytes = Vec::new();
+        (&mut reader).take(budget.member_limit() + 1).read_to_end(&mut bytes).await.map_err(invalid_archive)?;
+        budget.spend_member(&path, bytes.len() as u64)?;
+        if reader.compute_hash() != reader.entry().crc32() {
+            return Err(AppError::expected(StatusCode::BAD_REQUEST, format!("Entry {path} of the archive is corrupted")));
+        }
+        members.push(save_member(transaction, store, bucket_id, &path, bytes).await?);
+    }
+    Ok(members)
+}
+
+async fn save_tar<R: AsyncRead + Unpin>(transaction: &mut Transaction<'_, Postgres>, store: &Store, bucket_id: Uuid, folder: &str, budget: &mut Budget, reader: R) -> Result<Vec<Uuid>, AppError> {
+    let mut archive = Archive::new(reader);
+    let mut entries = archive.entries().map_err(invalid_archive)?;
+    let mut members = Vec::new();
+    while let Some(entry) = entries.next().await {
+        budget.entry()?;
+        let mut entry = entry.map_err(invalid_archive)?;
+        if !entry.header().entry_type().is_file() {
+            // Skipping still decompresses the data of the entry
+            budget.spend(entry.header().size().map_err(invalid_archive)?)?;
+            continue;
+        }
+        let path_bytes = entry.path_bytes();
+        let name = std::str::from_utf8(&path_bytes).map_err(invalid_archive)?;
+        let path = member_path(folder, name)?;
+
+        let mut bytes = Vec::new();
+        (&mut entry).take(budget.member_limit() + 1).read_to_end(&mut bytes).await.map_err(invalid_archive)?;
+        budget.spend_member(&path, bytes.len() as u64)?;
+        members.push(save_member(transaction, store, bucket_id, &path, bytes).await?);
+    }
+    Ok(members)
+}
+
+async fn save_member(transaction: &mut Transaction<'_, Postgres>, store: &Store, bucket_id: Uuid, path: &str, bytes: Vec<u8>) -> Result<Uuid, AppError> {
+    let (name, extension) = split_file_name(path);
+    save_file(transaction, store, bucket_id, &name, extension, Bytes::from(bytes)).await
+}
+
+/// Path of the entry holding a member, archives commonly prefix paths with `./`
+fn member_path(folder: &str, name: &str) -> Result<String, AppError> {
+    let name = name.trim_start_matches("./");
+    normalize_path(&format!("{folder}{name}"))
+        .map_err(|_| AppError::expected(StatusCode::BAD_REQUEST, format!("Invalid path {name:?} in archive")))
+}
+
+fn invalid_archive(e: impl std::fmt::Display) -> AppError {
+    AppError::expected(StatusCode::BAD_REQUEST, format!("Invalid archive: {e}"))
+}
@@ -1,64 +1,279 @@
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::ops::Range;
 use anyhow::anyhow;
-use axum::extract::{Multipart, Path, State};
+use axum::extract::{Multipart, Path, Query, State};
 use axum::{debug_handler, Json, Router};
 use axum::body::{Bytes, StreamBody};
-use axum::http::header::CONTENT_TYPE;
-use axum::http::{HeaderMap, StatusCode};
+use axum::http::header::{ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE, VARY};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
 use axum::response::IntoResponse;
 use axum::routing::{get, post};
-use serde::Serialize;
-use sha1::{Sha1, Digest};
-use sqlx::{PgPool, query};
-use tokio::{io};
-use tokio::fs::{File};
-use tokio_util::io::ReaderStream;
-use tracing::{debug, error};
+use bucket_storage_client::models::{FileEntry, MediaInfo, UploadKey, WebhookEvent};
+use sqlx::{PgPool, Postgres, query, Transaction};
+use sqlx::types::Json as JsonColumn;
+use tracing::{debug, error, info, warn};
+use serde::Deserialize;
+use time::OffsetDateTime;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 use crate::AppState;
 use crate::auth::Claims;
+use crate::buckets::{bucket_chunking, bucket_compression, bucket_data_key, bucket_strip_exif, bucket_trash_retention, bucket_versioning};
+use crate::{chunks, images, media, webhooks};
+use crate::lifecycle::set_expiry;
+use crate::metadata::{self, Metadata, set_metadata};
 use crate::errors::AppError;
+use crate::store::{checksum, Store, StoreFile};
+use crate::store::compression::Codec;
+use crate::versions::add_version;
+use self::extract::ArchiveKind;
+use self::paths::{normalize_path, normalize_prefix};
+
+pub mod extract;
+pub mod paths;
+
+/// Multipart form accepted by the upload routes, every field carrying a file name is stored
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct UploadForm {
+    #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct ListQuery {
+    /// Only entries whose path starts with this folder
+    prefix: Option<String>,
+    /// Only entries whose metadata holds exactly this `key:value` pair
+    tag: Option<String>,
+    /// Only entries whose media properties meet every condition like `kind=image,width>2000`
+    media: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct UploadQuery {
+    /// Seconds after which the uploaded files are deleted, on top of the lifecycle rules of the bucket
+    expires_in: Option<u64>,
+    /// Expands ZIP, tar and tar.gz files into one entry per member instead of storing them
+    #[serde(default)]
+    extract: bool,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct VersionQuery {
+    /// Version to read instead of the latest one
+    version: Option<Uuid>,
+}
 
 pub fn router() -> Router<AppState> {
     Router::new()
-        .route("/download/:file_id", get(download))
+        .route("/download/:file_id", get(download).head(head))
         .route("/upload/key", get(upload_url))
         .route("/upload", post(upload))
         .route("/upload/:upload_id", post(upload_with_key))
         .route("/delete/:file_id", get(delete))
+        .route("/files", get(list))
 }
 
-#[debug_handler]
-async fn download(claims: Claims, State(pool): State<PgPool>, Path(file_id): Path<Uuid>) -> Result<impl IntoResponse, AppError> {
-    debug!("Downloading {file_id} from bucket: {}", claims.bucket_id);
+#[utoipa::path(
+    get,
+    path = "/download/{file_id}",
+    tag = "files",
+    params(
+        ("file_id" = Uuid, Path, description = "Id of the file in the bucket"),
+        VersionQuery,
+        ("Range" = Option<String>, Header, description = "Single `bytes=start-end` range"),
+        ("Accept-Encoding" = Option<String>, Header, description = "Compressed files are sent as stored when the codec is accepted"),
+    ),
+    responses(
+        (status = 200, description = "File contents, `Content-Encoding` is set when sent compressed and metadata is sent as `X-Meta-<key>` headers, media properties as JSON in `X-Media`", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 206, description = "Requested byte range of the file", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 404, description = "File not found", body = ErrorResponse),
+        (status = 422, description = "Stored blob failed integrity verification", body = ErrorResponse),
+        (status = 416, description = "Range outside of the file", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+#[debug_handler(state = AppState)]
+async fn download(claims: Claims, State(pool): State<PgPool>, State(store): State<Store>, Path(entry_id): Path<Uuid>, Query(query): Query<VersionQuery>, headers: HeaderMap) -> Result<impl IntoResponse, AppError> {
+    debug!("Downloading {entry_id} from bucket: {}", claims.bucket_id);
     let res = query!(r#"
-    SELECT name, extension
+    SELECT files.id, name, extension, size, codec, stored_size, blob, chunked, corrupted_at, data_keys.wrapped_key AS "wrapped_key?",
+        bucket_files.metadata AS "metadata: JsonColumn<Metadata>", files.media AS "media: JsonColumn<MediaInfo>"
     FROM bucket_files
     JOIN files ON files.id = bucket_files.file_id
-    WHERE bucket_id = $1 AND file_id = $2
-    "#, claims.bucket_id, file_id).fetch_optional(&pool).await?.ok_or(AppError::expected(StatusCode::NO_CONTENT, "File not found"))?;
+    LEFT JOIN data_keys ON data_keys.id = files.data_key_id
+    WHERE bucket_id = $1 AND entry_id = $2 AND (bucket_files.id = $3 OR ($3::UUID IS NULL AND latest)) AND trashed_at IS NULL
+    "#, claims.bucket_id, entry_id, query.version).fetch_optional(&pool).await?.ok_or(AppError::expected(StatusCode::NOT_FOUND, "File not found"))?;
+    let file_id = res.id;
+
+    if res.corrupted_at.is_some() {
+        return Err(AppError::expected(StatusCode::UNPROCESSABLE_ENTITY, "File failed integrity verification and cannot be served"));
+    }
+
+    let size = res.size as u64;
+    let range = parse_range(&headers, size)?;
+    let codec: Codec = res.codec.parse().map_err(|e: String| anyhow!(e))?;
+    let file = StoreFile::new(file_id, res.extension.clone()).with_data_key(res.wrapped_key).with_codec(codec).with_blob(res.blob);
+    let pass_through = range.is_none() && codec != Codec::Identity && accepts_encoding(&headers, codec);
 
-    let file = Store::new().read(&StoreFile::new(file_id, res.extension.clone())).await?;
-    let stream = ReaderStream::new(file);
-    let body = StreamBody::new(stream);
     let mut headers = HeaderMap::new();
     if let Some(ext) = res.extension {
         if &ext == "png" || &ext == "jpg" {
             headers.append(CONTENT_TYPE, format!("image/{ext}").parse().unwrap());
         }
     }
+    headers.append(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    metadata::to_headers(&res.metadata.0, &mut headers);
+    media::to_headers(res.media.as_ref().map(|media| &media.0), &mut headers);
+    if codec != Codec::Identity {
+        headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+    }
+
+    if pass_through {
+        debug!("Sending {codec} encoded file as stored");
+        let stream = store.stream_encoded(&file).await?;
+        headers.append(CONTENT_ENCODING, HeaderValue::from_static(codec.as_str()));
+        headers.append(CONTENT_LENGTH, file.encoded_len(res.stored_size as u64)?.into());
+        return Ok((StatusCode::OK, headers, StreamBody::new(stream)));
+    }
+
+    let stream = match res.chunked {
+        true => chunks::stream(&pool, &store, file_id, range.clone()).await?,
+        false => store.stream(&file, range.clone()).await?,
+    };
+    let body = StreamBody::new(stream);
+    let status = match range {
+        Some(range) => {
+            headers.append(CONTENT_RANGE, format!("bytes {}-{}/{size}", range.start, range.end - 1).parse().unwrap());
+            headers.append(CONTENT_LENGTH, (range.end - range.start).into());
+            StatusCode::PARTIAL_CONTENT
+        }
+        None => {
+            headers.append(CONTENT_LENGTH, size.into());
+            StatusCode::OK
+        }
+    };
+
+    Ok((status, headers, body))
+}
+
+#[utoipa::path(
+    head,
+    path = "/download/{file_id}",
+    tag = "files",
+    params(
+        ("file_id" = Uuid, Path, description = "Id of the file in the bucket"),
+        VersionQuery,
+    ),
+    responses(
+        (status = 200, description = "`Content-Length` of the file, its metadata as `X-Meta-<key>` headers and media properties as JSON in `X-Media`"),
+        (status = 404, description = "File not found"),
+    ),
+    security(("basic" = [])),
+)]
+async fn head(claims: Claims, State(pool): State<PgPool>, Path(entry_id): Path<Uuid>, Query(query): Query<VersionQuery>) -> Result<impl IntoResponse, AppError> {
+    let res = query!(r#"
+    SELECT files.size, bucket_files.metadata AS "metadata: JsonColumn<Metadata>", files.media AS "media: JsonColumn<MediaInfo>"
+    FROM bucket_files
+    JOIN files ON files.id = bucket_files.file_id
+    WHERE bucket_id = $1 AND entry_id = $2 AND (bucket_files.id = $3 OR ($3::UUID IS NULL AND latest)) AND trashed_at IS NULL
+    "#, claims.bucket_id, entry_id, query.version).fetch_optional(&pool).await?.ok_or(AppError::expected(StatusCode::NOT_FOUND, "File not found"))?;
+
+    let mut headers = HeaderMap::new();
+    headers.append(CONTENT_LENGTH, res.size.into());
+    headers.append(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    metadata::to_headers(&res.metadata.0, &mut headers);
+    media::to_headers(res.media.as_ref().map(|media| &media.0), &mut headers);
+    Ok(headers)
+}
 
-    Ok((headers, body))
+/// Whether `Accept-Encoding` lists `codec` without a zero quality
+fn accepts_encoding(headers: &HeaderMap, codec: Codec) -> bool {
+    headers.get_all(ACCEPT_ENCODING).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut params = coding.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default();
+            let rejected = params.any(|param| param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0));
+            name.eq_ignore_ascii_case(codec.as_str()) && !rejected
+        })
 }
 
+/// Reads a single `bytes=` range of the `Range` header, other forms fall back to the whole file
+fn parse_range(headers: &HeaderMap, size: u64) -> Result<Option<Range<u64>>, AppError> {
+    let Some(value) = headers.get(RANGE).and_then(|value| value.to_str().ok()) else {
+        return Ok(None);
+    };
+    let Some((start, end)) = value.strip_prefix("bytes=").and_then(|spec| spec.split_once('-')) else {
+        return Ok(None);
+    };
+    if end.contains(',') {
+        return Ok(None);
+    }
 
+    let not_satisfiable = || AppError::expected(StatusCode::RANGE_NOT_SATISFIABLE, format!("Range not satisfiable for size {size}"));
+    let parse = |value: &str| value.trim().parse::<u64>().map_err(|_| not_satisfiable());
+    let range = match (start.trim(), end.trim()) {
+        ("", suffix) => size.saturating_sub(parse(suffix)?)..size,
+        (start, "") => parse(start)?..size,
+        (start, end) => parse(start)?..parse(end)?.saturating_add(1).min(size),
+    };
 
-#[derive(Serialize)]
-#[serde(rename_all="camelCase")]
-struct UploadKey {
-    upload_id: Uuid
+    if range.start >= range.end {
+        return Err(not_satisfiable());
+    }
+    Ok(Some(range))
+}
+
+#[utoipa::path(
+    get,
+    path = "/files",
+    tag = "files",
+    params(ListQuery),
+    responses(
+        (status = 200, description = "Entries of the bucket, recursively below the prefix", body = [FileEntry]),
+        (status = 400, description = "Invalid prefix, tag or media condition", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+async fn list(claims: Claims, State(pool): State<PgPool>, Query(query): Query<ListQuery>) -> Result<Json<Vec<FileEntry>>, AppError> {
+    let prefix = normalize_prefix(query.prefix.as_deref().unwrap_or_default())?;
+    let tag = query.tag.as_deref().map(metadata::parse_tag).transpose()?;
+    let media_filter = query.media.as_deref().map(media::parse_filter).transpose()?;
+    let entries = query!(r#"
+    SELECT bucket_files.entry_id, bucket_files.name, files.extension, files.size, files.checksum,
+        bucket_files.metadata AS "metadata: JsonColumn<Metadata>", files.media AS "media: JsonColumn<MediaInfo>"
+    FROM bucket_files
+    JOIN files ON files.id = bucket_files.file_id
+    WHERE bucket_id = $1 AND latest AND trashed_at IS NULL AND starts_with(bucket_files.name, $2)
+    AND ($3::JSONB IS NULL OR bucket_files.metadata @> $3)
+    AND ($4::TEXT IS NULL OR files.media @@ $4::TEXT::JSONPATH)
+    ORDER BY bucket_files.name
+    "#, claims.bucket_id, prefix, tag.map(JsonColumn) as _, media_filter).fetch_all(&pool).await?;
+
+    let entries = entries.into_iter().map(|rec| FileEntry {
+        id: rec.entry_id,
+        name: rec.name,
+        extension: rec.extension,
+        size: rec.size,
+        checksum: rec.checksum,
+        metadata: rec.metadata.0,
+        media: rec.media.map(|media| media.0),
+    }).collect();
+    Ok(Json(entries))
 }
 
+#[utoipa::path(
+    get,
+    path = "/upload/key",
+    tag = "files",
+    responses(
+        (status = 200, description = "Upload id usable without credentials", body = UploadKey),
+    ),
+    security(("basic" = [])),
+)]
 async fn upload_url(claims: Claims, State(pool): State<PgPool>) -> Result<Json<UploadKey>, AppError>{
     let upload_id = query!(r#"
     INSERT INTO upload_keys (bucket_id)
@@ -70,131 +285,203 @@ async fn upload_url(claims: Claims, State(pool): State<PgPool>) -> Result<Json<U
     Ok(Json(UploadKey {upload_id}))
 }
 
-#[debug_handler]
-async fn upload_with_key(State(pool): State<PgPool>, Path(upload_id): Path<Uuid>, multipart: Multipart) -> Result<Json<Vec<Uuid>>, AppError> {
+#[utoipa::path(
+    post,
+    path = "/upload/{upload_id}",
+    tag = "files",
+    params(("upload_id" = Uuid, Path, description = "Id issued by `/upload/key`"), UploadQuery),
+    request_body(content = UploadForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Ids of the stored files, one per member of extracted archives", body = [Uuid]),
+        (status = 400, description = "Wrong upload key, invalid archive or unsafe path inside it", body = ErrorResponse),
+        (status = 413, description = "Archive exceeds the extraction limits", body = ErrorResponse),
+    ),
+)]
+#[debug_handler(state = AppState)]
+async fn upload_with_key(State(pool): State<PgPool>, State(store): State<Store>, Path(upload_id): Path<Uuid>, Query(query): Query<UploadQuery>, headers: HeaderMap, multipart: Multipart) -> Result<Json<Vec<Uuid>>, AppError> {
     let bucket_id = query!(r#"
     SELECT bucket_id
     FROM upload_keys
     WHERE id = $1
     "#, upload_id).fetch_optional(&pool).await?.ok_or(AppError::expected(StatusCode::BAD_REQUEST, "Wrong upload key"))?.bucket_id;
 
-    let file_ids = save_multipart(&pool, multipart, bucket_id).await?;
+    let file_ids = save_multipart(&pool, &store, multipart, bucket_id, &query, metadata::from_headers(&headers)?).await?;
     debug!("Uploaded files with upload key");
     Ok(Json(file_ids))
 }
 
-#[debug_handler]
-async fn upload(claims: Claims, State(pool): State<PgPool>, multipart: Multipart) -> Result<Json<Vec<Uuid>>, AppError> {
+#[utoipa::path(
+    post,
+    path = "/upload",
+    tag = "files",
+    params(UploadQuery),
+    request_body(content = UploadForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Ids of the stored files, one per member of extracted archives", body = [Uuid]),
+        (status = 400, description = "Invalid archive or unsafe path inside it", body = ErrorResponse),
+        (status = 413, description = "Archive exceeds the extraction limits", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+#[debug_handler(state = AppState)]
+async fn upload(claims: Claims, State(pool): State<PgPool>, State(store): State<Store>, Query(query): Query<UploadQuery>, headers: HeaderMap, multipart: Multipart) -> Result<Json<Vec<Uuid>>, AppError> {
     debug!("Received multipart form");
-    let file_ids = save_multipart(&pool, multipart, claims.bucket_id).await?;
+    let file_ids = save_multipart(&pool, &store, multipart, claims.bucket_id, &query, metadata::from_headers(&headers)?).await?;
     Ok(Json(file_ids))
 }
 
-#[debug_handler]
-async fn delete(claims: Claims, State(pool): State<PgPool>, Path(file_id): Path<Uuid>) -> Result<(), AppError> {
+#[utoipa::path(
+    get,
+    path = "/delete/{file_id}",
+    tag = "files",
+    params(("file_id" = Uuid, Path, description = "Id of the file in the bucket")),
+    responses(
+        (status = 200, description = "File moved to the trash, or removed when the bucket keeps no trash"),
+        (status = 400, description = "File does not exist", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+#[debug_handler(state = AppState)]
+async fn delete(claims: Claims, State(pool): State<PgPool>, State(store): State<Store>, Path(file_id): Path<Uuid>) -> Result<(), AppError> {
     let mut transaction = pool.begin().await?;
-    let _rec = query!(r#"
-    SELECT *
-    FROM bucket_files
-    WHERE bucket_id = $1 AND file_id = $2
-    "#, claims.bucket_id, file_id).fetch_optional(&mut transaction).await?.ok_or(AppError::expected(StatusCode::BAD_REQUEST, "File does not exists"))?;
+    let released = delete_file(&mut transaction, claims.bucket_id, file_id).await?;
+    transaction.commit().await?;
+    remove_released(&store, released).await;
 
-    let rec = query!(r#"
-    SELECT COUNT(*)
-    FROM bucket_files
-    WHERE file_id = $1
-    "#, file_id).fetch_optional(&mut transaction).await?.ok_or(AppError::expected(StatusCode::BAD_REQUEST, "Trying to access non existing file reference"))?;
+    Ok(())
+}
 
-    let count = rec.count.ok_or(AppError::Unexpected(anyhow!("Could not count referenced files")))?;
-    debug!("File referenced by: {count}");
+/// Moves the entry to the trash, or removes every version right away when the bucket keeps no trash.
+/// Versioned buckets hide it behind a delete marker instead.
+/// Returns the blobs to pass to [`remove_released`] once the transaction committed.
+pub async fn delete_file(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid, entry_id: Uuid) -> Result<Vec<StoreFile>, AppError> {
+    remove_entry(transaction, bucket_id, entry_id, WebhookEvent::ObjectDeleted).await
+}
 
-    query!(r#"
-    DELETE FROM bucket_files
-    WHERE bucket_id = $1 AND file_id = $2
-    "#, claims.bucket_id, file_id).execute(&mut transaction).await?;
+/// [`delete_file`] for entries removed by lifecycle rules, reported to webhooks as expired instead of deleted
+pub async fn expire_file(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid, entry_id: Uuid) -> Result<Vec<StoreFile>, AppError> {
+    remove_entry(transaction, bucket_id, entry_id, WebhookEvent::ObjectExpired).await
+}
 
-    if count == 1 {
-        debug!("Deleting file permanently");
-        let extension = query!(r#"
-        DELETE FROM files
-        WHERE id = $1
-        RETURNING extension
-        "#, file_id).fetch_one(&mut transaction).await?.extension;
+async fn remove_entry(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid, entry_id: Uuid, event: WebhookEvent) -> Result<Vec<StoreFile>, AppError> {
+    let latest = query!(r#"
+    SELECT name, file_id, (SELECT extension FROM files WHERE files.id = bucket_files.file_id) AS extension
+    FROM bucket_files
+    WHERE bucket_id = $1 AND entry_id = $2 AND latest AND trashed_at IS NULL
+    FOR UPDATE
+    "#, bucket_id, entry_id).fetch_optional(&mut *transaction).await?
+        .filter(|rec| rec.file_id.is_some())
+        .ok_or(AppError::expected(StatusCode::BAD_REQUEST, "File does not exists"))?;
+    let data = serde_json::json!({ "entryId": entry_id, "name": latest.name, "extension": latest.extension });
+    webhooks::enqueue(transaction, bucket_id, event, data).await?;
+
+    if bucket_versioning(transaction, bucket_id).await? {
+        add_version(transaction, bucket_id, entry_id, &latest.name, None).await?;
+        debug!("Added delete marker to {entry_id}");
+        return Ok(Vec::new());
+    }
 
-        Store::new().remove(&StoreFile::new(file_id, extension)).await?;
+    if !bucket_trash_retention(transaction, bucket_id).await?.is_zero() {
+        query!(r#"
+        UPDATE bucket_files
+        SET trashed_at = now()
+        WHERE bucket_id = $1 AND entry_id = $2
+        "#, bucket_id, entry_id).execute(&mut *transaction).await?;
+        debug!("Moved {entry_id} to the trash");
+        return Ok(Vec::new());
     }
 
-    transaction.commit().await?;
+    let versions = query!(r#"
+    DELETE FROM bucket_files
+    WHERE bucket_id = $1 AND entry_id = $2
+    RETURNING file_id
+    "#, bucket_id, entry_id).fetch_all(&mut *transaction).await?;
 
-    Ok(())
+    release_files(transaction, versions.into_iter().filter_map(|rec| rec.file_id)).await
 }
 
-struct StoreFile {
-    id: Uuid,
-    extension: Option<String>,
+/// [`release_file`] for the files of removed versions, once per file
+pub async fn release_files(transaction: &mut Transaction<'_, Postgres>, file_ids: impl IntoIterator<Item = Uuid>) -> Result<Vec<StoreFile>, AppError> {
+    let mut released = Vec::new();
+    for file_id in file_ids.into_iter().collect::<HashSet<_>>() {
+        released.extend(release_file(transaction, file_id).await?);
+    }
+    Ok(released)
 }
 
-impl StoreFile {
-    fn new(id: Uuid, extension: Option<String>) -> Self {
-        Self {
-            id,
-            extension,
-        }
+/// Deletes the file with its chunks once no version in any bucket references it anymore.
+/// Returns the blobs nothing points at anymore, removing them has to wait for the commit
+/// so a rolled back transaction never leaves rows without their blob.
+pub async fn release_file(transaction: &mut Transaction<'_, Postgres>, file_id: Uuid) -> Result<Vec<StoreFile>, AppError> {
+    let rec = query!(r#"
+    SELECT EXISTS (SELECT 1 FROM bucket_files WHERE file_id = $1) AS "referenced!"
+    "#, file_id).fetch_one(&mut *transaction).await?;
+    if rec.referenced {
+        return Ok(Vec::new());
     }
 
-    fn path(&self) -> PathBuf {
-        let mut path = PathBuf::new();
-        path.push(self.id.to_string());
-        if let Some(ext) = &self.extension {
-            path.set_extension(ext);
+    debug!("Deleting file permanently");
+    let mut released = chunks::release(transaction, file_id).await?;
+    released.extend(images::release(transaction, file_id).await?);
+    let rec = query!(r#"
+    DELETE FROM files
+    WHERE id = $1
+    RETURNING extension, blob, chunked
+    "#, file_id).fetch_one(&mut *transaction).await?;
+
+    let file = StoreFile::new(file_id, rec.extension).with_blob(rec.blob);
+    let mut unshared = Vec::new();
+    for file in released.into_iter().chain((!rec.chunked).then_some(file)) {
+        if !blob_shared(transaction, &file).await? {
+            unshared.push(file);
         }
-        path
     }
+    Ok(unshared)
 }
 
-struct Store;
-const STORE_NAME: &str = "store";
-impl Store {
-    fn new() -> Self {
-        Self
-    }
-
-    async fn save(&self, file: &StoreFile, contents: Bytes) -> io::Result<()> {
-        let path = self.path(file.path());
-        tokio::fs::write(&path,contents).await?;
-        debug!("Saved file at: {path:?}");
-        Ok(())
-    }
-
-    async fn remove(&self, file: &StoreFile) -> io::Result<()> {
-        let path = self.path(file.path());
-        tokio::fs::remove_file(&path).await?;
-        debug!("Removed file at: {path:?}");
-        Ok(())
+/// Removes blobs released by a committed transaction.
+/// A failure only leaves an orphaned blob behind for `gc`, so it is logged instead of failing the request.
+pub async fn remove_released(store: &Store, released: Vec<StoreFile>) {
+    for file in released {
+        if let Err(e) = store.remove(&file).await {
+            warn!("Failed to remove released blob of {}: {e}", file.id);
+        }
     }
+}
 
-    async fn read(&self, file: &StoreFile) -> io::Result<File> {
-        let path = self.path(file.path());
-        let file = File::open(&path).await?;
-        debug!("Read file at: {path:?}");
-        Ok(file)
-    }
+/// Whether a `files` or `chunks` row other than `file` still points at its blob.
+/// Concurrent uploads of the same contents can end up at the same address.
+pub async fn blob_shared(transaction: &mut Transaction<'_, Postgres>, file: &StoreFile) -> Result<bool, AppError> {
+    let Some(blob) = &file.blob else {
+        return Ok(false);
+    };
+    let rec = query!(r#"
+    SELECT EXISTS (SELECT 1 FROM files WHERE blob = $1 AND id <> $2)
+        OR EXISTS (SELECT 1 FROM chunks WHERE blob = $1 AND id <> $2)
+        OR EXISTS (SELECT 1 FROM derivatives WHERE blob = $1 AND id <> $2) AS "shared!"
+    "#, blob, file.id).fetch_one(&mut *transaction).await?;
 
-    fn path(&self, other: PathBuf) -> PathBuf {
-        PathBuf::from(format!("./{}", STORE_NAME)).join(other)
-    }
+    Ok(rec.shared)
 }
 
-async fn save_multipart(pool: &PgPool, mut multipart: Multipart, bucket_id: Uuid) -> Result<Vec<Uuid>, AppError> {
+/// Stores every field carrying a file name, `x-meta-<key>` fields anywhere in the form add metadata to all of them.
+/// Archives are expanded into their members when the query asks for it.
+async fn save_multipart(pool: &PgPool, store: &Store, mut multipart: Multipart, bucket_id: Uuid, query: &UploadQuery, mut metadata: Metadata) -> Result<Vec<Uuid>, AppError> {
+    let expires_at = query.expires_in
+        .map(|secs| i64::try_from(secs).ok()
+            .and_then(|secs| OffsetDateTime::now_utc().checked_add(time::Duration::seconds(secs)))
+            .ok_or_else(|| AppError::expected(StatusCode::BAD_REQUEST, "Expiration is too far in the future")))
+        .transpose()?;
     let mut transaction = pool.begin().await?;
     let mut file_ids = Vec::new();
     while let Some(field) = multipart.next_field().await? {
-        let (name, extension) = if let Some(file_name) = field.file_name() {
-            let file_name_parts = file_name.rsplit_once('.');
-            match file_name_parts {
-                None => (file_name.to_string(), None),
-                Some((name,extension)) => (name.to_string(), Some(extension.to_string()))
-            }
+        let (path, (name, extension)) = if let Some(file_name) = field.file_name() {
+            let path = normalize_path(file_name)?;
+            let split = split_file_name(&path);
+            (path, split)
+        } else if let Some(key) = field.name().and_then(|name| name.to_ascii_lowercase().strip_prefix(metadata::META_PREFIX).map(str::to_string)) {
+            metadata.insert(metadata::normalize_key(&key)?, field.text().await?);
+            continue;
         } else {
             error!("Missing file name");
             continue;
@@ -203,41 +490,165 @@ async fn save_multipart(pool: &PgPool, mut multipart: Multipart, bucket_id: Uuid
         debug!("file name: {name}, extension: {extension:?}");
 
         let bytes = field.bytes().await?;
-        let mut hasher = Sha1::new();
-        hasher.update(bytes.clone());
-        let hash = hasher.finalize();
-        let checksum = format!("{hash:x}");
-
-        let file = query!(r#"
-        SELECT *
-        FROM files
-        WHERE checksum = $1
-        "#, checksum).fetch_optional(&mut transaction).await?;
-
-        if let Some(file) = file {
-            debug!("Matching file checksum");
-            query!(r#"
-            INSERT INTO bucket_files (name, bucket_id, file_id)
-            VALUES ($1, $2, $3)
-            "#, name, bucket_id, file.id).execute(&mut transaction).await?;
-            file_ids.push(file.id);
-            continue
+        let saved = match ArchiveKind::detect(&name, extension.as_deref()).filter(|_| query.extract) {
+            Some(kind) => extract::save_archive(&mut transaction, store, bucket_id, &path, kind, bytes).await?,
+            None => vec![save_file(&mut transaction, store, bucket_id, &name, extension, bytes).await?],
+        };
+        for file_id in saved {
+            if expires_at.is_some() {
+                set_expiry(&mut transaction, bucket_id, file_id, expires_at).await?;
+            }
+            file_ids.push(file_id);
         }
-
-        let file_id = query!(r#"
-        INSERT INTO files (extension, checksum)
-        VALUES ($1, $2)
-        RETURNING id
-        "#, extension, checksum).fetch_optional(&mut transaction).await?.ok_or(AppError::expected(StatusCode::NO_CONTENT, "File not found"))?.id;
-        Store::new().save(&StoreFile::new(file_id, extension), bytes).await?;
-
-        query!(r#"
-        INSERT INTO bucket_files (name, bucket_id, file_id)
-        VALUES ($1, $2, $3)
-        "#, name, bucket_id, file_id).execute(&mut transaction).await?;
-        file_ids.push(file_id);
     }
+    if !metadata.is_empty() {
+        for file_id in &file_ids {
+            set_metadata(&mut transaction, bucket_id, *file_id, &metadata).await?;
+        }
+    }
+    webhooks::check_quota(&mut transaction, bucket_id).await?;
     transaction.commit().await?;
     debug!("Saved files ids: {file_ids:#?}");
     Ok(file_ids)
 }
+
+/// Splits the extension off the last segment of the path, folders keep their dots
+pub fn split_file_name(file_name: &str) -> (String, Option<String>) {
+    match file_name.rsplit_once('.') {
+        Some((name, extension)) if !extension.contains('/') => (name.to_string(), Some(extension.to_string())),
+        _ => (file_name.to_string(), None),
+    }
+}
+
+/// Registers `bytes` as an entry of the bucket or a new version of it, reusing an already stored blob with the same checksum.
+/// With encryption enabled blobs are only shared between entries sealed by the same data key,
+/// which restricts deduplication to the bucket.
+/// Buckets with chunking enabled store the file as deduplicated chunks instead of one blob.
+/// New contents are inspected for their media properties, after removing image metadata in buckets stripping it.
+pub async fn save_file(transaction: &mut Transaction<'_, Postgres>, store: &Store, bucket_id: Uuid, name: &str, extension: Option<String>, bytes: Bytes) -> Result<Uuid, AppError> {
+    let bytes = match bucket_strip_exif(transaction, bucket_id).await? {
+        true => media::strip::strip_metadata(extension.as_deref(), bytes),
+        false => bytes,
+    };
+    let checksum = checksum(&bytes);
+    let data_key = bucket_data_key(transaction, store, bucket_id).await?;
+    let data_key_id = data_key.as_ref().map(|(id, _)| *id);
+
+    let file = query!(r#"
+    SELECT *
+    FROM files
+    WHERE checksum = $1 AND data_key_id IS NOT DISTINCT FROM $2
+    "#, checksum, data_key_id).fetch_optional(&mut *transaction).await?;
+
+    if let Some(file) = file {
+        debug!("Matching file checksum");
+        if file.corrupted_at.is_some() {
+            let codec = file.codec.parse().map_err(|e: String| anyhow!(e))?;
+            let stored = StoreFile::new(file.id, file.extension.clone())
+                .with_data_key(data_key.map(|(_, wrapped_key)| wrapped_key))
+                .with_codec(codec)
+                .with_blob(file.blob);
+            heal_file(transaction, store, stored, file.chunked, bytes).await?;
+        }
+        return add_created(transaction, bucket_id, name, extension.as_deref(), file.id, file.size, &checksum).await;
+    }
+
+    let size = bytes.len() as i64;
+    let codec = bucket_compression(transaction, bucket_id).await?.codec(extension.as_deref());
+    let chunked = bucket_chunking(transaction, bucket_id).await?;
+    let media = media::inspect(extension.as_deref(), bytes.clone()).await;
+    let file_id = query!(r#"
+    INSERT INTO files (extension, checksum, size, data_key_id, chunked, media)
+    VALUES ($1, $2, $3, $4, $5, $6)
+    RETURNING id
+    "#, extension, checksum, size, data_key_id, chunked, media.map(JsonColumn) as _).fetch_optional(&mut *transaction).await?.ok_or(AppError::expected(StatusCode::NO_CONTENT, "File not found"))?.id;
+
+    let (codec, stored_size, blob) = if chunked {
+        let stored_size = chunks::save_chunks(transaction, store, file_id, data_key, codec, bytes).await?;
+        (Codec::Identity, stored_size, None)
+    } else {
+        let file = StoreFile::new(file_id, extension.clone()).with_data_key(data_key.map(|(_, wrapped_key)| wrapped_key)).with_codec(codec);
+        let saved = store.save(&file, bytes).await?;
+        (saved.codec, saved.stored_size, Some(saved.blob))
+    };
+
+    query!(r#"
+    UPDATE files
+    SET codec = $1, stored_size = $2, blob = $3
+    WHERE id = $4
+    "#, codec.as_str(), stored_size as i64, blob, file_id).execute(&mut *transaction).await?;
+
+    add_created(transaction, bucket_id, name, extension.as_deref(), file_id, size, &checksum).await
+}
+
+/// Stores `bytes` again for a file the scrubber found corrupted and clears its corruption,
+/// they hash to the checksum of the file so they are the contents it lost
+async fn heal_file(transaction: &mut Transaction<'_, Postgres>, store: &Store, file: StoreFile, chunked: bool, bytes: Bytes) -> Result<(), AppError> {
+    if chunked {
+        chunks::restore_chunks(transaction, store, file.id, file.data_key, bytes).await?;
+    } else {
+        let saved = store.save(&file, bytes).await?;
+        query!(r#"
+        UPDATE files
+        SET codec = $1, stored_size = $2, blob = $3
+        WHERE id = $4
+        "#, saved.codec.as_str(), saved.stored_size as i64, saved.blob, file.id).execute(&mut *transaction).await?;
+        if file.blob.as_ref() != Some(&saved.blob) {
+            remove_stale(store, &file).await?;
+        }
+    }
+    query!(r#"
+    UPDATE files
+    SET corrupted_at = NULL, scrub_error = NULL, verified_at = now()
+    WHERE id = $1
+    "#, file.id).execute(&mut *transaction).await?;
+    info!("Healed corrupted file {} from an upload of its contents", file.id);
+    Ok(())
+}
+
+/// Removes a blob replaced by a healed copy, corrupted blobs may already be gone
+pub async fn remove_stale(store: &Store, file: &StoreFile) -> Result<(), AppError> {
+    match store.remove(file).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// [`add_to_bucket`] notifying the webhooks of the bucket of the new object
+async fn add_created(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid, name: &str, extension: Option<&str>, file_id: Uuid, size: i64, checksum: &str) -> Result<Uuid, AppError> {
+    let entry_id = add_to_bucket(transaction, bucket_id, name, extension, file_id).await?;
+    let data = serde_json::json!({ "entryId": entry_id, "name": name, "extension": extension, "size": size, "checksum": checksum });
+    webhooks::enqueue(transaction, bucket_id, WebhookEvent::ObjectCreated, data).await?;
+    Ok(entry_id)
+}
+
+/// Adds the file as the latest version of the entry with the same name and extension in versioned buckets,
+/// otherwise as a new entry, and returns the id of the entry
+pub async fn add_to_bucket(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid, name: &str, extension: Option<&str>, file_id: Uuid) -> Result<Uuid, AppError> {
+    let existing = match bucket_versioning(transaction, bucket_id).await? {
+        true => query!(r#"
+        SELECT bucket_files.entry_id
+        FROM bucket_files
+        JOIN files ON files.id = bucket_files.file_id
+        WHERE bucket_id = $1 AND name = $2 AND files.extension IS NOT DISTINCT FROM $3 AND trashed_at IS NULL
+        ORDER BY bucket_files.created_at DESC
+        LIMIT 1
+        "#, bucket_id, name, extension).fetch_optional(&mut *transaction).await?.map(|rec| rec.entry_id),
+        false => None,
+    };
+
+    // New entries take the id of their first file like entries stored before versioning,
+    // unless it already names another entry of the bucket holding the same contents
+    let entry_id = match existing {
+        Some(entry_id) => entry_id,
+        None => {
+            let rec = query!(r#"
+            SELECT EXISTS (SELECT 1 FROM bucket_files WHERE bucket_id = $1 AND entry_id = $2) AS "taken!"
+            "#, bucket_id, file_id).fetch_one(&mut *transaction).await?;
+            if rec.taken { Uuid::new_v4() } else { file_id }
+        }
+    };
+
+    add_version(transaction, bucket_id, entry_id, name, Some(file_id)).await?;
+    Ok(entry_id)
+}
@@ -0,0 +1,41 @@
+//! Entry keys are relative paths like `reports/2026/q3.csv`, folders only exist as prefixes of those keys.
+use axum::http::StatusCode;
+use crate::errors::AppError;
+
+/// Longest accepted key in bytes
+pub const MAX_PATH_LEN: usize = 1024;
+/// Longest accepted segment between two `/` in bytes
+pub const MAX_SEGMENT_LEN: usize = 255;
+
+/// Validates a relative path of an entry.
+/// Rejects absolute paths, empty, `.` and `..` segments, backslashes and control characters
+/// instead of rewriting them, so a key always reads back exactly as it was sent.
+pub fn normalize_path(path: &str) -> Result<String, AppError> {
+    if path.is_empty() || path.len() > MAX_PATH_LEN {
+        return Err(invalid(path));
+    }
+    for segment in path.split('/') {
+        let valid = !segment.is_empty()
+            && segment.len() <= MAX_SEGMENT_LEN
+            && segment != "."
+            && segment != ".."
+            && !segment.chars().any(|c| c == '\\' || c.is_control());
+        if !valid {
+            return Err(invalid(path));
+        }
+    }
+    Ok(path.to_string())
+}
+
+/// Validates a folder prefix and returns it ending with `/`, or empty for the root of the bucket
+pub fn normalize_prefix(prefix: &str) -> Result<String, AppError> {
+    let folder = prefix.strip_suffix('/').unwrap_or(prefix);
+    if folder.is_empty() {
+        return Ok(String::new());
+    }
+    Ok(format!("{}/", normalize_path(folder)?))
+}
+
+fn invalid(path: &str) -> AppError {
+    AppError::expected(StatusCode::BAD_REQUEST, format!("Invalid path {path:?}"))
+}
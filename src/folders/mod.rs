@@ -0,0 +1,218 @@
+//! Virtual folders over the paths of entries.
+//!
+//! A folder exists as long as an entry path starts with it. Listing groups the paths below a prefix
+//! at the next delimiter like S3 `CommonPrefixes`, moving and deleting apply to every entry below the prefix.
+use std::collections::HashSet;
+use axum::extract::{Query, State};
+use axum::{debug_handler, Json, Router};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use bucket_storage_client::models::{FileEntry, FolderListing, FolderMove, MediaInfo};
+use serde::Deserialize;
+use sqlx::{PgPool, query};
+use sqlx::types::Json as JsonColumn;
+use tracing::debug;
+use utoipa::IntoParams;
+use crate::AppState;
+use crate::auth::Claims;
+use crate::errors::AppError;
+use crate::files::{delete_file, remove_released};
+use crate::files::paths::{normalize_path, normalize_prefix};
+use crate::metadata::Metadata;
+use crate::store::Store;
+
+#[derive(Deserialize, IntoParams)]
+pub struct FolderQuery {
+    /// Folder to list, the root of the bucket when missing
+    prefix: Option<String>,
+    /// Groups paths below the prefix up to its first occurrence, `/` by default
+    delimiter: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct PrefixQuery {
+    /// Folder to delete with everything below it
+    prefix: String,
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/folders", get(list_folder).delete(delete_folder))
+        .route("/folders/move", post(move_folder))
+}
+
+#[utoipa::path(
+    get,
+    path = "/folders",
+    tag = "files",
+    params(FolderQuery),
+    responses(
+        (status = 200, description = "Entries and folders directly below the prefix", body = FolderListing),
+        (status = 400, description = "Invalid prefix or delimiter", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+async fn list_folder(claims: Claims, State(pool): State<PgPool>, Query(query): Query<FolderQuery>) -> Result<Json<FolderListing>, AppError> {
+    let prefix = normalize_prefix(query.prefix.as_deref().unwrap_or_default())?;
+    let delimiter = query.delimiter.unwrap_or_else(|| "/".to_string());
+    if delimiter.is_empty() {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, "Delimiter cannot be empty"));
+    }
+
+    let common_prefixes = query!(r#"
+    SELECT DISTINCT $2 || left(keys.rest, strpos(keys.rest, $3) + length($3) - 1) AS "prefix!"
+    FROM (
+        SELECT substr(bucket_files.name || COALESCE('.' || files.extension, ''), length($2) + 1) AS rest
+        FROM bucket_files
+        JOIN files ON files.id = bucket_files.file_id
+        WHERE bucket_id = $1 AND latest AND trashed_at IS NULL AND starts_with(bucket_files.name, $2)
+    ) keys
+    WHERE strpos(keys.rest, $3) > 0
+    ORDER BY 1
+    "#, claims.bucket_id, prefix, delimiter).fetch_all(&pool).await?;
+
+    let entries = query!(r#"
+    SELECT bucket_files.entry_id, bucket_files.name, files.extension, files.size, files.checksum,
+        bucket_files.metadata AS "metadata: JsonColumn<Metadata>", files.media AS "media: JsonColumn<MediaInfo>"
+    FROM bucket_files
+    JOIN files ON files.id = bucket_files.file_id
+    WHERE bucket_id = $1 AND latest AND trashed_at IS NULL AND starts_with(bucket_files.name, $2)
+    AND strpos(substr(bucket_files.name || COALESCE('.' || files.extension, ''), length($2) + 1), $3) = 0
+    ORDER BY bucket_files.name
+    "#, claims.bucket_id, prefix, delimiter).fetch_all(&pool).await?;
+
+    Ok(Json(FolderListing {
+        prefix,
+        common_prefixes: common_prefixes.into_iter().map(|rec| rec.prefix).collect(),
+        entries: entries.into_iter().map(|rec| FileEntry {
+            id: rec.entry_id,
+            name: rec.name,
+            extension: rec.extension,
+            size: rec.size,
+            checksum: rec.checksum,
+            metadata: rec.metadata.0,
+            media: rec.media.map(|media| media.0),
+        }).collect(),
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/folders/move",
+    tag = "files",
+    request_body = FolderMove,
+    responses(
+        (status = 200, description = "Number of entries moved, renaming a folder moves it within its parent", body = usize),
+        (status = 400, description = "Invalid folder", body = ErrorResponse),
+        (status = 404, description = "No entry below the folder", body = ErrorResponse),
+        (status = 409, description = "An entry already exists at one of the destination paths", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+async fn move_folder(claims: Claims, State(pool): State<PgPool>, Json(body): Json<FolderMove>) -> Result<Json<usize>, AppError> {
+    let from = normalize_prefix(&body.from)?;
+    let to = normalize_prefix(&body.to)?;
+    if from.is_empty() {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, "Cannot move the root of the bucket"));
+    }
+
+    let mut transaction = pool.begin().await?;
+    // Paths below the destination grow by the difference of the prefixes
+    let sources = query!(r#"
+    SELECT bucket_files.name, files.extension
+    FROM bucket_files
+    JOIN files ON files.id = bucket_files.file_id
+    WHERE bucket_id = $1 AND latest AND trashed_at IS NULL AND starts_with(name, $2)
+    "#, claims.bucket_id, from).fetch_all(&mut transaction).await?;
+    for rec in sources {
+        let name = format!("{to}{}", &rec.name[from.len()..]);
+        match rec.extension {
+            Some(ext) => normalize_path(&format!("{name}.{ext}"))?,
+            None => normalize_path(&name)?,
+        };
+    }
+
+    // Entries staying in place keep their paths, like single entries moved onto a taken path
+    let conflict = query!(r#"
+    SELECT target.name, files.extension
+    FROM bucket_files AS target
+    JOIN files ON files.id = target.file_id
+    WHERE target.bucket_id = $1 AND target.latest AND target.trashed_at IS NULL AND NOT starts_with(target.name, $2)
+    AND EXISTS (
+        SELECT 1
+        FROM bucket_files AS moved
+        JOIN files AS moved_files ON moved_files.id = moved.file_id
+        WHERE moved.bucket_id = $1 AND moved.latest AND moved.trashed_at IS NULL AND starts_with(moved.name, $2)
+        AND $3 || substr(moved.name, length($2) + 1) = target.name AND moved_files.extension IS NOT DISTINCT FROM files.extension
+    )
+    LIMIT 1
+    "#, claims.bucket_id, from, to).fetch_optional(&mut transaction).await?;
+    if let Some(rec) = conflict {
+        let path = match rec.extension {
+            Some(ext) => format!("{}.{ext}", rec.name),
+            None => rec.name,
+        };
+        return Err(AppError::expected(StatusCode::CONFLICT, format!("An entry already exists at {path:?}")));
+    }
+
+    // Every version moves along, restoring an older one keeps the entry in its new folder.
+    // Entries hidden behind a delete marker stay, restoring them must not land on a live entry.
+    let moved = query!(r#"
+    UPDATE bucket_files
+    SET name = $3 || substr(name, length($2) + 1)
+    WHERE bucket_id = $1 AND trashed_at IS NULL AND starts_with(name, $2)
+    AND entry_id IN (
+        SELECT entry_id
+        FROM bucket_files
+        WHERE bucket_id = $1 AND latest AND file_id IS NOT NULL AND trashed_at IS NULL AND starts_with(name, $2)
+    )
+    RETURNING entry_id
+    "#, claims.bucket_id, from, to).fetch_all(&mut transaction).await?;
+    transaction.commit().await?;
+
+    let entries = moved.into_iter().map(|rec| rec.entry_id).collect::<HashSet<_>>().len();
+    if entries == 0 {
+        return Err(AppError::expected(StatusCode::NOT_FOUND, "Folder not found"));
+    }
+    debug!("Moved {entries} entries from {from:?} to {to:?}");
+    Ok(Json(entries))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/folders",
+    tag = "files",
+    params(PrefixQuery),
+    responses(
+        (status = 200, description = "Number of entries deleted like single files", body = usize),
+        (status = 400, description = "Invalid folder", body = ErrorResponse),
+        (status = 404, description = "No entry below the folder", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+#[debug_handler(state = AppState)]
+async fn delete_folder(claims: Claims, State(pool): State<PgPool>, State(store): State<Store>, Query(query): Query<PrefixQuery>) -> Result<Json<usize>, AppError> {
+    let prefix = normalize_prefix(&query.prefix)?;
+    if prefix.is_empty() {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, "Cannot delete the root of the bucket"));
+    }
+
+    let mut transaction = pool.begin().await?;
+    let entries = query!(r#"
+    SELECT entry_id
+    FROM bucket_files
+    WHERE bucket_id = $1 AND latest AND file_id IS NOT NULL AND trashed_at IS NULL AND starts_with(name, $2)
+    "#, claims.bucket_id, prefix).fetch_all(&mut transaction).await?;
+
+    if entries.is_empty() {
+        return Err(AppError::expected(StatusCode::NOT_FOUND, "Folder not found"));
+    }
+    let mut released = Vec::new();
+    for rec in &entries {
+        released.extend(delete_file(&mut transaction, claims.bucket_id, rec.entry_id).await?);
+    }
+    transaction.commit().await?;
+    remove_released(&store, released).await;
+    debug!("Deleted {} entries below {prefix:?}", entries.len());
+    Ok(Json(entries.len()))
+}
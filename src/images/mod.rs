@@ -0,0 +1,230 @@
+//! Resized and converted versions of stored images.
+//!
+//! A derivative is generated on its first request and saved as a blob of its own in `derivatives`,
+//! tied to the source `files` row and sealed by its data key. Later requests for the same variant stream the saved blob.
+//! Requested dimensions are rounded up to one of [`SIZES`] and at most [`MAX_DERIVATIVES`] are saved per file,
+//! variants past that are generated on every request. Requests asking for no change stream the stored image itself.
+//! Derivatives go away with their source file, see [`release`].
+use std::io::Cursor;
+use anyhow::anyhow;
+use axum::extract::{Path, Query, State};
+use axum::{debug_handler, Router};
+use axum::body::{Bytes, StreamBody};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use bucket_storage_client::models::{ImageFit, ImageFormat, ImageQuery};
+use futures::TryStreamExt;
+use image::{DynamicImage, ImageReader, Limits};
+use image::imageops::FilterType;
+use sqlx::{PgPool, Postgres, query, Transaction};
+use tracing::debug;
+use uuid::Uuid;
+use crate::AppState;
+use crate::auth::Claims;
+use crate::chunks;
+use crate::errors::AppError;
+use crate::store::{ByteStream, Store, StoreFile};
+use crate::store::compression::Codec;
+
+/// Upper bound of the requested width and height
+pub const MAX_DIMENSION: u32 = 4096;
+/// Widths and heights derivatives are generated at, up to [`MAX_DIMENSION`]
+pub const SIZES: [u32; 16] = [16, 32, 48, 64, 96, 128, 192, 256, 384, 512, 768, 1024, 1536, 2048, 3072, MAX_DIMENSION];
+/// Upper bound of the derivatives saved for one file
+pub const MAX_DERIVATIVES: i64 = 32;
+/// Upper bound of the width and height of images decoded as a source
+pub const MAX_SOURCE_DIMENSION: u32 = 16384;
+/// Upper bound of the size of images decoded as a source
+pub const MAX_SOURCE_SIZE: i64 = 64 * 1024 * 1024;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/images/:file_id", get(image))
+}
+
+#[utoipa::path(
+    get,
+    path = "/images/{file_id}",
+    tag = "files",
+    params(
+        ("file_id" = Uuid, Path, description = "Id of the file in the bucket"),
+        ImageQuery,
+    ),
+    responses(
+        (status = 200, description = "The image transformed as asked", content_type = "image/*", body = Vec<u8>),
+        (status = 400, description = "Invalid dimensions or fit", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+        (status = 413, description = "Source image too large to process", body = ErrorResponse),
+        (status = 415, description = "File is not a PNG, JPEG or WebP image", body = ErrorResponse),
+        (status = 422, description = "Stored blob failed integrity verification", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+#[debug_handler(state = AppState)]
+async fn image(claims: Claims, State(pool): State<PgPool>, State(store): State<Store>, Path(entry_id): Path<Uuid>, Query(query): Query<ImageQuery>) -> Result<impl IntoResponse, AppError> {
+    let source = query!(r#"
+    SELECT files.id, extension, size, codec, blob, chunked, corrupted_at, data_keys.wrapped_key AS "wrapped_key?"
+    FROM bucket_files
+    JOIN files ON files.id = bucket_files.file_id
+    LEFT JOIN data_keys ON data_keys.id = files.data_key_id
+    WHERE bucket_id = $1 AND entry_id = $2 AND (bucket_files.id = $3 OR ($3::UUID IS NULL AND latest)) AND trashed_at IS NULL
+    "#, claims.bucket_id, entry_id, query.version).fetch_optional(&pool).await?.ok_or(AppError::expected(StatusCode::NOT_FOUND, "File not found"))?;
+
+    let source_format = source.extension.as_deref().and_then(ImageFormat::from_extension)
+        .ok_or(AppError::expected(StatusCode::UNSUPPORTED_MEDIA_TYPE, "File is not a PNG, JPEG or WebP image"))?;
+    if source.corrupted_at.is_some() {
+        return Err(AppError::expected(StatusCode::UNPROCESSABLE_ENTITY, "File failed integrity verification and cannot be served"));
+    }
+    let variant = Variant::new(&query, source_format)?;
+    let key = variant.key();
+    let content_type = variant.format.content_type();
+    let codec: Codec = source.codec.parse().map_err(|e: String| anyhow!(e))?;
+    let file = StoreFile::new(source.id, source.extension).with_data_key(source.wrapped_key.clone()).with_codec(codec).with_blob(source.blob);
+
+    let mut headers = HeaderMap::new();
+    headers.append(CONTENT_TYPE, content_type.parse().unwrap());
+    if variant.is_original(source_format) {
+        headers.append(CONTENT_LENGTH, (source.size as u64).into());
+        return Ok((headers, StreamBody::new(read_source(&pool, &store, &file, source.chunked).await?)));
+    }
+
+    let derivative = query!(r#"
+    SELECT id, size, blob
+    FROM derivatives
+    WHERE file_id = $1 AND variant = $2
+    "#, source.id, key).fetch_optional(&pool).await?;
+
+    if let Some(derivative) = derivative {
+        debug!("Serving derivative {key} of {}", source.id);
+        let file = StoreFile::new(derivative.id, Some(variant.format.extension().to_string())).with_data_key(source.wrapped_key).with_blob(Some(derivative.blob));
+        headers.append(CONTENT_LENGTH, (derivative.size as u64).into());
+        return Ok((headers, StreamBody::new(store.stream(&file, None).await?)));
+    }
+
+    if source.size > MAX_SOURCE_SIZE {
+        return Err(AppError::expected(StatusCode::PAYLOAD_TOO_LARGE, format!("Images over {MAX_SOURCE_SIZE} bytes cannot be processed")));
+    }
+    let contents = read_source(&pool, &store, &file, source.chunked).await?.try_fold(Vec::with_capacity(source.size as usize), |mut contents, bytes| async move {
+        contents.extend_from_slice(&bytes);
+        Ok(contents)
+    }).await?;
+
+    let bytes = tokio::task::spawn_blocking(move || variant.apply(&contents)).await.map_err(anyhow::Error::from)??;
+    headers.append(CONTENT_LENGTH, bytes.len().into());
+    // Requests deriving from the same file take turns, so the cap holds and each variant is saved once
+    let mut transaction = pool.begin().await?;
+    query!(r#"
+    SELECT id FROM files WHERE id = $1 FOR UPDATE
+    "#, source.id).fetch_optional(&mut transaction).await?.ok_or(AppError::expected(StatusCode::NOT_FOUND, "File not found"))?;
+    let saved = query!(r#"
+    SELECT COUNT(*) AS "count!", COUNT(*) FILTER (WHERE variant = $2) AS "variant!"
+    FROM derivatives
+    WHERE file_id = $1
+    "#, source.id, key).fetch_one(&mut transaction).await?;
+    if saved.count >= MAX_DERIVATIVES || saved.variant > 0 {
+        debug!("Generated derivative {key} of {} without saving it", source.id);
+        let body: ByteStream = Box::pin(futures::stream::once(async move { Ok(bytes) }));
+        return Ok((headers, StreamBody::new(body)));
+    }
+
+    let derivative_id = Uuid::new_v4();
+    let derived = StoreFile::new(derivative_id, Some(variant.format.extension().to_string())).with_data_key(source.wrapped_key);
+    let saved = store.save(&derived, bytes.clone()).await?;
+    query!(r#"
+    INSERT INTO derivatives (id, file_id, variant, format, size, blob)
+    VALUES ($1, $2, $3, $4, $5, $6)
+    "#, derivative_id, source.id, key, variant.format.extension(), bytes.len() as i64, saved.blob).execute(&mut transaction).await?;
+    transaction.commit().await?;
+    debug!("Generated derivative {key} of {}", source.id);
+
+    let body: ByteStream = Box::pin(futures::stream::once(async move { Ok(bytes) }));
+    Ok((headers, StreamBody::new(body)))
+}
+
+/// Validated transformation of a request
+#[derive(Clone, Copy)]
+struct Variant {
+    width: Option<u32>,
+    height: Option<u32>,
+    fit: ImageFit,
+    format: ImageFormat,
+}
+
+impl Variant {
+    fn new(query: &ImageQuery, source_format: ImageFormat) -> Result<Self, AppError> {
+        let fit = query.fit.unwrap_or_default();
+        for dimension in [query.width, query.height].into_iter().flatten() {
+            if !(1..=MAX_DIMENSION).contains(&dimension) {
+                return Err(AppError::expected(StatusCode::BAD_REQUEST, format!("Width and height must be between 1 and {MAX_DIMENSION}")));
+            }
+        }
+        if fit != ImageFit::Contain && (query.width.is_none() || query.height.is_none()) {
+            return Err(AppError::expected(StatusCode::BAD_REQUEST, "Cover and fill need both width and height"));
+        }
+        let snap = |dimension: u32| SIZES.into_iter().find(|size| *size >= dimension).unwrap_or(MAX_DIMENSION);
+        Ok(Self { width: query.width.map(snap), height: query.height.map(snap), fit, format: query.format.unwrap_or(source_format) })
+    }
+
+    /// Whether the variant leaves the source image as it is
+    fn is_original(&self, source_format: ImageFormat) -> bool {
+        self.width.is_none() && self.height.is_none() && self.format == source_format
+    }
+
+    /// Names the variant in `derivatives`, equal requests share the key
+    fn key(&self) -> String {
+        let dimension = |value: Option<u32>| value.map_or("auto".to_string(), |value| value.to_string());
+        format!("{}x{}-{:?}.{}", dimension(self.width), dimension(self.height), self.fit, self.format.extension()).to_lowercase()
+    }
+
+    fn apply(&self, source: &[u8]) -> Result<Bytes, AppError> {
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(MAX_SOURCE_DIMENSION);
+        limits.max_image_height = Some(MAX_SOURCE_DIMENSION);
+        let mut reader = ImageReader::new(Cursor::new(source)).with_guessed_format()?;
+        reader.limits(limits);
+        let image = reader.decode().map_err(|e| AppError::expected(StatusCode::UNPROCESSABLE_ENTITY, format!("Image cannot be decoded: {e}")))?;
+
+        let image = match (self.width, self.height, self.fit) {
+            (None, None, _) => image,
+            (Some(width), Some(height), ImageFit::Cover) => image.resize_to_fill(width, height, FilterType::Lanczos3),
+            (Some(width), Some(height), ImageFit::Fill) => image.resize_exact(width, height, FilterType::Lanczos3),
+            (width, height, _) => image.resize(width.unwrap_or(u32::MAX), height.unwrap_or(u32::MAX), FilterType::Lanczos3),
+        };
+        // JPEG has no alpha channel, the WebP encoder only takes 8 bit channels
+        let image = match self.format {
+            ImageFormat::Png => image,
+            ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8()),
+            ImageFormat::Webp => DynamicImage::ImageRgba8(image.to_rgba8()),
+        };
+        let format = match self.format {
+            ImageFormat::Png => image::ImageFormat::Png,
+            ImageFormat::Jpeg => image::ImageFormat::Jpeg,
+            ImageFormat::Webp => image::ImageFormat::WebP,
+        };
+        let mut encoded = Cursor::new(Vec::new());
+        image.write_to(&mut encoded, format).map_err(anyhow::Error::from)?;
+        Ok(Bytes::from(encoded.into_inner()))
+    }
+}
+
+async fn read_source(pool: &PgPool, store: &Store, file: &StoreFile, chunked: bool) -> Result<ByteStream, AppError> {
+    Ok(match chunked {
+        true => chunks::stream(pool, store, file.id, None).await?,
+        false => store.stream(file, None).await?,
+    })
+}
+
+/// Drops the derivatives of a file, to be called before its row is deleted.
+/// Their blobs are left to the caller like the ones of [`chunks::release`].
+pub async fn release(transaction: &mut Transaction<'_, Postgres>, file_id: Uuid) -> Result<Vec<StoreFile>, AppError> {
+    let removed = query!(r#"
+    DELETE FROM derivatives
+    WHERE file_id = $1
+    RETURNING id, format, blob
+    "#, file_id).fetch_all(&mut *transaction).await?;
+
+    debug!("Released {} derivatives of file {file_id}", removed.len());
+    Ok(removed.into_iter().map(|rec| StoreFile::new(rec.id, Some(rec.format)).with_blob(Some(rec.blob))).collect())
+}
@@ -0,0 +1,57 @@
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use rust_embed::{EmbeddedFile, RustEmbed};
+
+/// Output of `npm run build` in `interface/`, built before compiling with the `embed-interface` feature
+#[derive(RustEmbed)]
+#[folder = "interface/dist"]
+struct Assets;
+
+const INDEX: &str = "index.html";
+/// Vite puts content hashed bundles under `assets/`, so their contents never change for a given name
+const HASHED_PREFIX: &str = "assets/";
+
+/// Serves embedded files and falls back to `index.html` so client side routes survive a reload
+pub async fn serve(method: Method, uri: Uri, headers: HeaderMap) -> Response {
+    if method != Method::GET && method != Method::HEAD {
+        return StatusCode::METHOD_NOT_ALLOWED.into_response();
+    }
+
+    let path = uri.path().trim_start_matches('/');
+    let path = if path.is_empty() { INDEX } else { path };
+    match Assets::get(path) {
+        Some(file) => respond(path, file, &headers),
+        None if path.starts_with(HASHED_PREFIX) => (StatusCode::NOT_FOUND, "Not found").into_response(),
+        None => match Assets::get(INDEX) {
+            Some(index) => respond(INDEX, index, &headers),
+            None => (StatusCode::NOT_FOUND, "Not found").into_response(),
+        },
+    }
+}
+
+fn respond(path: &str, file: EmbeddedFile, headers: &HeaderMap) -> Response {
+    let etag = format!("\"{}\"", hex(&file.metadata.sha256_hash()));
+    let cache_control = if path.starts_with(HASHED_PREFIX) {
+        "public, max-age=31536000, immutable"
+    } else {
+        "no-cache"
+    };
+
+    let mut response = if headers.get(IF_NONE_MATCH).and_then(|value| value.to_str().ok()) == Some(etag.as_str()) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        ([(CONTENT_TYPE, file.metadata.mimetype().to_string())], file.data).into_response()
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static(cache_control));
+    if let Ok(etag) = HeaderValue::from_str(&etag) {
+        headers.insert(ETAG, etag);
+    }
+    response
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
@@ -1,19 +1,61 @@
 use std::env;
 use axum::Router;
 use sqlx::{migrate, PgPool};
+use sqlx::migrate::MigrateError;
 use axum::extract::{DefaultBodyLimit, FromRef};
 use axum::response::IntoResponse;
 use reqwest::StatusCode;
+use crate::store::Store;
+use crate::webhooks::WebhookConfig;
 
+pub mod admin;
+pub mod archive;
 pub mod auth;
+pub mod batch;
+pub mod buckets;
+pub mod chunks;
+pub mod entries;
 pub mod errors;
 pub mod files;
+pub mod folders;
+pub mod images;
+#[cfg(feature = "embed-interface")]
+pub mod interface;
+pub mod lifecycle;
+pub mod media;
+pub mod metadata;
+pub mod openapi;
+pub mod scrub;
+pub mod search;
+pub mod store;
+pub mod trash;
+pub mod versions;
+pub mod webhooks;
 
 pub fn app(app_state: AppState) -> Router {
-    Router::new()
+    let api = Router::new()
         .merge(auth::router())
         .merge(files::router())
-        .fallback(fallback)
+        .merge(folders::router())
+        .merge(entries::router())
+        .merge(metadata::router())
+        .merge(batch::router())
+        .merge(archive::router())
+        .merge(images::router())
+        .merge(search::router())
+        .merge(versions::router())
+        .merge(trash::router())
+        .merge(webhooks::router())
+        .merge(openapi::router())
+        .fallback(fallback);
+
+    let router = Router::new().nest("/api", api);
+    #[cfg(feature = "embed-interface")]
+    let router = router.fallback(interface::serve);
+    #[cfg(not(feature = "embed-interface"))]
+    let router = router.fallback(fallback);
+
+    router
         .layer(DefaultBodyLimit::disable())
         .with_state(app_state)
 }
@@ -24,23 +66,39 @@ async fn fallback() -> impl IntoResponse {
 
 #[derive(FromRef, Clone)]
 pub struct AppState {
-    pub pool: PgPool
+    pub pool: PgPool,
+    pub store: Store,
+    pub webhooks: WebhookConfig,
 }
 
 impl AppState {
     pub async fn new(environment: Environment) -> Self {
-        let pool = PgPool::connect(&env::var("DATABASE_URL").expect("DATABASE_URL var missing")).await.unwrap();
+        let app_state = Self::from_env().await;
         if environment == Environment::Production {
-            migrate!("./migrations").run(&pool).await.expect("Failed to migrate");
+            migrate(&app_state.pool).await.expect("Failed to migrate");
         }
-        Self { pool }
+        app_state
+    }
+
+    /// Connects to `DATABASE_URL` and opens the store without running migrations
+    pub async fn from_env() -> Self {
+        let pool = PgPool::connect(&env::var("DATABASE_URL").expect("DATABASE_URL var missing")).await.unwrap();
+        Self { pool, store: Store::from_env(), webhooks: WebhookConfig::from_env() }
+    }
+
+    pub async fn custom(pool: PgPool, store: Store) -> Self {
+        Self { pool, store, webhooks: WebhookConfig::default() }
     }
 
-    pub async fn custom(pool: PgPool) -> Self {
-        Self { pool }
+    pub fn with_webhooks(self, webhooks: WebhookConfig) -> Self {
+        Self { webhooks, ..self }
     }
 }
 
+pub async fn migrate(pool: &PgPool) -> Result<(), MigrateError> {
+    migrate!("./migrations").run(pool).await
+}
+
 #[derive(PartialEq)]
 pub enum Environment {
     Development,
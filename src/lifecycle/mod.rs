@@ -0,0 +1,185 @@
+//! Automatic expiry of bucket entries.
+//!
+//! An entry expires when its latest version is older than a rule of its bucket matching its name prefix and extension,
+//! or when the expiry set at upload passed. Expired entries go through [`expire_file`],
+//! so they land in the trash or behind a delete marker like entries deleted by hand.
+use std::env;
+use std::time::Duration;
+use axum::http::StatusCode;
+use serde::Serialize;
+use sqlx::{PgPool, Postgres, query, Transaction};
+use time::OffsetDateTime;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info};
+use uuid::Uuid;
+use crate::errors::AppError;
+use crate::files::{expire_file, remove_released};
+use crate::store::Store;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all="camelCase")]
+pub struct LifecycleRule {
+    pub id: Uuid,
+    pub bucket_id: Uuid,
+    /// Start of the entry name, any name when missing
+    pub prefix: Option<String>,
+    /// Exact extension, any extension when missing
+    pub extension: Option<String>,
+    pub expire_after_days: i32,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all="camelCase")]
+pub struct ExpiredEntry {
+    pub bucket_id: Uuid,
+    pub entry_id: Uuid,
+    pub name: String,
+    pub extension: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all="camelCase")]
+pub struct LifecycleReport {
+    /// Entries deleted, or that would be deleted on a dry run
+    pub expired: Vec<ExpiredEntry>,
+}
+
+pub async fn add_rule(pool: &PgPool, bucket_id: Uuid, prefix: Option<String>, extension: Option<String>, expire_after_days: i32) -> Result<Uuid, AppError> {
+    if expire_after_days <= 0 {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, "Rules expire entries after at least one day"));
+    }
+    let exists = query!(r#"
+    SELECT EXISTS (SELECT 1 FROM buckets WHERE id = $1) AS "exists!"
+    "#, bucket_id).fetch_one(pool).await?.exists;
+    if !exists {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, "Bucket does not exists"));
+    }
+
+    let rule_id = query!(r#"
+    INSERT INTO lifecycle_rules (bucket_id, prefix, extension, expire_after_days)
+    VALUES ($1, $2, $3, $4)
+    RETURNING id
+    "#, bucket_id, prefix, extension, expire_after_days).fetch_one(pool).await?.id;
+
+    debug!("Added lifecycle rule {rule_id} to bucket {bucket_id}");
+    Ok(rule_id)
+}
+
+pub async fn remove_rule(pool: &PgPool, rule_id: Uuid) -> Result<(), AppError> {
+    let res = query!(r#"
+    DELETE FROM lifecycle_rules
+    WHERE id = $1
+    "#, rule_id).execute(pool).await?;
+
+    if res.rows_affected() == 0 {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, "Rule does not exists"));
+    }
+    debug!("Removed lifecycle rule {rule_id}");
+    Ok(())
+}
+
+/// Rules of one bucket, or of every bucket when `bucket_id` is `None`
+pub async fn rules(pool: &PgPool, bucket_id: Option<Uuid>) -> Result<Vec<LifecycleRule>, AppError> {
+    let rules = query!(r#"
+    SELECT id, bucket_id, prefix, extension, expire_after_days
+    FROM lifecycle_rules
+    WHERE $1::UUID IS NULL OR bucket_id = $1
+    ORDER BY bucket_id, created_at
+    "#, bucket_id).fetch_all(pool).await?;
+
+    Ok(rules.into_iter().map(|rec| LifecycleRule {
+        id: rec.id,
+        bucket_id: rec.bucket_id,
+        prefix: rec.prefix,
+        extension: rec.extension,
+        expire_after_days: rec.expire_after_days,
+    }).collect())
+}
+
+/// Makes the entry expire at `expires_at` regardless of the rules of its bucket, `None` clears the expiry.
+/// Only the latest version carries it, so writing a new version keeps the entry.
+pub async fn set_expiry(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid, entry_id: Uuid, expires_at: Option<OffsetDateTime>) -> Result<(), AppError> {
+    query!(r#"
+    UPDATE bucket_files
+    SET expires_at = $1
+    WHERE bucket_id = $2 AND entry_id = $3 AND latest
+    "#, expires_at, bucket_id, entry_id).execute(&mut *transaction).await?;
+    Ok(())
+}
+
+/// Reads `LIFECYCLE_INTERVAL` in seconds, an hour by default
+pub fn lifecycle_interval() -> Duration {
+    let secs = env::var("LIFECYCLE_INTERVAL").map(|secs| secs.parse().expect("Failed to parse LIFECYCLE_INTERVAL")).unwrap_or(3600);
+    Duration::from_secs(secs)
+}
+
+/// Applies the lifecycle rules every `interval` until the process exits
+pub fn spawn(pool: PgPool, store: Store, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            match apply(&pool, &store, false).await {
+                Ok(report) => info!("Expired {} entries by lifecycle rules", report.expired.len()),
+                Err(e) => error!("Applying lifecycle rules failed: {e}"),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    })
+}
+
+/// Deletes every expired entry, one transaction per entry, or only reports them when `dry_run` is set
+pub async fn apply(pool: &PgPool, store: &Store, dry_run: bool) -> Result<LifecycleReport, AppError> {
+    let mut report = LifecycleReport::default();
+    let mut transaction = pool.begin().await?;
+    let expired = expired_entries(&mut transaction, None).await?;
+    transaction.commit().await?;
+
+    if dry_run {
+        report.expired = expired;
+        return Ok(report);
+    }
+
+    for entry in expired {
+        let mut transaction = pool.begin().await?;
+        // Checked again, the entry may have been deleted or written anew since
+        if expired_entries(&mut transaction, Some((entry.bucket_id, entry.entry_id))).await?.is_empty() {
+            continue;
+        }
+        let released = expire_file(&mut transaction, entry.bucket_id, entry.entry_id).await?;
+        transaction.commit().await?;
+        remove_released(store, released).await;
+        debug!("Expired {} of bucket {}", entry.entry_id, entry.bucket_id);
+        report.expired.push(entry);
+    }
+    Ok(report)
+}
+
+/// Latest versions past their expiry or matched by an expiring rule, limited to one entry when `entry` is set
+async fn expired_entries(transaction: &mut Transaction<'_, Postgres>, entry: Option<(Uuid, Uuid)>) -> Result<Vec<ExpiredEntry>, AppError> {
+    let (bucket_id, entry_id) = entry.unzip();
+    let expired = query!(r#"
+    SELECT bucket_files.bucket_id, bucket_files.entry_id, bucket_files.name, files.extension
+    FROM bucket_files
+    JOIN files ON files.id = bucket_files.file_id
+    WHERE latest AND trashed_at IS NULL
+    AND ($1::UUID IS NULL OR (bucket_files.bucket_id = $1 AND bucket_files.entry_id = $2))
+    AND (
+        bucket_files.expires_at < now()
+        OR EXISTS (
+            SELECT 1
+            FROM lifecycle_rules
+            WHERE lifecycle_rules.bucket_id = bucket_files.bucket_id
+            AND bucket_files.created_at < now() - make_interval(days => lifecycle_rules.expire_after_days)
+            AND (lifecycle_rules.prefix IS NULL OR starts_with(bucket_files.name, lifecycle_rules.prefix))
+            AND (lifecycle_rules.extension IS NULL OR files.extension = lifecycle_rules.extension)
+        )
+    )
+    ORDER BY bucket_files.bucket_id, bucket_files.created_at
+    "#, bucket_id, entry_id).fetch_all(&mut *transaction).await?;
+
+    Ok(expired.into_iter().map(|rec| ExpiredEntry {
+        bucket_id: rec.bucket_id,
+        entry_id: rec.entry_id,
+        name: rec.name,
+        extension: rec.extension,
+    }).collect())
+}
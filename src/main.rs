@@ -1,12 +1,11 @@
 use std::env;
 use std::net::{SocketAddr};
 use dotenv::dotenv;
-use tokio::fs;
-use tokio::fs::File;
 use tracing::info;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
-use bucket_storage::{app, AppState, Environment};
+use bucket_storage::{app, lifecycle, scrub, trash, webhooks, AppState, Environment};
+use bucket_storage::scrub::ScrubConfig;
 
 #[tokio::main]
 async fn main() {
@@ -25,14 +24,18 @@ async fn main() {
     let addr = match environment {
         Environment::Development => SocketAddr::from(([127, 0, 0, 1], 3001)),
         Environment::Production => {
-            tokio::fs::create_dir("./store").await.ok();
             let port = env::var("PORT").expect("PORT var missing").parse::<u16>().expect("Failed to parse PORT var");
             SocketAddr::from(([0, 0, 0, 0], port)) }
     };
 
-    setup().await;
     let app_state = AppState::new(environment).await;
-
+    app_state.store.setup().await.expect("Failed to set up bucket directory");
+    if let Some(config) = ScrubConfig::from_env() {
+        scrub::spawn(app_state.pool.clone(), app_state.store.clone(), config);
+    }
+    trash::spawn(app_state.pool.clone(), app_state.store.clone(), trash::purge_interval());
+    lifecycle::spawn(app_state.pool.clone(), app_state.store.clone(), lifecycle::lifecycle_interval());
+    webhooks::spawn(app_state.pool.clone(), app_state.webhooks, webhooks::delivery_interval());
 
     info!("listening on {}", addr);
     axum::Server::bind(&addr)
@@ -42,21 +45,3 @@ async fn main() {
         .await
         .expect("Failed to run axum server");
 }
-
-async fn setup() {
-    if let Ok(file) = File::open("./store").await {
-        if let Ok(meta) = file.metadata().await {
-            info!("Using existing bucket directory");
-            if !meta.is_dir() {
-                panic!("Occupied bucket directory");
-            }
-        }
-    } else {
-        fs::create_dir("./store").await.unwrap();
-        info!("Created bucket directory");
-        return;
-    }
-
-}
-
-
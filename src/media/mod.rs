@@ -0,0 +1,211 @@
+//! Properties of uploaded files read from their contents.
+//!
+//! Files are inspected once when their contents are first stored, the result is kept in `files.media`
+//! and shared by every entry deduplicated onto the file. Formats are chosen by extension and
+//! contents that fail to parse are stored without properties instead of failing the upload.
+//! Downloads return the properties as JSON in the `X-Media` header, listings can filter on them with [`parse_filter`].
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use axum::body::Bytes;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use bucket_storage_client::models::{MediaInfo, MediaKind};
+use image::ImageReader;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::TimeBase;
+use tracing::{debug, warn};
+use crate::errors::AppError;
+
+pub mod strip;
+
+/// Header carrying the properties of a file as JSON
+pub const MEDIA_HEADER: &str = "x-media";
+/// Files above this size are stored without inspecting them, inspection runs inside the upload transaction
+pub const MAX_INSPECTED_SIZE: usize = 64 * 1024 * 1024;
+/// Audio and video above this size without a declared duration are not walked packet by packet to find it
+const MAX_SCANNED_SIZE: usize = 16 * 1024 * 1024;
+/// Bytes of a text looked at to detect its encoding
+const TEXT_SAMPLE: usize = 64 * 1024;
+/// Upper bound of EXIF fields kept per image, they are sent back as one header
+const MAX_EXIF_FIELDS: usize = 32;
+const MAX_EXIF_VALUE_LEN: usize = 128;
+
+const TEXT_EXTENSIONS: [&str; 15] = ["txt", "md", "csv", "tsv", "json", "xml", "html", "htm", "log", "yaml", "yml", "toml", "ini", "srt", "vtt"];
+const AUDIO_EXTENSIONS: [&str; 7] = ["mp3", "wav", "flac", "ogg", "oga", "m4a", "aac"];
+const VIDEO_EXTENSIONS: [&str; 5] = ["mp4", "m4v", "mov", "mkv", "webm"];
+/// Fields of [`MediaInfo`] compared against numbers in filters
+const NUMBER_FIELDS: [&str; 4] = ["width", "height", "pages", "duration"];
+/// Fields of [`MediaInfo`] compared against words in filters
+const WORD_FIELDS: [&str; 3] = ["kind", "format", "encoding"];
+
+/// Reads the properties of `bytes` on a blocking thread, parsers failing or panicking on malformed contents give `None`
+pub async fn inspect(extension: Option<&str>, bytes: Bytes) -> Option<MediaInfo> {
+    let extension = extension?.to_ascii_lowercase();
+    if bytes.len() > MAX_INSPECTED_SIZE {
+        return None;
+    }
+    match tokio::task::spawn_blocking(move || inspect_blocking(&extension, bytes)).await {
+        Ok(media) => media,
+        Err(e) => {
+            warn!("Inspecting file contents failed: {e}");
+            None
+        }
+    }
+}
+
+fn inspect_blocking(extension: &str, bytes: Bytes) -> Option<MediaInfo> {
+    let media = match extension {
+        "png" | "jpg" | "jpeg" | "webp" => inspect_image(&bytes),
+        "pdf" => inspect_pdf(&bytes),
+        ext if TEXT_EXTENSIONS.contains(&ext) => Some(inspect_text(ext, &bytes)),
+        ext if AUDIO_EXTENSIONS.contains(&ext) => inspect_stream(ext, MediaKind::Audio, bytes),
+        ext if VIDEO_EXTENSIONS.contains(&ext) => inspect_stream(ext, MediaKind::Video, bytes),
+        _ => None,
+    };
+    debug!("Media of .{extension} file: {media:?}");
+    media
+}
+
+fn empty(kind: MediaKind, format: &str) -> MediaInfo {
+    MediaInfo { kind, format: format.to_string(), width: None, height: None, pages: None, encoding: None, duration: None, exif: BTreeMap::new() }
+}
+
+fn inspect_image(bytes: &[u8]) -> Option<MediaInfo> {
+    let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format().ok()?;
+    let format = match reader.format()? {
+        image::ImageFormat::Png => "png",
+        image::ImageFormat::Jpeg => "jpeg",
+        image::ImageFormat::WebP => "webp",
+        _ => return None,
+    };
+    let (width, height) = reader.into_dimensions().ok()?;
+    Some(MediaInfo { width: Some(width), height: Some(height), exif: read_exif(bytes), ..empty(MediaKind::Image, format) })
+}
+
+/// Named fields of the primary image, maker notes and values that cannot go into a header are left out
+fn read_exif(bytes: &[u8]) -> BTreeMap<String, String> {
+    let Ok(exif) = exif::Reader::new().read_from_container(&mut Cursor::new(bytes)) else {
+        return BTreeMap::new();
+    };
+    exif.fields()
+        .filter(|field| field.ifd_num == exif::In::PRIMARY && field.tag != exif::Tag::MakerNote && field.tag.description().is_some())
+        .filter_map(|field| {
+            let value = match &field.value {
+                exif::Value::Ascii(parts) => parts.iter().map(|part| String::from_utf8_lossy(part).trim().to_string()).collect::<Vec<_>>().join(", "),
+                _ => field.display_value().with_unit(&exif).to_string(),
+            };
+            let printable = value.bytes().all(|b| b == b' ' || b.is_ascii_graphic());
+            (printable && !value.is_empty() && value.len() <= MAX_EXIF_VALUE_LEN).then(|| (field.tag.to_string(), value))
+        })
+        .take(MAX_EXIF_FIELDS)
+        .collect()
+}
+
+fn inspect_pdf(bytes: &[u8]) -> Option<MediaInfo> {
+    let document = lopdf::Document::load_mem(bytes).ok()?;
+    Some(MediaInfo { pages: Some(document.get_pages().len() as u32), ..empty(MediaKind::Document, "pdf") })
+}
+
+fn inspect_text(extension: &str, bytes: &[u8]) -> MediaInfo {
+    let sample = &bytes[..bytes.len().min(TEXT_SAMPLE)];
+    // A character cut at the end of the sample still counts as UTF-8
+    let utf8 = std::str::from_utf8(sample).map_or_else(|e| e.error_len().is_none(), |_| true);
+    let encoding = match sample {
+        [0xEF, 0xBB, 0xBF, ..] => "utf-8".to_string(),
+        [0xFF, 0xFE, ..] => "utf-16le".to_string(),
+        [0xFE, 0xFF, ..] => "utf-16be".to_string(),
+        _ if utf8 => "utf-8".to_string(),
+        _ => {
+            let mut detector = chardetng::EncodingDetector::new();
+            detector.feed(sample, sample.len() == bytes.len());
+            detector.guess(None, true).name().to_ascii_lowercase()
+        }
+    };
+    MediaInfo { encoding: Some(encoding), ..empty(MediaKind::Text, extension) }
+}
+
+/// Duration of the longest track, counted from the packets when the container does not declare it
+fn inspect_stream(extension: &str, kind: MediaKind, bytes: Bytes) -> Option<MediaInfo> {
+    let scannable = bytes.len() <= MAX_SCANNED_SIZE;
+    let source = MediaSourceStream::new(Box::new(Cursor::new(bytes)), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(extension);
+    let mut format = symphonia::default::get_probe().format(&hint, source, &FormatOptions::default(), &MetadataOptions::default()).ok()?.format;
+
+    let declared = format.tracks().iter()
+        .filter_map(|track| seconds(track.codec_params.n_frames?, track.codec_params.time_base?))
+        .reduce(f64::max);
+    let duration = declared.or_else(|| {
+        if !scannable {
+            return None;
+        }
+        let track = format.default_track()?;
+        let (track_id, time_base) = (track.id, track.codec_params.time_base?);
+        let mut end = 0;
+        while let Ok(packet) = format.next_packet() {
+            if packet.track_id() == track_id {
+                end = end.max(packet.ts().saturating_add(packet.dur()));
+            }
+        }
+        seconds(end, time_base)
+    });
+    Some(MediaInfo { duration: duration.map(|secs| (secs * 1000.0).round() / 1000.0), ..empty(kind, extension) })
+}
+
+fn seconds(frames: u64, time_base: TimeBase) -> Option<f64> {
+    if time_base.numer == 0 || time_base.denom == 0 {
+        return None;
+    }
+    let time = time_base.calc_time(frames);
+    Some(time.seconds as f64 + time.frac)
+}
+
+/// Turns conditions like `kind=image,width>2000` into a JSON path predicate on `files.media` holding when all of them do.
+/// Numeric fields take `=`, `!=`, `<`, `<=`, `>` and `>=`, the other fields `=` and `!=` with a lowercase word.
+pub fn parse_filter(filter: &str) -> Result<String, AppError> {
+    let mut predicates = Vec::new();
+    for condition in filter.split(',') {
+        let invalid = || AppError::expected(StatusCode::BAD_REQUEST, format!("Invalid media condition {condition:?}"));
+        let (field, operator, value) = split_condition(condition).ok_or_else(invalid)?;
+        let operator = match operator {
+            "=" => "==",
+            "!=" | "<" | "<=" | ">" | ">=" => operator,
+            _ => return Err(invalid()),
+        };
+        let predicate = if NUMBER_FIELDS.contains(&field) {
+            let number = value.parse::<f64>().ok().filter(|number| number.is_finite()).ok_or_else(invalid)?;
+            format!("$.{field} {operator} {number}")
+        } else if WORD_FIELDS.contains(&field) && matches!(operator, "==" | "!=") {
+            let word = !value.is_empty() && value.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-');
+            if !word {
+                return Err(invalid());
+            }
+            format!("$.{field} {operator} \"{value}\"")
+        } else {
+            return Err(invalid());
+        };
+        predicates.push(predicate);
+    }
+    Ok(predicates.join(" && "))
+}
+
+/// Splits a condition at its operator into field, operator and value
+fn split_condition(condition: &str) -> Option<(&str, &str, &str)> {
+    let start = condition.find(['=', '!', '<', '>'])?;
+    let len = if condition[start + 1..].starts_with('=') { 2 } else { 1 };
+    let (field, rest) = condition.split_at(start);
+    let (operator, value) = rest.split_at(len);
+    Some((field.trim(), operator, value.trim()))
+}
+
+/// Appends the `X-Media` header when the file has properties
+pub fn to_headers(media: Option<&MediaInfo>, headers: &mut HeaderMap) {
+    let Some(value) = media.and_then(|media| serde_json::to_string(media).ok()) else {
+        return;
+    };
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.append(HeaderName::from_static(MEDIA_HEADER), value);
+    }
+}
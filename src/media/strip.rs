@@ -0,0 +1,109 @@
+//! Removing embedded metadata from images before they are stored.
+//!
+//! Only the containers are rewritten, pixel data is copied as it is. JPEG loses its APP1 (EXIF, XMP) and APP13 (IPTC) segments,
+//! PNG its `eXIf` and text chunks, WebP its `EXIF` and `XMP ` chunks.
+//! Orientation is part of EXIF, cameras relying on it show stripped photos unrotated.
+use axum::body::Bytes;
+use bucket_storage_client::models::ImageFormat;
+use tracing::debug;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+/// Flags of the `VP8X` chunk announcing EXIF and XMP chunks
+const WEBP_METADATA_FLAGS: u8 = 0x08 | 0x04;
+
+/// Strips images recognized by their extension, other files and images that fail to parse are returned unchanged
+pub fn strip_metadata(extension: Option<&str>, bytes: Bytes) -> Bytes {
+    let stripped = match extension.and_then(ImageFormat::from_extension) {
+        Some(ImageFormat::Jpeg) => strip_jpeg(&bytes),
+        Some(ImageFormat::Png) => strip_png(&bytes),
+        Some(ImageFormat::Webp) => strip_webp(&bytes),
+        None => None,
+    };
+    match stripped {
+        Some(stripped) => {
+            debug!("Stripped {} bytes of image metadata", bytes.len() - stripped.len());
+            Bytes::from(stripped)
+        }
+        None => bytes,
+    }
+}
+
+fn strip_jpeg(bytes: &[u8]) -> Option<Vec<u8>> {
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut stripped = Vec::with_capacity(bytes.len());
+    stripped.extend_from_slice(&bytes[..2]);
+    let mut pos = 2;
+    loop {
+        if *bytes.get(pos)? != 0xFF {
+            return None;
+        }
+        match *bytes.get(pos + 1)? {
+            // Fill byte before a marker
+            0xFF => pos += 1,
+            // Markers without a length
+            0x01 | 0xD0..=0xD7 => {
+                stripped.extend_from_slice(&bytes[pos..pos + 2]);
+                pos += 2;
+            }
+            // Entropy-coded data follows the start of scan, nothing after it is metadata
+            0xDA | 0xD9 => {
+                stripped.extend_from_slice(&bytes[pos..]);
+                return Some(stripped);
+            }
+            marker => {
+                let len = u16::from_be_bytes([*bytes.get(pos + 2)?, *bytes.get(pos + 3)?]) as usize;
+                let segment = bytes.get(pos..pos + 2 + len).filter(|_| len >= 2)?;
+                if marker != 0xE1 && marker != 0xED {
+                    stripped.extend_from_slice(segment);
+                }
+                pos += segment.len();
+            }
+        }
+    }
+}
+
+fn strip_png(bytes: &[u8]) -> Option<Vec<u8>> {
+    if !bytes.starts_with(&PNG_SIGNATURE) {
+        return None;
+    }
+    let mut stripped = PNG_SIGNATURE.to_vec();
+    let mut pos = PNG_SIGNATURE.len();
+    while pos < bytes.len() {
+        let len = u32::from_be_bytes(bytes.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        // Length, type, data and CRC
+        let chunk = bytes.get(pos..pos.checked_add(len)?.checked_add(12)?)?;
+        if !matches!(&chunk[4..8], b"eXIf" | b"tEXt" | b"iTXt" | b"zTXt") {
+            stripped.extend_from_slice(chunk);
+        }
+        pos += chunk.len();
+    }
+    Some(stripped)
+}
+
+fn strip_webp(bytes: &[u8]) -> Option<Vec<u8>> {
+    if bytes.get(0..4)? != b"RIFF" || bytes.get(8..12)? != b"WEBP" {
+        return None;
+    }
+    let mut stripped = bytes[..12].to_vec();
+    let mut pos = 12;
+    while pos < bytes.len() {
+        let len = u32::from_le_bytes(bytes.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        // Chunks are padded to an even length
+        let chunk = bytes.get(pos..pos.checked_add(len)?.checked_add(8 + len % 2)?)?;
+        match &chunk[..4] {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" if chunk.len() > 8 => {
+                stripped.extend_from_slice(chunk);
+                let flags = stripped.len() - chunk.len() + 8;
+                stripped[flags] &= !WEBP_METADATA_FLAGS;
+            }
+            _ => stripped.extend_from_slice(chunk),
+        }
+        pos += chunk.len();
+    }
+    let riff_len = u32::try_from(stripped.len() - 8).ok()?;
+    stripped[4..8].copy_from_slice(&riff_len.to_le_bytes());
+    Some(stripped)
+}
@@ -0,0 +1,148 @@
+//! User-defined metadata of entries.
+//!
+//! Every version carries its own string pairs, set at upload through `X-Meta-<key>` headers or multipart fields
+//! and changed later through `PATCH /files/:file_id/metadata`. Downloads return them as `X-Meta-<key>` headers.
+//! Keys are lowercase so they survive the round trip through case-insensitive header names.
+use std::collections::BTreeMap;
+use axum::extract::{Path, State};
+use axum::{Json, Router};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::routing::patch;
+use sqlx::{PgPool, Postgres, query, Transaction};
+use sqlx::types::Json as JsonColumn;
+use tracing::debug;
+use uuid::Uuid;
+use crate::AppState;
+use crate::auth::Claims;
+use crate::errors::AppError;
+
+pub type Metadata = BTreeMap<String, String>;
+
+/// Prefix of the headers and multipart fields carrying metadata
+pub const META_PREFIX: &str = "x-meta-";
+pub const MAX_KEYS: usize = 32;
+pub const MAX_KEY_LEN: usize = 128;
+pub const MAX_VALUE_LEN: usize = 1024;
+/// Upper bound of all keys and values together in bytes
+pub const MAX_TOTAL_LEN: usize = 8 * 1024;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/files/:file_id/metadata", patch(update_metadata))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/files/{file_id}/metadata",
+    tag = "files",
+    params(("file_id" = Uuid, Path, description = "Id of the file in the bucket")),
+    request_body(content = BTreeMap<String, Option<String>>, description = "Values to set, `null` removes the key"),
+    responses(
+        (status = 200, description = "Metadata of the latest version after the change", body = BTreeMap<String, String>),
+        (status = 400, description = "Invalid key or value, or limits exceeded", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+async fn update_metadata(claims: Claims, State(pool): State<PgPool>, Path(entry_id): Path<Uuid>, Json(changes): Json<BTreeMap<String, Option<String>>>) -> Result<Json<Metadata>, AppError> {
+    let changes = normalize_changes(changes)?;
+    let mut transaction = pool.begin().await?;
+    let metadata = change_metadata(&mut transaction, claims.bucket_id, entry_id, &changes).await?;
+    transaction.commit().await?;
+    debug!("Updated metadata of {entry_id}");
+    Ok(Json(metadata))
+}
+
+/// Normalizes the keys of changes, `None` values remove their key
+pub fn normalize_changes(changes: BTreeMap<String, Option<String>>) -> Result<BTreeMap<String, Option<String>>, AppError> {
+    changes.into_iter().map(|(key, value)| Ok((normalize_key(&key)?, value))).collect()
+}
+
+/// Applies normalized changes to the metadata of the latest version and returns the result
+pub async fn change_metadata(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid, entry_id: Uuid, changes: &BTreeMap<String, Option<String>>) -> Result<Metadata, AppError> {
+    let mut metadata = query!(r#"
+    SELECT metadata AS "metadata: JsonColumn<Metadata>"
+    FROM bucket_files
+    WHERE bucket_id = $1 AND entry_id = $2 AND latest AND file_id IS NOT NULL AND trashed_at IS NULL
+    FOR UPDATE
+    "#, bucket_id, entry_id).fetch_optional(&mut *transaction).await?.ok_or(AppError::expected(StatusCode::NOT_FOUND, "File not found"))?.metadata.0;
+
+    for (key, value) in changes {
+        match value {
+            Some(value) => metadata.insert(key.clone(), value.clone()),
+            None => metadata.remove(key),
+        };
+    }
+    set_metadata(transaction, bucket_id, entry_id, &metadata).await?;
+    Ok(metadata)
+}
+
+/// Replaces the metadata of the latest version after checking it against the limits
+pub async fn set_metadata(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid, entry_id: Uuid, metadata: &Metadata) -> Result<(), AppError> {
+    validate(metadata)?;
+    query!(r#"
+    UPDATE bucket_files
+    SET metadata = $1
+    WHERE bucket_id = $2 AND entry_id = $3 AND latest
+    "#, JsonColumn(metadata) as _, bucket_id, entry_id).execute(&mut *transaction).await?;
+    Ok(())
+}
+
+/// Lowercases the key and checks it only holds ASCII letters, digits, `-` and `_`
+pub fn normalize_key(key: &str) -> Result<String, AppError> {
+    let valid = !key.is_empty()
+        && key.len() <= MAX_KEY_LEN
+        && key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if !valid {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, format!("Invalid metadata key {key:?}")));
+    }
+    Ok(key.to_ascii_lowercase())
+}
+
+pub fn validate(metadata: &Metadata) -> Result<(), AppError> {
+    if metadata.len() > MAX_KEYS {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, format!("At most {MAX_KEYS} metadata keys are allowed")));
+    }
+    for (key, value) in metadata {
+        normalize_key(key)?;
+        // Values go back out as header values, which only take printable ASCII
+        if value.len() > MAX_VALUE_LEN || !value.bytes().all(|b| b == b' ' || b.is_ascii_graphic()) {
+            return Err(AppError::expected(StatusCode::BAD_REQUEST, format!("Invalid value of metadata key {key:?}")));
+        }
+    }
+    let total: usize = metadata.iter().map(|(key, value)| key.len() + value.len()).sum();
+    if total > MAX_TOTAL_LEN {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, format!("Metadata exceeds {MAX_TOTAL_LEN} bytes")));
+    }
+    Ok(())
+}
+
+/// Parses a `key:value` tag of a listing into the metadata entries have to contain
+pub fn parse_tag(tag: &str) -> Result<Metadata, AppError> {
+    let (key, value) = tag.split_once(':').ok_or(AppError::expected(StatusCode::BAD_REQUEST, "Tag must be `key:value`"))?;
+    Ok(Metadata::from([(normalize_key(key)?, value.to_string())]))
+}
+
+/// Collects the `X-Meta-<key>` headers of a request
+pub fn from_headers(headers: &HeaderMap) -> Result<Metadata, AppError> {
+    let mut metadata = Metadata::new();
+    for (name, value) in headers {
+        let Some(key) = name.as_str().strip_prefix(META_PREFIX) else {
+            continue;
+        };
+        let value = value.to_str().map_err(|_| AppError::expected(StatusCode::BAD_REQUEST, format!("Invalid value of metadata key {key:?}")))?;
+        metadata.insert(normalize_key(key)?, value.to_string());
+    }
+    Ok(metadata)
+}
+
+/// Appends one `X-Meta-<key>` header per pair
+pub fn to_headers(metadata: &Metadata, headers: &mut HeaderMap) {
+    for (key, value) in metadata {
+        let name = HeaderName::try_from(format!("{META_PREFIX}{key}"));
+        let value = HeaderValue::from_str(value);
+        if let (Ok(name), Ok(value)) = (name, value) {
+            headers.append(name, value);
+        }
+    }
+}
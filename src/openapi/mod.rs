@@ -0,0 +1,78 @@
+use axum::{Json, Router};
+use axum::routing::get;
+use bucket_storage_client::models::{ArchiveFormat, ArchiveRequest, BatchDelete, BatchFetch, BatchMetadata, BatchResult, DeliveryStatus, EntryDestination, ErrorResponse, FileEntry, FileVersion, FolderListing, FolderMove, ImageFit, ImageFormat, IssuedKey, MediaInfo, MediaKind, SearchHit, SearchResults, TrashEntry, UploadKey, Webhook, WebhookDelivery, WebhookEvent, WebhookPayload, WebhookRequest};
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use crate::{archive, auth, batch, entries, files, folders, images, metadata, search, trash, versions, webhooks, AppState};
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        auth::issue_key,
+        auth::verify_key,
+        files::download,
+        files::head,
+        files::list,
+        files::upload_url,
+        files::upload,
+        files::upload_with_key,
+        files::delete,
+        entries::copy,
+        entries::move_entry,
+        metadata::update_metadata,
+        batch::delete,
+        batch::fetch_metadata,
+        batch::update_metadata,
+        archive::archive,
+        images::image,
+        search::search,
+        folders::list_folder,
+        folders::move_folder,
+        folders::delete_folder,
+        versions::list_versions,
+        versions::restore_version,
+        versions::delete_version,
+        trash::list_trash,
+        trash::restore,
+        trash::purge_entry,
+        webhooks::create_webhook,
+        webhooks::list_webhooks,
+        webhooks::delete_webhook,
+        webhooks::list_deliveries,
+        webhooks::retry_delivery,
+    ),
+    components(schemas(ArchiveFormat, ArchiveRequest, BatchDelete, BatchFetch, BatchMetadata, BatchResult, EntryDestination, ErrorResponse, IssuedKey, UploadKey, FileEntry, FileVersion, FolderListing, FolderMove, ImageFit, ImageFormat, MediaInfo, MediaKind, SearchHit, SearchResults, TrashEntry, DeliveryStatus, Webhook, WebhookDelivery, WebhookEvent, WebhookPayload, WebhookRequest, files::UploadForm)),
+    modifiers(&BasicAuth),
+    servers((url = "/api")),
+    tags(
+        (name = "auth", description = "Buckets and their keys"),
+        (name = "files", description = "Storing and retrieving files"),
+        (name = "webhooks", description = "Notifications of bucket events"),
+    ),
+)]
+pub struct ApiDoc;
+
+/// Key id as the user name and the key as the password
+struct BasicAuth;
+
+impl Modify for BasicAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme("basic", SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)));
+        }
+    }
+}
+
+pub fn router() -> Router<AppState> {
+    let router = Router::new().route("/openapi.json", get(openapi));
+    #[cfg(feature = "redoc")]
+    let router = {
+        use utoipa_redoc::{Redoc, Servable};
+        router.merge(Redoc::with_url("/redoc", ApiDoc::openapi()))
+    };
+    router
+}
+
+async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
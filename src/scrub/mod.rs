@@ -0,0 +1,231 @@
+//! Background re-verification of stored blobs against `files.checksum`.
+//!
+//! Every pass walks the files least recently verified first and streams their plaintext through SHA-1
+//! at no more than the configured rate. Mismatching or missing blobs get `files.corrupted_at` set,
+//! which makes `download` refuse them until a later pass finds them intact again.
+//! Files that cannot be read for other reasons, like missing permissions, get `files.scrub_error`
+//! and the pass moves on, they are tried again on the next pass.
+//! Chunked files are verified by streaming their chunks in order.
+//! When blobs are mirrored, every replica of a file is checked by [`Store::repair`] first, which replaces corrupted ones.
+use std::env;
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+use anyhow::anyhow;
+use futures::TryStreamExt;
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use sqlx::{PgPool, query};
+use time::OffsetDateTime;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+use crate::{admin, chunks};
+use crate::errors::AppError;
+use crate::store::{ByteStream, Store, StoreFile};
+use crate::store::replication::Repair;
+
+/// Files fetched per query during a pass
+const BATCH: i64 = 100;
+
+#[derive(Debug, Clone)]
+pub struct ScrubConfig {
+    /// Upper bound of plaintext bytes hashed per second
+    pub bytes_per_second: u64,
+    /// Pause between the end of a pass and the start of the next one
+    pub interval: Duration,
+}
+
+impl ScrubConfig {
+    /// Reads `SCRUB_RATE` in bytes per second, 16 MiB by default, and `SCRUB_INTERVAL` in seconds, an hour by default.
+    /// Returns `None` when `SCRUB_RATE` is 0, which disables the scrubber.
+    pub fn from_env() -> Option<Self> {
+        let bytes_per_second = env::var("SCRUB_RATE").map(|rate| rate.parse().expect("Failed to parse SCRUB_RATE")).unwrap_or(16 * 1024 * 1024);
+        let interval = env::var("SCRUB_INTERVAL").map(|secs| secs.parse().expect("Failed to parse SCRUB_INTERVAL")).unwrap_or(3600);
+        (bytes_per_second > 0).then(|| Self { bytes_per_second, interval: Duration::from_secs(interval) })
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all="camelCase")]
+pub struct ScrubReport {
+    pub checked: usize,
+    pub bytes: u64,
+    pub corrupted: Vec<Uuid>,
+    pub repaired: Vec<Uuid>,
+    /// Files that could not be read, see `files.scrub_error`
+    pub failed: Vec<Uuid>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all="camelCase")]
+pub struct ScrubStatus {
+    pub files: i64,
+    pub unverified: i64,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub oldest_verification: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub latest_verification: Option<OffsetDateTime>,
+    pub corrupted: Vec<Uuid>,
+    pub failed: Vec<Uuid>,
+}
+
+/// Delays callers so that the consumed bytes never exceed the configured rate since creation
+struct RateLimiter {
+    bytes_per_second: u64,
+    started: Instant,
+    consumed: u64,
+}
+
+impl RateLimiter {
+    fn new(bytes_per_second: u64) -> Self {
+        Self { bytes_per_second, started: Instant::now(), consumed: 0 }
+    }
+
+    async fn consume(&mut self, bytes: u64) {
+        self.consumed += bytes;
+        let due = Duration::from_secs_f64(self.consumed as f64 / self.bytes_per_second as f64);
+        if let Some(wait) = due.checked_sub(self.started.elapsed()) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Runs a pass every `config.interval` until the process exits, followed by a replica repair when blobs are mirrored
+pub fn spawn(pool: PgPool, store: Store, config: ScrubConfig) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            match scrub(&pool, &store, config.bytes_per_second).await {
+                Ok(report) => info!("Scrubbed {} files, {} corrupted, {} unreadable", report.checked, report.corrupted.len(), report.failed.len()),
+                Err(e) => error!("Scrub pass failed: {e}"),
+            }
+            if store.replicas() > 1 {
+                match admin::repair(&pool, &store).await {
+                    Ok(report) => info!("Restored replicas of {} files, {} lost", report.restored.len(), report.lost.len()),
+                    Err(e) => error!("Replica repair failed: {e}"),
+                }
+            }
+            tokio::time::sleep(config.interval).await;
+        }
+    })
+}
+
+/// Verifies every file not verified since the pass started, at most `bytes_per_second` of plaintext per second
+pub async fn scrub(pool: &PgPool, store: &Store, bytes_per_second: u64) -> Result<ScrubReport, AppError> {
+    let mut report = ScrubReport::default();
+    let mut limiter = RateLimiter::new(bytes_per_second);
+    // Database time, the updates below stamp `verified_at` with it too
+    let started = query!(r#"
+    SELECT now() AS "now!"
+    "#).fetch_one(pool).await?.now;
+
+    loop {
+        let files = query!(r#"
+        SELECT files.id, files.extension, files.checksum, files.codec, files.blob, files.chunked, files.corrupted_at, data_keys.wrapped_key AS "wrapped_key?"
+        FROM files
+        LEFT JOIN data_keys ON data_keys.id = files.data_key_id
+        WHERE files.verified_at IS NULL OR files.verified_at < $1
+        ORDER BY files.verified_at NULLS FIRST, files.id
+        LIMIT $2
+        "#, started, BATCH).fetch_all(pool).await?;
+        if files.is_empty() {
+            break;
+        }
+
+        for rec in files {
+            let codec = rec.codec.parse().map_err(|e: String| anyhow!(e))?;
+            let file = StoreFile::new(rec.id, rec.extension).with_data_key(rec.wrapped_key).with_codec(codec).with_blob(rec.blob);
+            // Reads skip corrupted replicas, so every replica is checked here before the plaintext
+            let mirrored = store.replicas() > 1 && file.blob.is_some() && !rec.chunked;
+            if mirrored {
+                match store.repair(&file).await {
+                    Ok(Repair::Restored { copies, removed }) => info!("Repaired replicas of file {}: {copies} copies, {removed} removed", rec.id),
+                    Ok(_) => {}
+                    Err(e) => warn!("Replicas of file {} could not be repaired: {e}", rec.id),
+                }
+            }
+            let stream = match rec.chunked {
+                true => chunks::stream(pool, store, rec.id, None).await.map_err(|e| std::io::Error::other(e.to_string())),
+                false => store.stream(&file, None).await,
+            };
+            let checksum = verify(stream, &mut limiter, &mut report.bytes).await;
+            // `None` when reading failed without telling whether the contents are intact
+            let (intact, scrub_error) = match checksum {
+                Ok(checksum) if checksum == rec.checksum => (Some(true), None),
+                Ok(checksum) => {
+                    warn!("File {} checksum mismatch: expected {}, found {checksum}", rec.id, rec.checksum);
+                    (Some(false), None)
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::InvalidData) => {
+                    warn!("File {} failed verification: {e}", rec.id);
+                    (Some(false), None)
+                }
+                Err(e) => {
+                    error!("File {} could not be verified: {e}", rec.id);
+                    (None, Some(e.to_string()))
+                }
+            };
+
+            query!(r#"
+            UPDATE files
+            SET verified_at = now(), scrub_error = $2, corrupted_at = CASE
+                WHEN $1::BOOLEAN IS NULL THEN corrupted_at
+                WHEN $1 THEN NULL
+                ELSE COALESCE(corrupted_at, now())
+            END
+            WHERE id = $3
+            "#, intact, scrub_error, rec.id).execute(pool).await?;
+
+            report.checked += 1;
+            match (intact, rec.corrupted_at) {
+                (None, _) => report.failed.push(rec.id),
+                (Some(false), _) => report.corrupted.push(rec.id),
+                (Some(true), Some(_)) => report.repaired.push(rec.id),
+                (Some(true), None) => {}
+            }
+        }
+    }
+
+    debug!("Scrub pass finished: {report:?}");
+    Ok(report)
+}
+
+async fn verify(stream: std::io::Result<ByteStream>, limiter: &mut RateLimiter, bytes: &mut u64) -> std::io::Result<String> {
+    let mut stream = stream?;
+    let mut hasher = Sha1::new();
+    while let Some(chunk) = stream.try_next().await? {
+        limiter.consume(chunk.len() as u64).await;
+        *bytes += chunk.len() as u64;
+        hasher.update(&chunk);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub async fn status(pool: &PgPool) -> Result<ScrubStatus, AppError> {
+    let rec = query!(r#"
+    SELECT COUNT(*) AS "files!", COUNT(*) FILTER (WHERE verified_at IS NULL) AS "unverified!", MIN(verified_at) AS oldest_verification, MAX(verified_at) AS latest_verification
+    FROM files
+    "#).fetch_one(pool).await?;
+
+    let corrupted = query!(r#"
+    SELECT id
+    FROM files
+    WHERE corrupted_at IS NOT NULL
+    ORDER BY corrupted_at
+    "#).fetch_all(pool).await?;
+
+    let failed = query!(r#"
+    SELECT id
+    FROM files
+    WHERE scrub_error IS NOT NULL
+    ORDER BY verified_at
+    "#).fetch_all(pool).await?;
+
+    Ok(ScrubStatus {
+        files: rec.files,
+        unverified: rec.unverified,
+        oldest_verification: rec.oldest_verification,
+        latest_verification: rec.latest_verification,
+        corrupted: corrupted.into_iter().map(|rec| rec.id).collect(),
+        failed: failed.into_iter().map(|rec| rec.id).collect(),
+    })
+}
@@ -0,0 +1,92 @@
+//! Search over the live entries of a bucket.
+//!
+//! Text matches are case-insensitive substrings of the path, served by the trigram index on `bucket_files.name`
+//! and ranked by trigram similarity. The other conditions filter on the file and its latest version.
+use axum::extract::{Query, State};
+use axum::{Json, Router};
+use axum::http::StatusCode;
+use axum::routing::get;
+use bucket_storage_client::models::{FileEntry, MediaInfo, SearchHit, SearchQuery, SearchResults};
+use sqlx::{PgPool, query};
+use sqlx::types::Json as JsonColumn;
+use crate::AppState;
+use crate::auth::Claims;
+use crate::errors::AppError;
+use crate::metadata::{self, Metadata};
+
+pub const DEFAULT_LIMIT: i64 = 50;
+pub const MAX_LIMIT: i64 = 1000;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/search", get(search))
+}
+
+#[utoipa::path(
+    get,
+    path = "/search",
+    tag = "files",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "One page of matching entries, best matches first", body = SearchResults),
+        (status = 400, description = "Invalid tag or page", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+async fn search(claims: Claims, State(pool): State<PgPool>, Query(query): Query<SearchQuery>) -> Result<Json<SearchResults>, AppError> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    let offset = query.offset.unwrap_or(0);
+    if !(1..=MAX_LIMIT).contains(&limit) || offset < 0 {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, format!("Limit must be between 1 and {MAX_LIMIT} and offset positive")));
+    }
+    let tag = query.tag.as_deref().map(metadata::parse_tag).transpose()?;
+    let text = query.q.filter(|q| !q.is_empty());
+    let pattern = text.as_deref().map(|q| format!("%{}%", escape_like(q)));
+
+    let hits = query!(r#"
+    SELECT bucket_files.entry_id, bucket_files.name, files.extension, files.size, files.checksum, bucket_files.created_at,
+        bucket_files.metadata AS "metadata: JsonColumn<Metadata>", files.media AS "media: JsonColumn<MediaInfo>",
+        COALESCE(similarity(bucket_files.name, $2), 0) AS "score!",
+        COUNT(*) OVER () AS "total!"
+    FROM bucket_files
+    JOIN files ON files.id = bucket_files.file_id
+    WHERE bucket_id = $1 AND latest AND trashed_at IS NULL
+    AND ($3::TEXT IS NULL OR bucket_files.name ILIKE $3)
+    AND ($4::TEXT IS NULL OR files.extension = $4)
+    AND ($5::BIGINT IS NULL OR files.size >= $5)
+    AND ($6::BIGINT IS NULL OR files.size <= $6)
+    AND ($7::TIMESTAMPTZ IS NULL OR bucket_files.created_at >= $7)
+    AND ($8::TIMESTAMPTZ IS NULL OR bucket_files.created_at < $8)
+    AND ($9::JSONB IS NULL OR bucket_files.metadata @> $9)
+    ORDER BY COALESCE(similarity(bucket_files.name, $2), 0) DESC, bucket_files.created_at DESC, bucket_files.entry_id
+    LIMIT $10 OFFSET $11
+    "#,
+        claims.bucket_id, text, pattern, query.extension, query.min_size, query.max_size,
+        query.uploaded_after, query.uploaded_before, tag.map(JsonColumn) as _, limit, offset,
+    ).fetch_all(&pool).await?;
+
+    // Pages past the end have no row to carry the total
+    let total = match hits.first() {
+        Some(hit) => hit.total,
+        None => 0,
+    };
+    let hits = hits.into_iter().map(|rec| SearchHit {
+        entry: FileEntry {
+            id: rec.entry_id,
+            name: rec.name,
+            extension: rec.extension,
+            size: rec.size,
+            checksum: rec.checksum,
+            metadata: rec.metadata.0,
+            media: rec.media.map(|media| media.0),
+        },
+        score: rec.score,
+        uploaded_at: rec.created_at,
+    }).collect();
+    Ok(Json(SearchResults { total, hits }))
+}
+
+/// Makes `%`, `_` and `\` match themselves in a `LIKE` pattern
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
@@ -0,0 +1,56 @@
+//! Content-defined chunking with a gear rolling hash.
+//!
+//! Boundaries depend only on the bytes right before them, so an edit in the middle of a file
+//! changes the chunks around it while the ones before and after keep their contents.
+use axum::body::Bytes;
+
+/// No boundary is placed before a chunk reaches this size
+pub const MIN_SIZE: usize = 16 * 1024;
+/// Chunks are cut at this size when the hash found no boundary
+pub const MAX_SIZE: usize = 256 * 1024;
+/// 16 high bits of the hash must be zero, a boundary every 64 KiB on average after `MIN_SIZE`
+const MASK: u64 = 0xffff << 48;
+
+/// Pseudo random value per byte, generated with SplitMix64 so boundaries are stable across builds
+const GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Length of the chunk starting `data`
+fn boundary(data: &[u8]) -> usize {
+    if data.len() <= MIN_SIZE {
+        return data.len();
+    }
+    let end = data.len().min(MAX_SIZE);
+    let mut hash: u64 = 0;
+    for (i, &byte) in data[..end].iter().enumerate().skip(MIN_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        if hash & MASK == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// Splits `data` into chunks without copying it, empty data has no chunks
+pub fn split(data: &Bytes) -> Vec<Bytes> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let len = boundary(&data[start..]);
+        chunks.push(data.slice(start..start + len));
+        start += len;
+    }
+    chunks
+}
@@ -0,0 +1,143 @@
+//! Transparent compression of stored blobs.
+//!
+//! Blobs are compressed before they are encrypted, `files.codec` records how the stored bytes are encoded.
+//! Compressed blobs cannot be seeked, ranges are served by decompressing and skipping the bytes before them.
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::str::FromStr;
+use async_compression::tokio::bufread::ZstdDecoder;
+use futures::TryStreamExt;
+use tokio::io::AsyncReadExt;
+use tokio_util::io::{ReaderStream, StreamReader};
+use crate::store::ByteStream;
+
+const ZSTD_LEVEL: i32 = 3;
+
+/// Extensions of formats that are compressed already and gain nothing from another pass
+const PRECOMPRESSED: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "webp", "avif", "heic",
+    "mp3", "mp4", "m4a", "mkv", "mov", "webm", "ogg", "flac",
+    "zip", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar",
+    "pdf", "docx", "xlsx", "pptx", "odt", "jar", "woff", "woff2",
+];
+
+/// Extensions of text formats compressed under the `auto` policy
+const TEXTUAL: &[&str] = &[
+    "txt", "log", "json", "ndjson", "csv", "tsv", "xml", "html", "htm", "css", "js",
+    "md", "yaml", "yml", "toml", "ini", "sql", "svg",
+];
+
+/// Truncated or malformed streams are reported like blobs failing authentication
+fn corrupted(e: io::Error) -> io::Error {
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::Other => io::Error::new(io::ErrorKind::InvalidData, e),
+        _ => e,
+    }
+}
+
+/// Encoding of the bytes kept in the store, named after the matching `Content-Encoding`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    #[default]
+    Identity,
+    Zstd,
+}
+
+impl Codec {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Codec::Identity => "identity",
+            Codec::Zstd => "zstd",
+        }
+    }
+
+    /// Encodes `bytes`, `None` when the encoded form would not be smaller
+    pub fn encode(&self, bytes: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let encoded = match self {
+            Codec::Identity => return Ok(None),
+            Codec::Zstd => zstd::bulk::compress(bytes, ZSTD_LEVEL)?,
+        };
+        Ok(Some(encoded).filter(|encoded| encoded.len() < bytes.len()))
+    }
+
+    /// Decodes `stream` and keeps the decoded bytes of `range` only
+    pub async fn decode(&self, stream: ByteStream, range: Range<u64>) -> io::Result<ByteStream> {
+        let mut reader = match self {
+            Codec::Identity => return Ok(stream),
+            Codec::Zstd => ZstdDecoder::new(StreamReader::new(stream)),
+        };
+        tokio::io::copy(&mut (&mut reader).take(range.start), &mut tokio::io::sink()).await.map_err(corrupted)?;
+        let stream = ReaderStream::new(reader.take(range.end.saturating_sub(range.start)));
+        Ok(Box::pin(stream.map_err(corrupted)))
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "identity" => Ok(Codec::Identity),
+            "zstd" => Ok(Codec::Zstd),
+            other => Err(format!("Unknown codec {other}")),
+        }
+    }
+}
+
+/// Per bucket choice of which uploads get compressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Compression {
+    /// Compress text formats only
+    #[default]
+    Auto,
+    /// Compress everything except already compressed formats
+    Always,
+    Never,
+}
+
+impl Compression {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::Auto => "auto",
+            Compression::Always => "always",
+            Compression::Never => "never",
+        }
+    }
+
+    /// Codec for a new blob with the given file extension
+    pub fn codec(&self, extension: Option<&str>) -> Codec {
+        let extension = extension.map(str::to_ascii_lowercase);
+        let is = |list: &[&str]| extension.as_deref().is_some_and(|extension| list.contains(&extension));
+        match self {
+            Compression::Always if !is(PRECOMPRESSED) => Codec::Zstd,
+            Compression::Auto if is(TEXTUAL) => Codec::Zstd,
+            _ => Codec::Identity,
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Compression::Auto),
+            "always" => Ok(Compression::Always),
+            "never" => Ok(Compression::Never),
+            other => Err(format!("Unknown compression policy {other}")),
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Envelope encryption of stored blobs.
+//!
+//! Every bucket gets a random data key which is kept in `data_keys` wrapped by the master key.
+//! Blobs are split into fixed size chunks sealed independently with XChaCha20-Poly1305, so any byte
+//! range can be decrypted without reading the chunks before it.
+//! The nonce of a chunk is the random per-blob prefix followed by the chunk index and a flag marking
+//! the final chunk, which rejects reordered and truncated blobs.
+use std::io::{self, SeekFrom};
+use std::ops::Range;
+use axum::body::Bytes;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng, Payload};
+use chacha20poly1305::{AeadCore, Key, XChaCha20Poly1305, XNonce};
+use futures::stream::{self, Stream};
+use rand::RngCore;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+pub const KEY_LEN: usize = 32;
+pub const CHUNK_LEN: u64 = 64 * 1024;
+const MAGIC: &[u8; 4] = b"BSE1";
+const NONCE_PREFIX_LEN: usize = 15;
+const NONCE_LEN: usize = 24;
+const TAG_LEN: u64 = 16;
+const HEADER_LEN: u64 = (MAGIC.len() + NONCE_PREFIX_LEN) as u64;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Key encrypting the data keys, never stored in the database
+#[derive(Clone)]
+pub struct MasterKey(Key);
+
+impl MasterKey {
+    pub fn generate() -> Self {
+        Self(XChaCha20Poly1305::generate_key(&mut OsRng))
+    }
+
+    /// Accepts the base64 form produced by [`MasterKey::to_base64`]
+    pub fn from_base64(input: &str) -> Result<Self, String> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(input.trim())
+            .map_err(|e| format!("Master key is not valid base64: {e}"))?;
+        if bytes.len() != KEY_LEN {
+            return Err(format!("Master key must be {KEY_LEN} bytes long, got {}", bytes.len()));
+        }
+        Ok(Self(*Key::from_slice(&bytes)))
+    }
+
+    pub fn to_base64(&self) -> String {
+        base64::engine::general_purpose::STANDARD.encode(self.0)
+    }
+
+    /// Returns the nonce followed by the sealed data key
+    pub fn wrap(&self, key: &DataKey) -> Vec<u8> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = XChaCha20Poly1305::new(&self.0)
+            .encrypt(&nonce, key.0.as_slice())
+            .expect("Sealing a data key cannot fail");
+        [nonce.as_slice(), &sealed].concat()
+    }
+
+    pub fn unwrap(&self, wrapped: &[u8]) -> io::Result<DataKey> {
+        if wrapped.len() < NONCE_LEN {
+            return Err(invalid_data("Wrapped data key is too short"));
+        }
+        let (nonce, sealed) = wrapped.split_at(NONCE_LEN);
+        let key = XChaCha20Poly1305::new(&self.0)
+            .decrypt(XNonce::from_slice(nonce), sealed)
+            .map_err(|_| invalid_data("Data key was not wrapped by this master key"))?;
+        if key.len() != KEY_LEN {
+            return Err(invalid_data("Wrapped data key has a wrong length"));
+        }
+        Ok(DataKey(*Key::from_slice(&key)))
+    }
+}
+
+#[derive(Clone)]
+pub struct DataKey(Key);
+
+impl DataKey {
+    pub fn generate() -> Self {
+        Self(XChaCha20Poly1305::generate_key(&mut OsRng))
+    }
+
+    /// Seals `plaintext` into the chunked blob format, `aad` binds the blob to its owner
+    pub fn encrypt(&self, aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let cipher = XChaCha20Poly1305::new(&self.0);
+        let mut prefix = [0u8; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut prefix);
+
+        let chunks = chunk_count(plaintext.len() as u64);
+        let mut blob = Vec::with_capacity(encrypted_len(plaintext.len() as u64) as usize);
+        blob.extend_from_slice(MAGIC);
+        blob.extend_from_slice(&prefix);
+        for index in 0..chunks {
+            let start = (index * CHUNK_LEN) as usize;
+            let end = (start + CHUNK_LEN as usize).min(plaintext.len());
+            let nonce = chunk_nonce(&prefix, index, index + 1 == chunks);
+            let sealed = cipher
+                .encrypt(&nonce, Payload { msg: &plaintext[start..end], aad })
+                .expect("Sealing a chunk cannot fail");
+            blob.extend_from_slice(&sealed);
+        }
+        blob
+    }
+
+    /// Streams the plaintext bytes of `range` out of an encrypted blob of `physical_len` bytes
+    pub async fn decrypt_range(&self, aad: Vec<u8>, mut file: File, physical_len: u64, range: Range<u64>) -> io::Result<impl Stream<Item = io::Result<Bytes>>> {
+        let mut header = [0u8; HEADER_LEN as usize];
+        file.read_exact(&mut header).await?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(invalid_data("Blob is not encrypted"));
+        }
+        let mut prefix = [0u8; NONCE_PREFIX_LEN];
+        prefix.copy_from_slice(&header[MAGIC.len()..]);
+
+        let plaintext_len = plaintext_len(physical_len)?;
+        let chunks = chunk_count(plaintext_len);
+        let range = range.start.min(plaintext_len)..range.end.min(plaintext_len);
+        let first = range.start / CHUNK_LEN;
+        file.seek(SeekFrom::Start(HEADER_LEN + first * (CHUNK_LEN + TAG_LEN))).await?;
+
+        let cipher = XChaCha20Poly1305::new(&self.0);
+        let state = (file, first, range);
+        Ok(stream::try_unfold(state, move |(mut file, index, range)| {
+            let cipher = cipher.clone();
+            let aad = aad.clone();
+            async move {
+                if range.start >= range.end {
+                    return Ok(None);
+                }
+                let chunk_start = index * CHUNK_LEN;
+                let chunk_len = CHUNK_LEN.min(plaintext_len - chunk_start);
+                let mut sealed = vec![0u8; (chunk_len + TAG_LEN) as usize];
+                file.read_exact(&mut sealed).await?;
+                let nonce = chunk_nonce(&prefix, index, index + 1 == chunks);
+                let plaintext = cipher
+                    .decrypt(&nonce, Payload { msg: &sealed, aad: &aad })
+                    .map_err(|_| invalid_data("Blob failed authentication"))?;
+
+                let from = (range.start - chunk_start) as usize;
+                let to = (range.end - chunk_start).min(chunk_len) as usize;
+                let bytes = Bytes::copy_from_slice(&plaintext[from..to]);
+                let next = (chunk_start + to as u64)..range.end;
+                Ok(Some((bytes, (file, index + 1, next))))
+            }
+        }))
+    }
+}
+
+fn chunk_nonce(prefix: &[u8; NONCE_PREFIX_LEN], index: u64, last: bool) -> XNonce {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..NONCE_LEN - 1].copy_from_slice(&index.to_be_bytes());
+    nonce[NONCE_LEN - 1] = last as u8;
+    *XNonce::from_slice(&nonce)
+}
+
+/// An empty plaintext still produces one sealed chunk so truncation to the header is detected
+fn chunk_count(plaintext_len: u64) -> u64 {
+    plaintext_len.div_ceil(CHUNK_LEN).max(1)
+}
+
+pub fn encrypted_len(plaintext_len: u64) -> u64 {
+    HEADER_LEN + plaintext_len + chunk_count(plaintext_len) * TAG_LEN
+}
+
+pub fn plaintext_len(physical_len: u64) -> io::Result<u64> {
+    let sealed = physical_len.checked_sub(HEADER_LEN).ok_or(invalid_data("Encrypted blob is truncated"))?;
+    let chunks = sealed.div_ceil(CHUNK_LEN + TAG_LEN).max(1);
+    sealed.checked_sub(chunks * TAG_LEN).ok_or(invalid_data("Encrypted blob is truncated"))
+}
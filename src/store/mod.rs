@@ -0,0 +1,466 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::io::SeekFrom;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use axum::body::Bytes;
+use futures::{future, Stream, TryStreamExt};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use tokio::{fs, io};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+use tracing::{debug, info};
+use uuid::Uuid;
+use crate::store::compression::Codec;
+use crate::store::crypto::{DataKey, MasterKey};
+
+pub mod chunking;
+pub mod compression;
+pub mod crypto;
+pub mod replication;
+
+const STORE_NAME: &str = "store";
+/// Directory under the root receiving blobs while they are written, kept on the same filesystem so renames are atomic
+const TEMP_DIR: &str = "tmp";
+
+pub type ByteStream = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+
+/// Length of a blob address, the hex SHA-256 of the bytes on disk
+const BLOB_LEN: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoreFile {
+    pub id: Uuid,
+    pub extension: Option<String>,
+    /// Data key wrapped by the master key, `None` for plaintext blobs
+    pub data_key: Option<Vec<u8>>,
+    pub codec: Codec,
+    /// Address of the blob in the sharded layout, `None` for blobs still kept flat as `<id>.<extension>`
+    pub blob: Option<String>,
+}
+
+impl StoreFile {
+    pub fn new(id: Uuid, extension: Option<String>) -> Self {
+        Self {
+            id,
+            extension,
+            data_key: None,
+            codec: Codec::Identity,
+            blob: None,
+        }
+    }
+
+    pub fn with_data_key(mut self, data_key: Option<Vec<u8>>) -> Self {
+        self.data_key = data_key;
+        self
+    }
+
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    pub fn with_blob(mut self, blob: Option<String>) -> Self {
+        self.blob = blob;
+        self
+    }
+
+    /// Length of the encoded bytes, which is what [`Store::stream_encoded`] yields, for a blob of `physical_len` bytes
+    pub fn encoded_len(&self, physical_len: u64) -> io::Result<u64> {
+        match self.data_key {
+            Some(_) => crypto::plaintext_len(physical_len),
+            None => Ok(physical_len),
+        }
+    }
+
+    /// Location of the blob relative to the store root
+    pub fn path(&self) -> PathBuf {
+        match &self.blob {
+            Some(blob) => blob_path(blob),
+            None => self.legacy_path(),
+        }
+    }
+
+    fn legacy_path(&self) -> PathBuf {
+        let mut path = PathBuf::new();
+        path.push(self.id.to_string());
+        if let Some(ext) = &self.extension {
+            path.set_extension(ext);
+        }
+        path
+    }
+}
+
+/// Shards blobs by the first two byte pairs of their address, `abcd…` lives at `ab/cd/abcd…`
+fn blob_path(blob: &str) -> PathBuf {
+    [&blob[..2], &blob[2..4], blob].iter().collect()
+}
+
+fn is_blob_address(name: &str) -> bool {
+    name.len() == BLOB_LEN && name.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+fn is_legacy_name(name: &str) -> bool {
+    let id = name.split_once('.').map_or(name, |(id, _)| id);
+    Uuid::parse_str(id).is_ok()
+}
+
+/// Outcome of [`Store::save`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedBlob {
+    pub codec: Codec,
+    /// Bytes written to disk
+    pub stored_size: u64,
+    /// Address the blob was stored under
+    pub blob: String,
+}
+
+#[derive(Clone)]
+pub struct Store {
+    /// The first root is the primary one, blobs of the flat layout only ever live there
+    roots: Vec<PathBuf>,
+    replicas: usize,
+    master_key: Option<MasterKey>,
+}
+
+impl Store {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { roots: vec![root.into()], replicas: 1, master_key: None }
+    }
+
+    /// Spreads blobs over several roots, writing each one to `replicas` of them
+    pub fn with_roots(roots: Vec<PathBuf>, replicas: usize) -> Self {
+        assert!((1..=roots.len()).contains(&replicas), "Replica count must be between 1 and the number of roots");
+        Self { roots, replicas, master_key: None }
+    }
+
+    /// New blobs are encrypted once a master key is set
+    pub fn with_master_key(mut self, master_key: MasterKey) -> Self {
+        self.master_key = Some(master_key);
+        self
+    }
+
+    /// Uses the roots listed in `STORE_PATH`, separated like `PATH`, and `./store` otherwise.
+    /// `STORE_REPLICAS` sets how many roots receive each blob, two by default when there are several.
+    /// The master key is read from `MASTER_KEY` or from the file named by `MASTER_KEY_FILE`.
+    pub fn from_env() -> Self {
+        let roots = match env::var_os("STORE_PATH") {
+            Some(paths) => env::split_paths(&paths).collect(),
+            None => vec![PathBuf::from(format!("./{STORE_NAME}"))],
+        };
+        let replicas = env::var("STORE_REPLICAS").map(|n| n.parse().expect("Failed to parse STORE_REPLICAS")).unwrap_or(roots.len().min(2));
+        let store = Self::with_roots(roots, replicas);
+        let master_key = match (env::var("MASTER_KEY"), env::var("MASTER_KEY_FILE")) {
+            (Ok(key), _) => Some(key),
+            (_, Ok(path)) => Some(std::fs::read_to_string(path).expect("Failed to read MASTER_KEY_FILE")),
+            _ => None,
+        };
+        match master_key {
+            Some(key) => store.with_master_key(MasterKey::from_base64(&key).expect("Invalid master key")),
+            None => store,
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.master_key.is_some()
+    }
+
+    /// Number of roots every blob is written to
+    pub fn replicas(&self) -> usize {
+        self.replicas
+    }
+
+    fn master_key(&self) -> io::Result<&MasterKey> {
+        self.master_key.as_ref().ok_or(io::Error::other("Master key is not configured"))
+    }
+
+    /// Generates a data key and returns it wrapped by the master key
+    pub fn new_data_key(&self) -> io::Result<Vec<u8>> {
+        Ok(self.master_key()?.wrap(&DataKey::generate()))
+    }
+
+    /// Unwraps a data key with the configured master key and wraps it again with `new`
+    pub fn rewrap_data_key(&self, wrapped: &[u8], new: &MasterKey) -> io::Result<Vec<u8>> {
+        let key = self.master_key()?.unwrap(wrapped)?;
+        Ok(new.wrap(&key))
+    }
+
+    fn data_key(&self, file: &StoreFile) -> io::Result<Option<DataKey>> {
+        match &file.data_key {
+            Some(wrapped) => Ok(Some(self.master_key()?.unwrap(wrapped)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Creates the store directories when missing and sweeps temp files of interrupted writes
+    pub async fn setup(&self) -> io::Result<()> {
+        for root in &self.roots {
+            match fs::metadata(root).await {
+                Ok(meta) if meta.is_dir() => info!("Using existing bucket directory {root:?}"),
+                Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, "Occupied bucket directory")),
+                Err(_) => {
+                    fs::create_dir_all(root).await?;
+                    info!("Created bucket directory {root:?}");
+                }
+            }
+        }
+        self.sweep_temp().await?;
+        Ok(())
+    }
+
+    /// Removes temp files left behind by writes that never finished, returning how many were removed.
+    /// Only safe while no other process writes to the same store.
+    pub async fn sweep_temp(&self) -> io::Result<usize> {
+        let mut count = 0;
+        for root in &self.roots {
+            let mut entries = match fs::read_dir(root.join(TEMP_DIR)).await {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                entries => entries?,
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                fs::remove_file(entry.path()).await?;
+                count += 1;
+            }
+        }
+        if count > 0 {
+            info!("Removed {count} temp files of interrupted writes");
+        }
+        Ok(count)
+    }
+
+    /// Writes `contents` encoded with `file.codec`, then encrypted when the file has a data key,
+    /// to as many roots as there are replicas.
+    /// Contents that do not shrink are kept unencoded, the result tells which codec was applied.
+    pub async fn save(&self, file: &StoreFile, contents: Bytes) -> io::Result<SavedBlob> {
+        let (codec, contents) = match file.codec.encode(&contents)? {
+            Some(encoded) => (file.codec, Bytes::from(encoded)),
+            None => (Codec::Identity, contents),
+        };
+        let contents = match self.data_key(file)? {
+            Some(key) => Bytes::from(key.encrypt(file.id.as_bytes(), &contents)),
+            None => contents,
+        };
+        let blob = format!("{:x}", Sha256::digest(&contents));
+        let path = blob_path(&blob);
+        let roots = self.place(self.replicas, &[])?;
+        future::try_join_all(roots.iter().map(|&root| write_replica(&self.roots[root], &path, &contents))).await?;
+        debug!("Saved {codec} file at: {path:?} in {} roots", roots.len());
+        Ok(SavedBlob { codec, stored_size: contents.len() as u64, blob })
+    }
+
+    pub async fn remove(&self, file: &StoreFile) -> io::Result<()> {
+        self.remove_path(&file.path()).await
+    }
+
+    /// Removes every replica of the blob at `path`, relative to the roots as listed by [`Store::blobs`]
+    pub async fn remove_path(&self, path: &Path) -> io::Result<()> {
+        let mut removed = false;
+        for root in &self.roots {
+            match fs::remove_file(root.join(path)).await {
+                Ok(()) => removed = true,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if !removed {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("Blob {path:?} is not stored")));
+        }
+        debug!("Removed file at: {path:?}");
+        Ok(())
+    }
+
+    /// Hard links a blob kept in the flat layout to its sharded address in the primary root and returns the address.
+    /// The flat path stays in place, so readers that resolved it already keep working until it is removed.
+    /// Further replicas are left to [`Store::repair`].
+    pub async fn relocate(&self, file: &StoreFile) -> io::Result<String> {
+        let root = &self.roots[0];
+        let legacy = root.join(file.legacy_path());
+        let blob = hash_file(&legacy).await?;
+
+        let path = root.join(blob_path(&blob));
+        create_shard(root, &path).await?;
+        match fs::hard_link(&legacy, &path).await {
+            Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+            _ => {}
+        }
+        sync_dir(path.parent().expect("Blob paths are sharded")).await?;
+        debug!("Linked {legacy:?} to {path:?}");
+        Ok(blob)
+    }
+
+    /// Removes the flat copy of a blob after [`Store::relocate`]
+    pub async fn remove_legacy(&self, file: &StoreFile) -> io::Result<()> {
+        self.remove_path(&file.legacy_path()).await
+    }
+
+    /// Streams the plaintext of the blob, limited to `range` when given
+    pub async fn stream(&self, file: &StoreFile, range: Option<Range<u64>>) -> io::Result<ByteStream> {
+        if file.codec == Codec::Identity {
+            return self.read(file, range).await;
+        }
+        let stream = self.read(file, None).await?;
+        file.codec.decode(stream, range.unwrap_or(0..u64::MAX)).await
+    }
+
+    /// Streams the decrypted blob still encoded with `file.codec`
+    pub async fn stream_encoded(&self, file: &StoreFile) -> io::Result<ByteStream> {
+        self.read(file, None).await
+    }
+
+    async fn read(&self, file: &StoreFile, range: Option<Range<u64>>) -> io::Result<ByteStream> {
+        let mut reader = self.open(file, range.is_none()).await?;
+        let range = range.unwrap_or(0..u64::MAX);
+
+        if let Some(key) = self.data_key(file)? {
+            let physical_len = reader.metadata().await?.len();
+            let stream = key.decrypt_range(file.id.as_bytes().to_vec(), reader, physical_len, range).await?;
+            return Ok(Box::pin(stream));
+        }
+
+        reader.seek(SeekFrom::Start(range.start)).await?;
+        Ok(Box::pin(ReaderStream::new(reader.take(range.end.saturating_sub(range.start)))))
+    }
+
+    pub async fn exists(&self, file: &StoreFile) -> io::Result<bool> {
+        for root in &self.roots {
+            if fs::try_exists(root.join(file.path())).await? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Streams the stored plaintext through SHA-1, the same digest used for `files.checksum`
+    pub async fn checksum(&self, file: &StoreFile) -> io::Result<String> {
+        checksum_stream(self.stream(file, None).await?).await
+    }
+
+    /// Lists every blob currently present in any root, both flat and sharded, as paths relative to the roots
+    pub async fn blobs(&self) -> io::Result<Vec<PathBuf>> {
+        let mut blobs = BTreeSet::new();
+        for root in &self.roots {
+            let mut entries = match fs::read_dir(root).await {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                entries => entries?,
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                    continue;
+                };
+                let file_type = entry.file_type().await?;
+                if file_type.is_file() && is_legacy_name(&name) {
+                    blobs.insert(PathBuf::from(name));
+                } else if file_type.is_dir() && name.len() == 2 {
+                    sharded_blobs(root, PathBuf::from(name), &mut blobs).await?;
+                }
+            }
+        }
+        Ok(blobs.into_iter().collect())
+    }
+}
+
+async fn sharded_blobs(root: &Path, shard: PathBuf, blobs: &mut BTreeSet<PathBuf>) -> io::Result<()> {
+    let mut shards = fs::read_dir(root.join(&shard)).await?;
+    while let Some(inner) = shards.next_entry().await? {
+        if !inner.file_type().await?.is_dir() {
+            continue;
+        }
+        let mut entries = fs::read_dir(inner.path()).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            if entry.file_type().await?.is_file() && name.to_str().is_some_and(is_blob_address) {
+                blobs.insert(shard.join(inner.file_name()).join(name));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes `contents` to `path` under `root` through [`write_atomic`], creating the shard directories first
+async fn write_replica(root: &Path, path: &Path, contents: &[u8]) -> io::Result<()> {
+    let path = root.join(path);
+    create_shard(root, &path).await?;
+    write_atomic(root, &path, contents).await
+}
+
+/// Writes `contents` to a temp file of `root`, fsyncs it and renames it to `path`, then fsyncs the directory,
+/// so `path` either holds the complete contents or does not exist after a crash
+async fn write_atomic(root: &Path, path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_atomic_with(root, path, contents, |file| file).await
+}
+
+/// [`write_atomic`] writing the temp file through the writer `wrap` makes of it,
+/// tests use it to fail or stall a write at a known offset
+pub async fn write_atomic_with<W>(root: &Path, path: &Path, contents: &[u8], wrap: impl FnOnce(File) -> W) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let temp_dir = root.join(TEMP_DIR);
+    fs::create_dir_all(&temp_dir).await?;
+    let temp = temp_dir.join(Uuid::new_v4().to_string());
+
+    let res = async {
+        let file = File::create(&temp).await?;
+        let synced = file.try_clone().await?;
+        let mut writer = wrap(file);
+        writer.write_all(contents).await?;
+        writer.flush().await?;
+        synced.sync_all().await?;
+        fs::rename(&temp, path).await
+    }.await;
+    if let Err(e) = res {
+        let _ = fs::remove_file(&temp).await;
+        return Err(e);
+    }
+    sync_dir(path.parent().expect("Blob paths have a parent")).await
+}
+
+/// Creates the shard directories of `path` and persists their directory entries
+async fn create_shard(root: &Path, path: &Path) -> io::Result<()> {
+    let shard = path.parent().expect("Blob paths are sharded");
+    if fs::try_exists(shard).await? {
+        return Ok(());
+    }
+    fs::create_dir_all(shard).await?;
+    let mut dir = shard;
+    while let Some(parent) = dir.parent() {
+        sync_dir(parent).await?;
+        if parent == root {
+            break;
+        }
+        dir = parent;
+    }
+    Ok(())
+}
+
+/// Hex SHA-256 of the file at `path`, the address of a sharded blob
+async fn hash_file(path: &Path) -> io::Result<String> {
+    let mut reader = ReaderStream::new(File::open(path).await?);
+    let mut hasher = Sha256::new();
+    while let Some(chunk) = reader.try_next().await? {
+        hasher.update(&chunk);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+async fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir).await?.sync_all().await
+}
+
+/// SHA-1 of everything `stream` yields, see [`Store::checksum`]
+pub async fn checksum_stream(mut stream: ByteStream) -> io::Result<String> {
+    let mut hasher = Sha1::new();
+    while let Some(chunk) = stream.try_next().await? {
+        hasher.update(&chunk);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub fn checksum(bytes: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(bytes);
+    format!("{:x}", hasher.finalize())
+}
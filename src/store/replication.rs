@@ -0,0 +1,156 @@
+//! Mirroring of sharded blobs across the store roots.
+//!
+//! Every blob is written to `replicas` roots picked at random, weighted by their free space.
+//! Full reads take the first intact replica and range reads the first one found,
+//! [`Store::repair`] removes corrupted ones and brings blobs back to full redundancy.
+use std::path::{Path, PathBuf};
+use rand::distributions::{Distribution, WeightedIndex};
+use tokio::{fs, io};
+use tracing::{debug, warn};
+use crate::store::{hash_file, write_replica, Store, StoreFile};
+
+/// Outcome of [`Store::repair`] for one blob
+#[derive(Debug, Clone, PartialEq)]
+pub enum Repair {
+    Healthy,
+    /// Missing replicas were copied to `copies` roots after removing `removed` corrupted ones
+    Restored { copies: usize, removed: usize },
+    /// No root holds an intact replica
+    Lost,
+}
+
+/// Picks `count` distinct indices, each one with a probability proportional to its weight among those left
+pub fn weighted_choice(weights: &[u64], count: usize) -> io::Result<Vec<usize>> {
+    let mut weights = weights.to_vec();
+    let mut chosen = Vec::with_capacity(count);
+    let mut rng = rand::thread_rng();
+    for _ in 0..count {
+        let index = WeightedIndex::new(&weights)
+            .map_err(|e| io::Error::other(format!("Not enough roots with free space: {e}")))?
+            .sample(&mut rng);
+        weights[index] = 0;
+        chosen.push(index);
+    }
+    Ok(chosen)
+}
+
+impl Store {
+    /// Picks `count` roots outside `exclude`, weighted by their free space
+    pub(super) fn place(&self, count: usize, exclude: &[usize]) -> io::Result<Vec<usize>> {
+        let candidates: Vec<usize> = (0..self.roots.len()).filter(|i| !exclude.contains(i)).collect();
+        if candidates.len() == count {
+            return Ok(candidates);
+        }
+        let weights = self.roots.iter().enumerate().map(|(i, root)| {
+            if exclude.contains(&i) {
+                return Ok(0);
+            }
+            match fs2::available_space(root) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+                space => space,
+            }
+        }).collect::<io::Result<Vec<u64>>>()?;
+        weighted_choice(&weights, count)
+    }
+
+    /// Opens the first replica of the blob that exists.
+    /// With `verify`, mirrored replicas are hashed first and corrupted ones skipped, queueing a [`Store::repair`] of the blob.
+    /// Range reads go without, hashing would read the whole blob for every one of them.
+    pub(super) async fn open(&self, file: &StoreFile, verify: bool) -> io::Result<fs::File> {
+        let path = file.path();
+        let blob = file.blob.as_ref().filter(|_| verify && self.replicas > 1);
+        let mut corrupted = false;
+        for root in &self.roots {
+            let full = root.join(&path);
+            if let Some(blob) = blob {
+                match hash_file(&full).await {
+                    Ok(hash) if hash == *blob => {}
+                    Ok(_) => {
+                        warn!("Replica {full:?} is corrupted, reading another one");
+                        corrupted = true;
+                        continue;
+                    }
+                    Err(e) => {
+                        debug!("Replica {full:?} is unavailable: {e}");
+                        continue;
+                    }
+                }
+            }
+            match fs::File::open(&full).await {
+                Ok(reader) => {
+                    if corrupted {
+                        self.queue_repair(file);
+                    }
+                    return Ok(reader);
+                }
+                Err(e) => debug!("Replica {full:?} is unavailable: {e}"),
+            }
+        }
+        match corrupted {
+            true => Err(io::Error::new(io::ErrorKind::InvalidData, format!("No intact replica of {path:?}"))),
+            false => Err(io::Error::new(io::ErrorKind::NotFound, format!("No replica of {path:?}"))),
+        }
+    }
+
+    /// Repairs the blob in the background, the read that found a corrupted replica does not wait for it
+    fn queue_repair(&self, file: &StoreFile) {
+        let store = self.clone();
+        let file = file.clone();
+        tokio::spawn(async move {
+            match store.repair(&file).await {
+                Ok(repair) => debug!("Queued repair of {:?}: {repair:?}", file.path()),
+                Err(e) => warn!("Queued repair of {:?} failed: {e}", file.path()),
+            }
+        });
+    }
+
+    /// Removes corrupted replicas of a sharded blob and copies an intact one until `replicas` roots hold it
+    pub async fn repair(&self, file: &StoreFile) -> io::Result<Repair> {
+        let Some(blob) = &file.blob else {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Blobs in the flat layout are not replicated"));
+        };
+        let path = file.path();
+        let mut intact = Vec::new();
+        let mut corrupted = Vec::new();
+        for (i, root) in self.roots.iter().enumerate() {
+            match hash_file(&root.join(&path)).await {
+                Ok(hash) if hash == *blob => intact.push(i),
+                Ok(_) => corrupted.push(i),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Corrupted replicas are kept as the last trace of a lost blob
+        let Some(&source) = intact.first() else {
+            return Ok(Repair::Lost);
+        };
+        for &i in &corrupted {
+            remove_replica(&self.roots[i], &path).await?;
+        }
+        let missing = self.replicas.saturating_sub(intact.len());
+        if missing == 0 && corrupted.is_empty() {
+            return Ok(Repair::Healthy);
+        }
+
+        let targets = self.place(missing, &intact)?;
+        if !targets.is_empty() {
+            let contents = fs::read(self.roots[source].join(&path)).await?;
+            for &i in &targets {
+                write_replica(&self.roots[i], &path, &contents).await?;
+            }
+        }
+        debug!("Repaired {path:?}: {} copies, {} removed", targets.len(), corrupted.len());
+        Ok(Repair::Restored { copies: targets.len(), removed: corrupted.len() })
+    }
+
+    /// Roots of the store, the first one being the primary root
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+}
+
+async fn remove_replica(root: &Path, path: &Path) -> io::Result<()> {
+    warn!("Removing corrupted replica {:?}", root.join(path));
+    fs::remove_file(root.join(path)).await
+}
@@ -0,0 +1,182 @@
+//! Trash of deleted entries.
+//!
+//! `delete` marks every version of an entry with `trashed_at`, which hides it from listing and download.
+//! Trashed entries can be restored until the retention of their bucket passes,
+//! then the purger removes them and releases their files like a permanent delete.
+use std::env;
+use std::time::Duration;
+use axum::extract::{Path, State};
+use axum::{debug_handler, Json, Router};
+use axum::http::StatusCode;
+use axum::routing::{delete, get, post};
+use bucket_storage_client::models::TrashEntry;
+use serde::Serialize;
+use sqlx::{PgPool, query};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info};
+use uuid::Uuid;
+use crate::AppState;
+use crate::auth::Claims;
+use crate::errors::AppError;
+use crate::files::{release_files, remove_released};
+use crate::store::Store;
+use crate::webhooks;
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all="camelCase")]
+pub struct PurgeReport {
+    /// Expired entries removed with all their versions
+    pub entries: usize,
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/trash", get(list_trash))
+        .route("/trash/:file_id/restore", post(restore))
+        .route("/trash/:file_id", delete(purge_entry))
+}
+
+#[utoipa::path(
+    get,
+    path = "/trash",
+    tag = "files",
+    responses(
+        (status = 200, description = "Deleted entries that can still be restored", body = [TrashEntry]),
+    ),
+    security(("basic" = [])),
+)]
+async fn list_trash(claims: Claims, State(pool): State<PgPool>) -> Result<Json<Vec<TrashEntry>>, AppError> {
+    let entries = query!(r#"
+    SELECT bucket_files.entry_id, bucket_files.name, files.extension, files.size, bucket_files.trashed_at AS "trashed_at!",
+        bucket_files.trashed_at + make_interval(secs => buckets.trash_retention_secs) AS "expires_at!"
+    FROM bucket_files
+    JOIN buckets ON buckets.id = bucket_files.bucket_id
+    JOIN files ON files.id = bucket_files.file_id
+    WHERE bucket_id = $1 AND latest AND trashed_at IS NOT NULL
+    ORDER BY bucket_files.trashed_at DESC
+    "#, claims.bucket_id).fetch_all(&pool).await?;
+
+    let entries = entries.into_iter().map(|rec| TrashEntry {
+        id: rec.entry_id,
+        name: rec.name,
+        extension: rec.extension,
+        size: rec.size,
+        deleted_at: rec.trashed_at,
+        expires_at: rec.expires_at,
+    }).collect();
+    Ok(Json(entries))
+}
+
+#[utoipa::path(
+    post,
+    path = "/trash/{file_id}/restore",
+    tag = "files",
+    params(("file_id" = Uuid, Path, description = "Id of the deleted file")),
+    responses(
+        (status = 200, description = "File is listed and downloadable again"),
+        (status = 404, description = "File is not in the trash", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+/// Entries past their retention are left to the purger even when it did not run yet
+async fn restore(claims: Claims, State(pool): State<PgPool>, Path(entry_id): Path<Uuid>) -> Result<(), AppError> {
+    let mut transaction = pool.begin().await?;
+    let res = query!(r#"
+    UPDATE bucket_files
+    SET trashed_at = NULL
+    FROM buckets
+    WHERE buckets.id = bucket_files.bucket_id AND bucket_files.bucket_id = $1 AND bucket_files.entry_id = $2
+    AND bucket_files.trashed_at >= now() - make_interval(secs => buckets.trash_retention_secs)
+    "#, claims.bucket_id, entry_id).execute(&mut transaction).await?;
+
+    if res.rows_affected() == 0 {
+        return Err(AppError::expected(StatusCode::NOT_FOUND, "File is not in the trash"));
+    }
+    webhooks::enqueue_created(&mut transaction, claims.bucket_id, entry_id).await?;
+    transaction.commit().await?;
+    debug!("Restored {entry_id} from the trash");
+    Ok(())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/trash/{file_id}",
+    tag = "files",
+    params(("file_id" = Uuid, Path, description = "Id of the deleted file")),
+    responses(
+        (status = 200, description = "File removed permanently before its retention passed"),
+        (status = 404, description = "File is not in the trash", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+#[debug_handler(state = AppState)]
+async fn purge_entry(claims: Claims, State(pool): State<PgPool>, State(store): State<Store>, Path(entry_id): Path<Uuid>) -> Result<(), AppError> {
+    let mut transaction = pool.begin().await?;
+    let versions = query!(r#"
+    DELETE FROM bucket_files
+    WHERE bucket_id = $1 AND entry_id = $2 AND trashed_at IS NOT NULL
+    RETURNING file_id
+    "#, claims.bucket_id, entry_id).fetch_all(&mut transaction).await?;
+
+    if versions.is_empty() {
+        return Err(AppError::expected(StatusCode::NOT_FOUND, "File is not in the trash"));
+    }
+    let released = release_files(&mut transaction, versions.into_iter().filter_map(|rec| rec.file_id)).await?;
+    transaction.commit().await?;
+    remove_released(&store, released).await;
+    debug!("Purged {entry_id} from the trash");
+    Ok(())
+}
+
+/// Reads `TRASH_PURGE_INTERVAL` in seconds, ten minutes by default
+pub fn purge_interval() -> Duration {
+    let secs = env::var("TRASH_PURGE_INTERVAL").map(|secs| secs.parse().expect("Failed to parse TRASH_PURGE_INTERVAL")).unwrap_or(600);
+    Duration::from_secs(secs)
+}
+
+/// Purges expired entries every `interval` until the process exits
+pub fn spawn(pool: PgPool, store: Store, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            match purge(&pool, &store).await {
+                Ok(report) => info!("Purged {} expired entries from the trash", report.entries),
+                Err(e) => error!("Trash purge failed: {e}"),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    })
+}
+
+/// Removes every entry that stayed in the trash longer than the retention of its bucket, one transaction per entry
+pub async fn purge(pool: &PgPool, store: &Store) -> Result<PurgeReport, AppError> {
+    let mut report = PurgeReport::default();
+    let expired = query!(r#"
+    SELECT DISTINCT bucket_files.bucket_id, bucket_files.entry_id
+    FROM bucket_files
+    JOIN buckets ON buckets.id = bucket_files.bucket_id
+    WHERE bucket_files.trashed_at < now() - make_interval(secs => buckets.trash_retention_secs)
+    "#).fetch_all(pool).await?;
+
+    for rec in expired {
+        let mut transaction = pool.begin().await?;
+        // Checked again, the entry may have been restored and deleted anew since
+        let versions = query!(r#"
+        DELETE FROM bucket_files
+        USING buckets
+        WHERE buckets.id = bucket_files.bucket_id AND bucket_files.bucket_id = $1 AND bucket_files.entry_id = $2
+        AND bucket_files.trashed_at < now() - make_interval(secs => buckets.trash_retention_secs)
+        RETURNING bucket_files.file_id
+        "#, rec.bucket_id, rec.entry_id).fetch_all(&mut transaction).await?;
+
+        if versions.is_empty() {
+            continue;
+        }
+        let released = release_files(&mut transaction, versions.into_iter().filter_map(|rec| rec.file_id)).await?;
+        transaction.commit().await?;
+        remove_released(store, released).await;
+        report.entries += 1;
+    }
+
+    debug!("Trash purge finished: {report:?}");
+    Ok(report)
+}
@@ -0,0 +1,168 @@
+//! Versions of bucket entries.
+//!
+//! Every `bucket_files` row is a version, versions of one entry share its `entry_id` and exactly one is `latest`.
+//! Buckets with versioning enabled add a version when a name is written again and a delete marker,
+//! a version without a file, when the entry is deleted.
+use axum::extract::{Path, State};
+use axum::{debug_handler, Json, Router};
+use axum::http::StatusCode;
+use axum::routing::{delete, get, post};
+use bucket_storage_client::models::FileVersion;
+use sqlx::{PgPool, Postgres, query, Transaction};
+use tracing::debug;
+use uuid::Uuid;
+use crate::AppState;
+use crate::auth::Claims;
+use crate::errors::AppError;
+use crate::files::{release_file, remove_released};
+use crate::store::{Store, StoreFile};
+use crate::webhooks;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/files/:file_id/versions", get(list_versions))
+        .route("/files/:file_id/versions/:version_id/restore", post(restore_version))
+        .route("/files/:file_id/versions/:version_id", delete(delete_version))
+}
+
+#[utoipa::path(
+    get,
+    path = "/files/{file_id}/versions",
+    tag = "files",
+    params(("file_id" = Uuid, Path, description = "Id of the file in the bucket")),
+    responses(
+        (status = 200, description = "Versions of the file, newest first", body = [FileVersion]),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+async fn list_versions(claims: Claims, State(pool): State<PgPool>, Path(entry_id): Path<Uuid>) -> Result<Json<Vec<FileVersion>>, AppError> {
+    let versions = query!(r#"
+    SELECT bucket_files.id, bucket_files.latest, bucket_files.file_id, bucket_files.created_at, files.size AS "size?", files.checksum AS "checksum?"
+    FROM bucket_files
+    LEFT JOIN files ON files.id = bucket_files.file_id
+    WHERE bucket_id = $1 AND entry_id = $2 AND trashed_at IS NULL
+    ORDER BY bucket_files.created_at DESC
+    "#, claims.bucket_id, entry_id).fetch_all(&pool).await?;
+
+    if versions.is_empty() {
+        return Err(AppError::expected(StatusCode::NOT_FOUND, "File not found"));
+    }
+    let versions = versions.into_iter().map(|rec| FileVersion {
+        id: rec.id,
+        latest: rec.latest,
+        deleted: rec.file_id.is_none(),
+        size: rec.size,
+        checksum: rec.checksum,
+        created_at: rec.created_at,
+    }).collect();
+    Ok(Json(versions))
+}
+
+#[utoipa::path(
+    post,
+    path = "/files/{file_id}/versions/{version_id}/restore",
+    tag = "files",
+    params(
+        ("file_id" = Uuid, Path, description = "Id of the file in the bucket"),
+        ("version_id" = Uuid, Path, description = "Version to restore"),
+    ),
+    responses(
+        (status = 200, description = "Id of the new latest version holding the restored contents", body = Uuid),
+        (status = 400, description = "Delete markers cannot be restored", body = ErrorResponse),
+        (status = 404, description = "Version not found", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+#[debug_handler(state = AppState)]
+async fn restore_version(claims: Claims, State(pool): State<PgPool>, Path((entry_id, version_id)): Path<(Uuid, Uuid)>) -> Result<Json<Uuid>, AppError> {
+    let mut transaction = pool.begin().await?;
+    let version = query!(r#"
+    SELECT name, file_id
+    FROM bucket_files
+    WHERE bucket_id = $1 AND entry_id = $2 AND id = $3 AND trashed_at IS NULL
+    "#, claims.bucket_id, entry_id, version_id).fetch_optional(&mut transaction).await?.ok_or(AppError::expected(StatusCode::NOT_FOUND, "Version not found"))?;
+    let file_id = version.file_id.ok_or(AppError::expected(StatusCode::BAD_REQUEST, "Delete markers cannot be restored"))?;
+
+    let restored = add_version(&mut transaction, claims.bucket_id, entry_id, &version.name, Some(file_id)).await?;
+    query!(r#"
+    UPDATE bucket_files
+    SET metadata = (SELECT metadata FROM bucket_files WHERE id = $1)
+    WHERE id = $2
+    "#, version_id, restored).execute(&mut transaction).await?;
+    webhooks::enqueue_created(&mut transaction, claims.bucket_id, entry_id).await?;
+    transaction.commit().await?;
+    debug!("Restored version {version_id} of {entry_id} as {restored}");
+    Ok(Json(restored))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/files/{file_id}/versions/{version_id}",
+    tag = "files",
+    params(
+        ("file_id" = Uuid, Path, description = "Id of the file in the bucket"),
+        ("version_id" = Uuid, Path, description = "Version to remove permanently"),
+    ),
+    responses(
+        (status = 200, description = "Version removed, the previous one becomes the latest when it was the latest"),
+        (status = 404, description = "Version not found", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+#[debug_handler(state = AppState)]
+async fn delete_version(claims: Claims, State(pool): State<PgPool>, State(store): State<Store>, Path((entry_id, version_id)): Path<(Uuid, Uuid)>) -> Result<(), AppError> {
+    let mut transaction = pool.begin().await?;
+    let released = remove_version(&mut transaction, claims.bucket_id, entry_id, version_id).await?;
+    transaction.commit().await?;
+    remove_released(&store, released).await;
+    Ok(())
+}
+
+/// Makes a new version the latest of the entry, `file_id` is `None` for delete markers
+pub async fn add_version(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid, entry_id: Uuid, name: &str, file_id: Option<Uuid>) -> Result<Uuid, AppError> {
+    query!(r#"
+    UPDATE bucket_files
+    SET latest = false
+    WHERE bucket_id = $1 AND entry_id = $2 AND latest
+    "#, bucket_id, entry_id).execute(&mut *transaction).await?;
+
+    let version_id = query!(r#"
+    INSERT INTO bucket_files (name, bucket_id, file_id, entry_id)
+    VALUES ($1, $2, $3, $4)
+    RETURNING id
+    "#, name, bucket_id, file_id, entry_id).fetch_one(&mut *transaction).await?.id;
+
+    Ok(version_id)
+}
+
+/// Removes one version permanently, the file goes too when no other version references it.
+/// Returns the blobs to pass to [`remove_released`] once the transaction committed.
+pub async fn remove_version(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid, entry_id: Uuid, version_id: Uuid) -> Result<Vec<StoreFile>, AppError> {
+    let removed = query!(r#"
+    DELETE FROM bucket_files
+    WHERE bucket_id = $1 AND entry_id = $2 AND id = $3 AND trashed_at IS NULL
+    RETURNING file_id, latest
+    "#, bucket_id, entry_id, version_id).fetch_optional(&mut *transaction).await?.ok_or(AppError::expected(StatusCode::NOT_FOUND, "Version not found"))?;
+
+    if removed.latest {
+        query!(r#"
+        UPDATE bucket_files
+        SET latest = true
+        WHERE id = (
+            SELECT id
+            FROM bucket_files
+            WHERE bucket_id = $1 AND entry_id = $2
+            ORDER BY created_at DESC
+            LIMIT 1
+        )
+        "#, bucket_id, entry_id).execute(&mut *transaction).await?;
+    }
+
+    let released = match removed.file_id {
+        Some(file_id) => release_file(transaction, file_id).await?,
+        None => Vec::new(),
+    };
+    debug!("Removed version {version_id} of {entry_id}");
+    Ok(released)
+}
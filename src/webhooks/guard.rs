@@ -0,0 +1,75 @@
+//! Keeping webhook deliveries away from the network the server runs in.
+//!
+//! Anyone holding a bucket key registers webhook URLs and reads back the status codes and errors of their deliveries,
+//! so deliveries to loopback, private, link-local and other non-public addresses would let them probe internal services.
+//! Hosts are checked when the webhook is registered and resolved again by [`PublicResolver`] on every attempt,
+//! a name pointing somewhere else by then still never connects to a non-public address.
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use axum::http::StatusCode;
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::Url;
+use url::Host;
+use crate::errors::AppError;
+
+/// Whether the address is reachable on the public internet
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified() || ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_broadcast()
+        || ip.is_documentation() || ip.is_multicast()
+        // "This network", shared address space of carrier-grade NAT, IETF protocol assignments, benchmarking and reserved
+        || a == 0 || (a == 100 && b & 0xC0 == 64) || (a == 192 && b == 0 && c == 0) || (a == 198 && b & 0xFE == 18) || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified() || ip.is_loopback() || ip.is_multicast()
+        // Unique local, link-local, documentation and IPv4-compatible addresses
+        || first & 0xFE00 == 0xFC00 || first & 0xFFC0 == 0xFE80 || (first == 0x2001 && ip.segments()[1] == 0x0DB8)
+        || ip.segments()[..6].iter().all(|segment| *segment == 0))
+}
+
+/// Rejects URLs whose host is or resolves to a non-public address
+pub async fn check_url(url: &Url) -> Result<(), AppError> {
+    let refused = || AppError::expected(StatusCode::BAD_REQUEST, "Webhook URL must point to a public address");
+    let public = match url.host() {
+        Some(Host::Ipv4(ip)) => is_public(ip.into()),
+        Some(Host::Ipv6(ip)) => is_public(ip.into()),
+        Some(Host::Domain(domain)) => {
+            let port = url.port_or_known_default().unwrap_or(80);
+            let addrs = tokio::net::lookup_host((domain, port)).await
+                .map_err(|_| AppError::expected(StatusCode::BAD_REQUEST, format!("Webhook host {domain} does not resolve")))?
+                .collect::<Vec<_>>();
+            !addrs.is_empty() && addrs.iter().all(|addr| is_public(addr.ip()))
+        }
+        None => false,
+    };
+    public.then_some(()).ok_or_else(refused)
+}
+
+/// Resolver of the delivery client leaving out every non-public address of a host
+pub struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0)).await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect::<Vec<SocketAddr>>();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
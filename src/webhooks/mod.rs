@@ -0,0 +1,504 @@
+//! Notifying endpoints registered by bucket owners of events of their bucket.
+//!
+//! Events are written to `webhook_deliveries` in the transaction of the change they report, one row per subscribed webhook,
+//! so an event goes out if and only if its change committed. The dispatcher claims due rows for a lease, posts them as JSON
+//! signed with the secret of the webhook and retries failures with exponential backoff. After [`MAX_ATTEMPTS`] a delivery
+//! is dead until it is retried through the API. Deliveries are sent at least once, receivers drop repeats by the payload id.
+use std::collections::HashSet;
+use std::env;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::anyhow;
+use axum::extract::{Path, Query, State};
+use axum::{debug_handler, Json, Router};
+use axum::http::StatusCode;
+use axum::routing::{delete, get, post};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use bucket_storage_client::models::{DeliveryQuery, DeliveryStatus, Webhook, WebhookDelivery, WebhookEvent, WebhookPayload, WebhookRequest};
+use futures::future::join_all;
+use hmac::{Hmac, Mac};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::{PgPool, Postgres, query, Transaction};
+use time::OffsetDateTime;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use url::Host;
+use uuid::Uuid;
+use crate::AppState;
+use crate::auth::Claims;
+use crate::errors::AppError;
+use crate::search::{DEFAULT_LIMIT, MAX_LIMIT};
+
+/// HMAC-SHA256 of the timestamp, a dot and the body as `sha256=<hex>`, see [`sign`]
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+/// Unix time of the attempt, part of the signed message so receivers can reject replays
+pub const TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+pub const EVENT_HEADER: &str = "x-webhook-event";
+pub const DELIVERY_HEADER: &str = "x-webhook-delivery";
+/// Attempts after which a delivery is dead
+pub const MAX_ATTEMPTS: i32 = 8;
+pub const MAX_WEBHOOKS: i64 = 16;
+/// Percentage of the quota whose crossing sends a quota warning
+pub const QUOTA_WARNING_PERCENT: i64 = 90;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a claimed delivery stays hidden from other dispatchers, longer than an attempt can take
+const LEASE: Duration = Duration::from_secs(60);
+/// Deliveries claimed and sent concurrently at a time
+const BATCH_SIZE: i64 = 32;
+
+pub mod guard;
+
+/// Settings of webhooks read from the environment
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WebhookConfig {
+    /// Lets webhooks reach loopback, private and other non-public addresses, only for setups where every key holder is trusted
+    pub allow_private: bool,
+}
+
+impl WebhookConfig {
+    /// Reads `WEBHOOK_ALLOW_PRIVATE`, `false` by default
+    pub fn from_env() -> Self {
+        let allow_private = env::var("WEBHOOK_ALLOW_PRIVATE").map(|allow| allow.parse().expect("Failed to parse WEBHOOK_ALLOW_PRIVATE")).unwrap_or(false);
+        Self { allow_private }
+    }
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/webhooks", get(list_webhooks).post(create_webhook))
+        .route("/webhooks/:webhook_id", delete(delete_webhook))
+        .route("/webhooks/:webhook_id/deliveries", get(list_deliveries))
+        .route("/webhooks/:webhook_id/deliveries/:delivery_id/retry", post(retry_delivery))
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all="camelCase")]
+pub struct DeliveryReport {
+    pub delivered: usize,
+    /// Failed attempts scheduled again
+    pub failed: usize,
+    /// Failed attempts that were the last one
+    pub dead: usize,
+}
+
+#[utoipa::path(
+    post,
+    path = "/webhooks",
+    tag = "webhooks",
+    request_body = WebhookRequest,
+    responses(
+        (status = 200, description = "Registered webhook with the secret signing its deliveries, the only time it is returned", body = Webhook),
+        (status = 400, description = "Invalid or non-public URL, no events or too many webhooks", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+#[debug_handler(state = AppState)]
+async fn create_webhook(claims: Claims, State(pool): State<PgPool>, State(config): State<WebhookConfig>, Json(body): Json<WebhookRequest>) -> Result<Json<Webhook>, AppError> {
+    let url = Url::parse(&body.url).ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https") && url.has_host())
+        .ok_or(AppError::expected(StatusCode::BAD_REQUEST, "Webhook URL must be an absolute http or https URL"))?;
+    if !config.allow_private {
+        guard::check_url(&url).await?;
+    }
+    let mut seen = HashSet::new();
+    let events = body.events.into_iter().filter(|event| seen.insert(*event)).collect::<Vec<_>>();
+    if events.is_empty() {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, "Webhook must subscribe to at least one event"));
+    }
+
+    let mut transaction = pool.begin().await?;
+    let count = query!(r#"
+    SELECT COUNT(*) AS "count!" FROM webhooks WHERE bucket_id = $1
+    "#, claims.bucket_id).fetch_one(&mut transaction).await?.count;
+    if count >= MAX_WEBHOOKS {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, format!("At most {MAX_WEBHOOKS} webhooks per bucket are allowed")));
+    }
+
+    let secret = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+    let names = events.iter().map(|event| event.as_str().to_string()).collect::<Vec<_>>();
+    let rec = query!(r#"
+    INSERT INTO webhooks (bucket_id, url, secret, events)
+    VALUES ($1, $2, $3, $4)
+    RETURNING id, created_at
+    "#, claims.bucket_id, url.as_str(), secret, &names).fetch_one(&mut transaction).await?;
+    transaction.commit().await?;
+    debug!("Registered webhook {} of bucket {}", rec.id, claims.bucket_id);
+
+    Ok(Json(Webhook { id: rec.id, url: url.to_string(), events, secret: Some(secret), created_at: rec.created_at }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "Webhooks of the bucket without their secrets", body = [Webhook]),
+    ),
+    security(("basic" = [])),
+)]
+async fn list_webhooks(claims: Claims, State(pool): State<PgPool>) -> Result<Json<Vec<Webhook>>, AppError> {
+    let webhooks = query!(r#"
+    SELECT id, url, events, created_at
+    FROM webhooks
+    WHERE bucket_id = $1
+    ORDER BY created_at
+    "#, claims.bucket_id).fetch_all(&pool).await?;
+
+    let webhooks = webhooks.into_iter().map(|rec| Ok(Webhook {
+        id: rec.id,
+        url: rec.url,
+        events: parse_events(&rec.events)?,
+        secret: None,
+        created_at: rec.created_at,
+    })).collect::<Result<_, AppError>>()?;
+    Ok(Json(webhooks))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/webhooks/{webhook_id}",
+    tag = "webhooks",
+    params(("webhook_id" = Uuid, Path, description = "Id of the webhook")),
+    responses(
+        (status = 200, description = "Webhook removed with its pending deliveries and log"),
+        (status = 404, description = "Webhook not found", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+async fn delete_webhook(claims: Claims, State(pool): State<PgPool>, Path(webhook_id): Path<Uuid>) -> Result<(), AppError> {
+    let res = query!(r#"
+    DELETE FROM webhooks
+    WHERE id = $1 AND bucket_id = $2
+    "#, webhook_id, claims.bucket_id).execute(&pool).await?;
+
+    if res.rows_affected() == 0 {
+        return Err(AppError::expected(StatusCode::NOT_FOUND, "Webhook not found"));
+    }
+    debug!("Removed webhook {webhook_id}");
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks/{webhook_id}/deliveries",
+    tag = "webhooks",
+    params(
+        ("webhook_id" = Uuid, Path, description = "Id of the webhook"),
+        DeliveryQuery,
+    ),
+    responses(
+        (status = 200, description = "One page of the deliveries of the webhook, newest first", body = [WebhookDelivery]),
+        (status = 400, description = "Invalid page", body = ErrorResponse),
+        (status = 404, description = "Webhook not found", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+async fn list_deliveries(claims: Claims, State(pool): State<PgPool>, Path(webhook_id): Path<Uuid>, Query(query): Query<DeliveryQuery>) -> Result<Json<Vec<WebhookDelivery>>, AppError> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    let offset = query.offset.unwrap_or(0);
+    if !(1..=MAX_LIMIT).contains(&limit) || offset < 0 {
+        return Err(AppError::expected(StatusCode::BAD_REQUEST, format!("Limit must be between 1 and {MAX_LIMIT} and offset positive")));
+    }
+    webhook_bucket(&pool, claims.bucket_id, webhook_id).await?;
+    Ok(Json(deliveries(&pool, webhook_id, None, query.status, limit, offset).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/webhooks/{webhook_id}/deliveries/{delivery_id}/retry",
+    tag = "webhooks",
+    params(
+        ("webhook_id" = Uuid, Path, description = "Id of the webhook"),
+        ("delivery_id" = Uuid, Path, description = "Id of the delivery"),
+    ),
+    responses(
+        (status = 200, description = "Delivery scheduled for an attempt right away, with all attempts available again", body = WebhookDelivery),
+        (status = 404, description = "Webhook or undelivered delivery not found", body = ErrorResponse),
+    ),
+    security(("basic" = [])),
+)]
+async fn retry_delivery(claims: Claims, State(pool): State<PgPool>, Path((webhook_id, delivery_id)): Path<(Uuid, Uuid)>) -> Result<Json<WebhookDelivery>, AppError> {
+    webhook_bucket(&pool, claims.bucket_id, webhook_id).await?;
+    let res = query!(r#"
+    UPDATE webhook_deliveries
+    SET status = 'pending', attempts = 0, next_attempt_at = now()
+    WHERE id = $1 AND webhook_id = $2 AND status <> 'delivered'
+    "#, delivery_id, webhook_id).execute(&pool).await?;
+
+    if res.rows_affected() == 0 {
+        return Err(AppError::expected(StatusCode::NOT_FOUND, "Delivery not found or already delivered"));
+    }
+    debug!("Scheduled delivery {delivery_id} again");
+    let delivery = deliveries(&pool, webhook_id, Some(delivery_id), None, 1, 0).await?.pop()
+        .ok_or(AppError::expected(StatusCode::NOT_FOUND, "Delivery not found"))?;
+    Ok(Json(delivery))
+}
+
+/// Checks that the webhook belongs to the bucket
+async fn webhook_bucket(pool: &PgPool, bucket_id: Uuid, webhook_id: Uuid) -> Result<(), AppError> {
+    query!(r#"
+    SELECT id FROM webhooks WHERE id = $1 AND bucket_id = $2
+    "#, webhook_id, bucket_id).fetch_optional(pool).await?.ok_or(AppError::expected(StatusCode::NOT_FOUND, "Webhook not found"))?;
+    Ok(())
+}
+
+async fn deliveries(pool: &PgPool, webhook_id: Uuid, delivery_id: Option<Uuid>, status: Option<DeliveryStatus>, limit: i64, offset: i64) -> Result<Vec<WebhookDelivery>, AppError> {
+    let rows = query!(r#"
+    SELECT webhook_deliveries.id, event, data, status, attempts, next_attempt_at, last_status_code, last_error,
+        webhook_deliveries.created_at, delivered_at, webhooks.bucket_id
+    FROM webhook_deliveries
+    JOIN webhooks ON webhooks.id = webhook_deliveries.webhook_id
+    WHERE webhook_id = $1 AND ($2::UUID IS NULL OR webhook_deliveries.id = $2) AND ($3::TEXT IS NULL OR status = $3)
+    ORDER BY webhook_deliveries.created_at DESC, webhook_deliveries.id
+    LIMIT $4 OFFSET $5
+    "#, webhook_id, delivery_id, status.map(|status| status.as_str()), limit, offset).fetch_all(pool).await?;
+
+    rows.into_iter().map(|rec| {
+        let event = rec.event.parse().map_err(|e: String| anyhow!(e))?;
+        Ok(WebhookDelivery {
+            id: rec.id,
+            event,
+            status: rec.status.parse().map_err(|e: String| anyhow!(e))?,
+            attempts: rec.attempts,
+            last_status_code: rec.last_status_code.map(|code| code as u16),
+            last_error: rec.last_error,
+            next_attempt_at: rec.next_attempt_at,
+            created_at: rec.created_at,
+            delivered_at: rec.delivered_at,
+            payload: WebhookPayload { id: rec.id, event, bucket_id: rec.bucket_id, occurred_at: rec.created_at, data: rec.data },
+        })
+    }).collect()
+}
+
+fn parse_events(events: &[String]) -> Result<Vec<WebhookEvent>, AppError> {
+    Ok(events.iter().map(|event| event.parse()).collect::<Result<_, String>>().map_err(|e| anyhow!(e))?)
+}
+
+/// Adds a delivery of the event for every webhook of the bucket subscribed to it
+pub async fn enqueue(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid, event: WebhookEvent, data: serde_json::Value) -> Result<(), AppError> {
+    let res = query!(r#"
+    INSERT INTO webhook_deliveries (webhook_id, event, data)
+    SELECT id, $2, $3
+    FROM webhooks
+    WHERE bucket_id = $1 AND $2 = ANY(events)
+    "#, bucket_id, event.as_str(), data).execute(&mut *transaction).await?;
+
+    if res.rows_affected() > 0 {
+        debug!("Queued {} deliveries of {} in bucket {bucket_id}", res.rows_affected(), event.as_str());
+    }
+    Ok(())
+}
+
+/// Notifies `object.created` for the latest version of an entry added or brought back without an upload,
+/// like copies and restores
+pub async fn enqueue_created(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid, entry_id: Uuid) -> Result<(), AppError> {
+    let rec = query!(r#"
+    SELECT bucket_files.name, files.extension, files.size, files.checksum
+    FROM bucket_files
+    JOIN files ON files.id = bucket_files.file_id
+    WHERE bucket_id = $1 AND entry_id = $2 AND latest
+    "#, bucket_id, entry_id).fetch_optional(&mut *transaction).await?;
+
+    let Some(rec) = rec else {
+        return Ok(());
+    };
+    let data = serde_json::json!({ "entryId": entry_id, "name": rec.name, "extension": rec.extension, "size": rec.size, "checksum": rec.checksum });
+    enqueue(transaction, bucket_id, WebhookEvent::ObjectCreated, data).await
+}
+
+/// Sends a quota warning when the size of the bucket crossed [`QUOTA_WARNING_PERCENT`] of its quota,
+/// once until an upload finds the bucket below it again. Buckets without a quota return right away.
+pub async fn check_quota(transaction: &mut Transaction<'_, Postgres>, bucket_id: Uuid) -> Result<(), AppError> {
+    let rec = query!(r#"
+    SELECT quota_bytes, quota_warned, used_bytes
+    FROM buckets
+    WHERE id = $1
+    "#, bucket_id).fetch_one(&mut *transaction).await?;
+
+    let Some(quota_bytes) = rec.quota_bytes else {
+        return Ok(());
+    };
+    let above = rec.used_bytes.saturating_mul(100) >= quota_bytes.saturating_mul(QUOTA_WARNING_PERCENT);
+    if above == rec.quota_warned {
+        return Ok(());
+    }
+    // Concurrent uploads crossing the threshold together warn once
+    let res = query!(r#"
+    UPDATE buckets
+    SET quota_warned = $1
+    WHERE id = $2 AND quota_warned <> $1
+    "#, above, bucket_id).execute(&mut *transaction).await?;
+
+    if above && res.rows_affected() > 0 {
+        warn!("Bucket {bucket_id} uses {} of its {quota_bytes} bytes", rec.used_bytes);
+        let data = serde_json::json!({ "usedBytes": rec.used_bytes, "quotaBytes": quota_bytes });
+        enqueue(transaction, bucket_id, WebhookEvent::QuotaWarning, data).await?;
+    }
+    Ok(())
+}
+
+/// Signature of a delivery, receivers recompute it with the secret of the webhook to authenticate it
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(format!("{timestamp}.").as_bytes());
+    mac.update(body);
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+/// Delay before the attempt following `attempts` failed ones
+fn backoff(attempts: i32) -> Duration {
+    let doublings = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    INITIAL_BACKOFF.saturating_mul(2u32.pow(doublings)).min(MAX_BACKOFF)
+}
+
+/// Client sending deliveries. Redirects are not followed so deliveries only reach the registered URL,
+/// and unless the config allows it only public addresses are connected to.
+#[derive(Clone)]
+pub struct DeliveryClient {
+    http: reqwest::Client,
+    allow_private: bool,
+}
+
+impl DeliveryClient {
+    pub fn new(config: WebhookConfig) -> Self {
+        let builder = reqwest::Client::builder()
+            .timeout(DELIVERY_TIMEOUT)
+            .redirect(Policy::none())
+            .no_proxy();
+        let builder = match config.allow_private {
+            true => builder,
+            false => builder.dns_resolver(Arc::new(guard::PublicResolver)),
+        };
+        let http = builder.build().expect("Failed to build webhook client");
+        Self { http, allow_private: config.allow_private }
+    }
+}
+
+/// Reads `WEBHOOK_DELIVERY_INTERVAL` in seconds, five seconds by default
+pub fn delivery_interval() -> Duration {
+    let secs = env::var("WEBHOOK_DELIVERY_INTERVAL").map(|secs| secs.parse().expect("Failed to parse WEBHOOK_DELIVERY_INTERVAL")).unwrap_or(5);
+    Duration::from_secs(secs)
+}
+
+/// Sends due deliveries every `interval` until the process exits
+pub fn spawn(pool: PgPool, config: WebhookConfig, interval: Duration) -> JoinHandle<()> {
+    let client = DeliveryClient::new(config);
+    tokio::spawn(async move {
+        loop {
+            match deliver(&pool, &client).await {
+                Ok(report) if report.delivered + report.failed + report.dead > 0 => info!("Sent webhook deliveries: {report:?}"),
+                Ok(_) => {}
+                Err(e) => error!("Sending webhook deliveries failed: {e}"),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    })
+}
+
+/// Attempts every delivery that is due, [`BATCH_SIZE`] at a time
+pub async fn deliver(pool: &PgPool, client: &DeliveryClient) -> Result<DeliveryReport, AppError> {
+    let mut report = DeliveryReport::default();
+    loop {
+        let claimed = query!(r#"
+        UPDATE webhook_deliveries
+        SET next_attempt_at = now() + make_interval(secs => $1)
+        FROM webhooks
+        WHERE webhooks.id = webhook_deliveries.webhook_id AND webhook_deliveries.id IN (
+            SELECT id
+            FROM webhook_deliveries
+            WHERE status = 'pending' AND next_attempt_at <= now()
+            ORDER BY next_attempt_at
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING webhook_deliveries.id, webhook_deliveries.event, webhook_deliveries.data, webhook_deliveries.attempts,
+            webhook_deliveries.created_at, webhooks.bucket_id, webhooks.url, webhooks.secret
+        "#, LEASE.as_secs_f64(), BATCH_SIZE).fetch_all(pool).await?;
+        if claimed.is_empty() {
+            return Ok(report);
+        }
+
+        let attempts = claimed.into_iter().map(|rec| async move {
+            let event = rec.event.parse().map_err(|e: String| anyhow!(e))?;
+            let payload = WebhookPayload { id: rec.id, event, bucket_id: rec.bucket_id, occurred_at: rec.created_at, data: rec.data };
+            let outcome = attempt(client, &rec.url, &rec.secret, &payload).await;
+            Ok::<_, AppError>((rec.id, rec.attempts + 1, outcome))
+        });
+        for res in join_all(attempts).await {
+            let (delivery_id, attempts, outcome) = res?;
+            let (status, status_code, last_error) = match outcome {
+                Ok(code) => {
+                    report.delivered += 1;
+                    (DeliveryStatus::Delivered, Some(code), None)
+                }
+                Err((code, e)) if attempts >= MAX_ATTEMPTS => {
+                    warn!("Delivery {delivery_id} is dead after {attempts} attempts: {e}");
+                    report.dead += 1;
+                    (DeliveryStatus::Dead, code, Some(e))
+                }
+                Err((code, e)) => {
+                    debug!("Delivery {delivery_id} failed: {e}");
+                    report.failed += 1;
+                    (DeliveryStatus::Pending, code, Some(e))
+                }
+            };
+            query!(r#"
+            UPDATE webhook_deliveries
+            SET status = $1, attempts = $2, last_status_code = $3, last_error = $4, next_attempt_at = now() + make_interval(secs => $5),
+                delivered_at = CASE WHEN $1 = 'delivered' THEN now() END
+            WHERE id = $6
+            "#, status.as_str(), attempts, status_code.map(i32::from), last_error, backoff(attempts).as_secs_f64(), delivery_id).execute(pool).await?;
+        }
+    }
+}
+
+/// Posts the payload, a response other than 2xx fails with its status
+async fn attempt(client: &DeliveryClient, url: &str, secret: &str, payload: &WebhookPayload) -> Result<u16, (Option<u16>, String)> {
+    let url = Url::parse(url).map_err(|e| (None, e.to_string()))?;
+    // Addresses in the URL are connected to without resolving them
+    let literal = match url.host() {
+        Some(Host::Ipv4(ip)) => Some(IpAddr::from(ip)),
+        Some(Host::Ipv6(ip)) => Some(IpAddr::from(ip)),
+        _ => None,
+    };
+    if let Some(ip) = literal.filter(|ip| !client.allow_private && !guard::is_public(*ip)) {
+        return Err((None, format!("{ip} is not a public address")));
+    }
+    let body = serde_json::to_vec(payload).map_err(|e| (None, e.to_string()))?;
+    let timestamp = OffsetDateTime::now_utc().unix_timestamp();
+    let res = client.http.post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, payload.event.as_str())
+        .header(DELIVERY_HEADER, payload.id.to_string())
+        .header(TIMESTAMP_HEADER, timestamp)
+        .header(SIGNATURE_HEADER, format!("sha256={}", sign(secret, timestamp, &body)))
+        .body(body)
+        .send().await
+        .map_err(|e| (None, describe(&e)))?;
+
+    let status = res.status();
+    match status.is_success() {
+        true => Ok(status.as_u16()),
+        false => Err((Some(status.as_u16()), format!("Endpoint responded with {status}"))),
+    }
+}
+
+/// The error followed by its causes, reqwest only names the failed request at the top
+fn describe(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
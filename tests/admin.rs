@@ -0,0 +1,81 @@
+use axum::body::Bytes;
+use sqlx::PgPool;
+use tracing_test::traced_test;
+use uuid::Uuid;
+use bucket_storage::{admin, buckets, files};
+use bucket_storage::store::Store;
+
+async fn bucket_with_file(pool: &PgPool, store: &Store, name: &str, contents: &'static [u8]) -> (Uuid, Uuid) {
+    let mut transaction = pool.begin().await.unwrap();
+    let bucket_id = buckets::create_bucket(&mut transaction, "admin").await.unwrap();
+    let file_id = files::save_file(&mut transaction, store, bucket_id, name, Some("txt".to_string()), Bytes::from_static(contents)).await.unwrap();
+    transaction.commit().await.unwrap();
+    (bucket_id, file_id)
+}
+
+#[traced_test]
+#[sqlx::test]
+async fn usage_and_delete_bucket(pool: PgPool) {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::new(dir.path());
+    let (bucket_id, _) = bucket_with_file(&pool, &store, "notes", b"hello").await;
+
+    let usage = buckets::usage(&pool, Some(bucket_id)).await.unwrap();
+    assert_eq!(usage.len(), 1);
+    assert_eq!(usage[0].files, 1);
+    assert_eq!(usage[0].bytes, 5);
+
+    buckets::delete_bucket(&pool, &store, bucket_id).await.unwrap();
+    assert!(buckets::list_buckets(&pool).await.unwrap().is_empty());
+    assert!(store.blobs().await.unwrap().is_empty());
+}
+
+#[traced_test]
+#[sqlx::test]
+async fn gc_removes_orphans(pool: PgPool) {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::new(dir.path());
+    bucket_with_file(&pool, &store, "kept", b"kept").await;
+    tokio::fs::write(dir.path().join(format!("{}.txt", Uuid::new_v4())), b"orphan").await.unwrap();
+
+    let report = admin::gc(&pool, &store, true).await.unwrap();
+    assert_eq!(report.orphaned_blobs.len(), 1);
+    assert_eq!(store.blobs().await.unwrap().len(), 2);
+
+    let report = admin::gc(&pool, &store, false).await.unwrap();
+    assert_eq!(report.orphaned_blobs.len(), 1);
+    assert!(report.unreferenced_files.is_empty());
+    assert_eq!(store.blobs().await.unwrap().len(), 1);
+}
+
+#[traced_test]
+#[sqlx::test]
+async fn fsck_detects_corruption(pool: PgPool) {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::new(dir.path());
+    let (_, file_id) = bucket_with_file(&pool, &store, "data", b"original").await;
+    assert!(admin::fsck(&pool, &store).await.unwrap().is_clean());
+
+    let blobs = store.blobs().await.unwrap();
+    tokio::fs::write(dir.path().join(&blobs[0]), b"tampered").await.unwrap();
+    let report = admin::fsck(&pool, &store).await.unwrap();
+    assert_eq!(report.corrupted, vec![file_id]);
+}
+
+#[traced_test]
+#[sqlx::test]
+async fn export_import_roundtrip(pool: PgPool) {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::new(dir.path());
+    let export = tempfile::tempdir().unwrap();
+    let (bucket_id, _) = bucket_with_file(&pool, &store, "report", b"contents").await;
+
+    assert_eq!(buckets::export_bucket(&pool, &store, bucket_id, export.path()).await.unwrap(), 1);
+    buckets::delete_bucket(&pool, &store, bucket_id).await.unwrap();
+
+    let imported = buckets::import_bucket(&pool, &store, export.path()).await.unwrap();
+    let usage = buckets::usage(&pool, Some(imported)).await.unwrap();
+    assert_eq!(usage[0].name, "admin");
+    assert_eq!(usage[0].files, 1);
+    assert!(admin::fsck(&pool, &store).await.unwrap().is_clean());
+}
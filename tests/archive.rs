@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+use async_compression::tokio::bufread::GzipDecoder;
+use async_zip::base::read::mem::ZipFileReader;
+use bucket_storage_client::{ArchiveFormat, ArchiveRequest, Error};
+use futures::StreamExt;
+use sqlx::PgPool;
+use tokio::io::AsyncReadExt;
+use tokio_tar::Archive;
+use tracing_test::traced_test;
+use uuid::Uuid;
+
+mod tools;
+use crate::tools::AppData;
+
+async fn unzip(data: Vec<u8>) -> BTreeMap<String, Vec<u8>> {
+    let zip = ZipFileReader::new(data).await.unwrap();
+    let mut files = BTreeMap::new();
+    for index in 0..zip.file().entries().len() {
+        let mut reader = zip.reader_with_entry(index).await.unwrap();
+        let mut contents = Vec::new();
+        reader.read_to_end_checked(&mut contents).await.unwrap();
+        files.insert(reader.entry().filename().as_str().unwrap().to_string(), contents);
+    }
+    files
+}
+
+async fn untar(data: Vec<u8>) -> BTreeMap<String, Vec<u8>> {
+    let mut tar = Archive::new(GzipDecoder::new(&data[..]));
+    let mut entries = tar.entries().unwrap();
+    let mut files = BTreeMap::new();
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry.unwrap();
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents).await.unwrap();
+        files.insert(entry.path().unwrap().to_str().unwrap().to_string(), contents);
+    }
+    files
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn zip_whole_bucket(pool: PgPool) {
+    let data = AppData::new(pool).await;
+    let client = data.authorized();
+    // Larger than the pipe between the writing task and the response
+    let large = (0..400_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    client.upload("reports/large.bin", large.clone(), large.len() as u64).await.unwrap();
+    client.upload("notes.txt", "first", 5).await.unwrap();
+    client.upload("notes.txt", "second", 6).await.unwrap();
+    client.upload("notes.txt", "third", 5).await.unwrap();
+
+    let download = client.download_archive(&ArchiveRequest::default()).await.unwrap();
+    assert_eq!(download.content_type(), Some("application/zip"));
+    let files = unzip(download.bytes().await.unwrap().to_vec()).await;
+    assert_eq!(files.keys().collect::<Vec<_>>(), ["notes (1).txt", "notes (2).txt", "notes.txt", "reports/large.bin"]);
+    assert_eq!(files["reports/large.bin"], large);
+    // Duplicates are numbered in upload order
+    assert_eq!(files["notes.txt"], b"first");
+    assert_eq!(files["notes (2).txt"], b"third");
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn tar_gz_of_prefix_and_ids(pool: PgPool) {
+    let data = AppData::new(pool).await;
+    let client = data.authorized();
+    let q3 = client.upload("reports/2026/q3.csv", "a,b", 3).await.unwrap();
+    client.upload("reports/2026/q4.csv", "c,d", 3).await.unwrap();
+    let notes = client.upload("notes.txt", "hello", 5).await.unwrap();
+
+    let request = ArchiveRequest { prefix: Some("reports".to_string()), format: ArchiveFormat::TarGz, ..Default::default() };
+    let download = client.download_archive(&request).await.unwrap();
+    assert_eq!(download.content_type(), Some("application/gzip"));
+    let files = untar(download.bytes().await.unwrap().to_vec()).await;
+    assert_eq!(files.keys().collect::<Vec<_>>(), ["reports/2026/q3.csv", "reports/2026/q4.csv"]);
+
+    let request = ArchiveRequest { ids: Some(vec![q3, notes]), format: ArchiveFormat::TarGz, ..Default::default() };
+    let files = untar(client.download_archive(&request).await.unwrap().bytes().await.unwrap().to_vec()).await;
+    assert_eq!(files["notes.txt"], b"hello");
+    assert_eq!(files["reports/2026/q3.csv"], b"a,b");
+
+    let missing = ArchiveRequest { ids: Some(vec![q3, Uuid::new_v4()]), ..Default::default() };
+    assert!(matches!(client.download_archive(&missing).await, Err(Error::NotFound(_))));
+    let empty = ArchiveRequest { prefix: Some("photos".to_string()), ..Default::default() };
+    assert!(matches!(client.download_archive(&empty).await, Err(Error::NotFound(_))));
+    let both = ArchiveRequest { ids: Some(vec![q3]), prefix: Some("reports".to_string()), ..Default::default() };
+    assert!(matches!(client.download_archive(&both).await, Err(Error::BadRequest(_))));
+}
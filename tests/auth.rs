@@ -1,7 +1,7 @@
+use bucket_storage_client::Error;
 use sqlx::{PgPool, query};
-use tracing::debug;
 use tracing_test::traced_test;
-use bucket_storage::auth::ArgonHash;
+use uuid::Uuid;
 
 mod tools;
 use crate::tools::AppData;
@@ -16,12 +16,7 @@ async fn auth(pool: PgPool) {
     "#).fetch_all(&pool).await.unwrap();
     println!("{a:?}");
     let data = AppData::new(pool).await;
-    let res = data.client()
-        .get(data.api("/download"))
-        .basic_auth("195ea586-110f-454a-a7e6-87bbec64c41c",Some("ee014d6f-5798-44b0-9186-f68f3261146e"))
-        .send().await.unwrap();
-    let body = res.text().await.unwrap();
-    debug!("{body}");
+    data.authorized().verify_key().await.unwrap();
 }
 
 #[traced_test]
@@ -32,3 +27,16 @@ async fn a(pool: PgPool) {
     "#).fetch_all(&pool).await.unwrap();
     println!("{a:?}");
 }
+
+#[traced_test]
+#[sqlx::test]
+async fn issued_key_is_valid(pool: PgPool) {
+    let data = AppData::new(pool).await;
+    let issued = data.client().issue_key().await.unwrap();
+    data.client().with_credentials(issued.id, issued.key).verify_key().await.unwrap();
+
+    let res = data.client().with_credentials(issued.id, "wrong").verify_key().await;
+    assert!(matches!(res, Err(Error::BadRequest(_))));
+    let res = data.client().with_credentials(Uuid::new_v4(), "wrong").verify_key().await;
+    assert!(matches!(res, Err(Error::BadRequest(_))));
+}
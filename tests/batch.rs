@@ -0,0 +1,76 @@
+use std::collections::BTreeMap;
+use bucket_storage_client::Error;
+use sqlx::PgPool;
+use tracing_test::traced_test;
+use uuid::Uuid;
+use bucket_storage::store::Store;
+
+mod tools;
+use crate::tools::AppData;
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn delete_ids_and_prefix(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let store = Store::new(data.store_dir());
+    let client = data.authorized();
+    let first = client.upload("notes.txt", "hello", 5).await.unwrap();
+    let second = client.upload("todo.txt", "todo", 4).await.unwrap();
+    let copy = client.copy(second, "todo-copy.txt").await.unwrap();
+    client.upload("reports/q3.csv", "a,b", 3).await.unwrap();
+    client.upload("reports/q4.csv", "c,d", 3).await.unwrap();
+    let missing = Uuid::new_v4();
+
+    let results = client.delete_many(&[first, missing, second]).await.unwrap();
+    assert_eq!(results.iter().map(|res| res.id).collect::<Vec<_>>(), [first, missing, second]);
+    assert!(results[0].is_success() && results[2].is_success());
+    assert_eq!(results[1].status, 400);
+    assert!(results[1].error.is_some());
+    // The copy still references the blob of the second file
+    assert_eq!(store.blobs().await.unwrap().len(), 3);
+    assert_eq!(&client.download(copy).await.unwrap().bytes().await.unwrap()[..], b"todo");
+
+    let results = client.delete_prefix("reports").await.unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|res| res.is_success()));
+    assert_eq!(client.list().await.unwrap().len(), 1);
+    assert_eq!(store.blobs().await.unwrap().len(), 1);
+
+    assert!(matches!(client.delete_prefix("").await, Err(Error::BadRequest(_))));
+    assert!(matches!(client.delete_many(&vec![missing; 10_001]).await, Err(Error::BadRequest(_))));
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn update_metadata_of_many(pool: PgPool) {
+    let data = AppData::new(pool).await;
+    let client = data.authorized();
+    let first = client.upload("a.txt", "a", 1).await.unwrap();
+    let second = client.upload_with_metadata("b.txt", "b", 1, &BTreeMap::from([("stale".to_string(), "yes".to_string())])).await.unwrap();
+    let missing = Uuid::new_v4();
+
+    let changes = BTreeMap::from([("Project".to_string(), Some("apollo".to_string())), ("stale".to_string(), None)]);
+    let results = client.update_metadata_many(&[first, second, missing], &changes).await.unwrap();
+    assert!(results[0].is_success() && results[1].is_success());
+    assert_eq!(results[2].status, 404);
+    assert_eq!(client.list_tagged("project", "apollo").await.unwrap().len(), 2);
+    assert_eq!(client.metadata(second).await.unwrap(), BTreeMap::from([("project".to_string(), "apollo".to_string())]));
+
+    let fetched = client.metadata_many(&[second, missing, first]).await.unwrap();
+    assert_eq!(fetched.iter().map(|res| res.id).collect::<Vec<_>>(), [second, missing, first]);
+    assert_eq!(fetched[0].metadata, Some(BTreeMap::from([("project".to_string(), "apollo".to_string())])));
+    assert_eq!((fetched[1].status, fetched[1].metadata.is_none()), (404, true));
+    assert!(fetched[2].is_success());
+    assert!(matches!(client.metadata_many(&vec![missing; 10_001]).await, Err(Error::BadRequest(_))));
+
+    // An entry over the limits fails on its own
+    let long = BTreeMap::from([("note".to_string(), Some("x".repeat(1000)))]);
+    client.update_metadata(second, &BTreeMap::from([("other".to_string(), Some("y".repeat(1000)))])).await.unwrap();
+    let many = (0..7).map(|i| (format!("key{i}"), Some("z".repeat(900)))).chain(long).collect();
+    let results = client.update_metadata_many(&[first, second], &many).await.unwrap();
+    assert!(results[0].is_success());
+    assert_eq!(results[1].status, 400);
+
+    let invalid = BTreeMap::from([("bad key".to_string(), Some("x".to_string()))]);
+    assert!(matches!(client.update_metadata_many(&[first], &invalid).await, Err(Error::BadRequest(_))));
+}
@@ -0,0 +1,109 @@
+use axum::body::Bytes;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use sqlx::{PgPool, query};
+use tracing_test::traced_test;
+use bucket_storage::{admin, buckets, scrub};
+use bucket_storage::store::Store;
+use bucket_storage::store::chunking::{self, MAX_SIZE, MIN_SIZE};
+
+mod tools;
+use crate::tools::{AppData, bucket_id};
+
+fn contents(len: usize, seed: u64) -> Vec<u8> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+async fn chunk_count(pool: &PgPool) -> i64 {
+    query!(r#"SELECT COUNT(*) AS "count!" FROM chunks"#).fetch_one(pool).await.unwrap().count
+}
+
+async fn enable_chunking(pool: &PgPool) {
+    buckets::set_chunking(pool, bucket_id(pool).await, true).await.unwrap();
+}
+
+#[test]
+fn boundaries_follow_contents() {
+    let data = Bytes::from(contents(2 * 1024 * 1024, 1));
+    let chunks = chunking::split(&data);
+    assert_eq!(chunks.concat(), &data[..]);
+    assert!(chunks[..chunks.len() - 1].iter().all(|chunk| (MIN_SIZE..=MAX_SIZE).contains(&chunk.len())));
+    assert!(chunks.len() > 8, "Chunks are too large: {}", chunks.len());
+
+    // Inserting bytes only changes the chunks around the insertion
+    let mut edited = data.to_vec();
+    edited.splice(1_000_000..1_000_000, *b"inserted");
+    let edited = chunking::split(&Bytes::from(edited));
+    let shared = edited.iter().filter(|chunk| chunks.contains(chunk)).count();
+    assert!(shared + 2 >= chunks.len(), "Only {shared} of {} chunks are shared", chunks.len());
+
+    assert!(chunking::split(&Bytes::new()).is_empty());
+    assert_eq!(chunking::split(&Bytes::from_static(b"small")).len(), 1);
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn versions_share_chunks(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let store = Store::new(data.store_dir());
+    let client = data.authorized();
+    enable_chunking(&pool).await;
+
+    let first = contents(1024 * 1024, 2);
+    let mut second = first.clone();
+    second.splice(600_000..600_010, *b"new version of the file");
+    let first_id = client.upload("report.bin", first.clone(), first.len() as u64).await.unwrap();
+    let chunks_of_first = chunk_count(&pool).await;
+    let second_id = client.upload("report-v2.bin", second.clone(), second.len() as u64).await.unwrap();
+    let added = chunk_count(&pool).await - chunks_of_first;
+    assert!(added <= 2, "Second version added {added} chunks");
+    assert_eq!(store.blobs().await.unwrap().len() as i64, chunk_count(&pool).await);
+
+    assert_eq!(&client.download(first_id).await.unwrap().bytes().await.unwrap()[..], &first[..]);
+    assert_eq!(&client.download(second_id).await.unwrap().bytes().await.unwrap()[..], &second[..]);
+    for range in [0..1, 65_000..300_000, 599_990..600_100, 1_000_000..second.len() as u64] {
+        let partial = client.download_range(second_id, range.clone()).await.unwrap();
+        assert_eq!(partial.content_length(), Some(range.end - range.start));
+        assert_eq!(&partial.bytes().await.unwrap()[..], &second[range.start as usize..range.end as usize]);
+    }
+    assert!(admin::fsck(&pool, &store).await.unwrap().is_clean());
+
+    client.delete(first_id).await.unwrap();
+    let remaining = chunk_count(&pool).await;
+    assert!(remaining < chunks_of_first + added);
+    let referenced = query!(r#"SELECT COUNT(DISTINCT chunk_id) AS "count!" FROM file_chunks"#).fetch_one(&pool).await.unwrap().count;
+    assert_eq!(remaining, referenced);
+    assert_eq!(store.blobs().await.unwrap().len() as i64, chunk_count(&pool).await);
+    assert_eq!(&client.download(second_id).await.unwrap().bytes().await.unwrap()[..], &second[..]);
+
+    client.delete(second_id).await.unwrap();
+    assert_eq!(chunk_count(&pool).await, 0);
+    assert!(store.blobs().await.unwrap().is_empty());
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn missing_chunk_is_detected(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let store = Store::new(data.store_dir());
+    let client = data.authorized();
+    enable_chunking(&pool).await;
+    let file_id = client.upload("data.bin", contents(300_000, 3), 300_000).await.unwrap();
+
+    let blobs = store.blobs().await.unwrap();
+    assert!(blobs.len() > 1);
+    store.remove_path(&blobs[0]).await.unwrap();
+
+    let report = admin::fsck(&pool, &store).await.unwrap();
+    assert_eq!(report.missing, vec![file_id]);
+    let report = scrub::scrub(&pool, &store, 64 * 1024 * 1024).await.unwrap();
+    assert_eq!(report.corrupted, vec![file_id]);
+    assert!(client.download(file_id).await.is_err());
+
+    // Uploading the same contents again stores the lost chunk back
+    client.upload("again.bin", contents(300_000, 3), 300_000).await.unwrap();
+    assert!(scrub::status(&pool).await.unwrap().corrupted.is_empty());
+    assert!(admin::fsck(&pool, &store).await.unwrap().is_clean());
+    assert_eq!(&client.download(file_id).await.unwrap().bytes().await.unwrap()[..], &contents(300_000, 3)[..]);
+}
@@ -0,0 +1,145 @@
+use axum::body::Bytes;
+use futures::TryStreamExt;
+use reqwest::header::{ACCEPT_ENCODING, CONTENT_ENCODING};
+use sqlx::{PgPool, query};
+use tracing_test::traced_test;
+use uuid::Uuid;
+use bucket_storage::{buckets, files};
+use bucket_storage::store::{Store, StoreFile};
+use bucket_storage::store::compression::{Codec, Compression};
+use bucket_storage::store::crypto::MasterKey;
+
+mod tools;
+use crate::tools::{AppData, KEY, KEY_ID};
+
+fn log_lines(count: usize) -> Vec<u8> {
+    (0..count).map(|i| format!("2023-05-07T12:00:00Z INFO request {i} served in 3ms\n")).collect::<String>().into_bytes()
+}
+
+async fn read(store: &Store, file: &StoreFile, range: Option<std::ops::Range<u64>>) -> Vec<u8> {
+    let chunks: Vec<Bytes> = store.stream(file, range).await.unwrap().try_collect().await.unwrap();
+    chunks.concat()
+}
+
+#[test]
+fn policy_skips_compressed_formats() {
+    assert_eq!(Compression::Auto.codec(Some("json")), Codec::Zstd);
+    assert_eq!(Compression::Auto.codec(Some("LOG")), Codec::Zstd);
+    assert_eq!(Compression::Auto.codec(Some("bin")), Codec::Identity);
+    assert_eq!(Compression::Auto.codec(None), Codec::Identity);
+    assert_eq!(Compression::Always.codec(Some("bin")), Codec::Zstd);
+    assert_eq!(Compression::Always.codec(None), Codec::Zstd);
+    assert_eq!(Compression::Always.codec(Some("png")), Codec::Identity);
+    assert_eq!(Compression::Always.codec(Some("gz")), Codec::Identity);
+    assert_eq!(Compression::Never.codec(Some("txt")), Codec::Identity);
+}
+
+#[tokio::test]
+async fn compressed_ranges() {
+    let dir = tempfile::tempdir().unwrap();
+    let plaintext = log_lines(5_000);
+    for store in [Store::new(dir.path()), Store::new(dir.path()).with_master_key(MasterKey::generate())] {
+        let data_key = store.is_encrypted().then(|| store.new_data_key().unwrap());
+        let file = StoreFile::new(Uuid::new_v4(), Some("log".to_string())).with_data_key(data_key).with_codec(Codec::Zstd);
+        let saved = store.save(&file, Bytes::from(plaintext.clone())).await.unwrap();
+        assert_eq!(saved.codec, Codec::Zstd);
+        assert!(saved.stored_size * 5 < plaintext.len() as u64);
+
+        let file = file.with_blob(Some(saved.blob));
+        let raw = tokio::fs::read(dir.path().join(file.path())).await.unwrap();
+        assert_eq!(raw.len() as u64, saved.stored_size);
+        assert_eq!(read(&store, &file, None).await, plaintext);
+        for range in [0..1, 1_000..90_000, 100_000..u64::MAX] {
+            let end = range.end.min(plaintext.len() as u64) as usize;
+            assert_eq!(read(&store, &file, Some(range.clone())).await, &plaintext[range.start as usize..end]);
+        }
+        assert_eq!(store.checksum(&file).await.unwrap(), bucket_storage::store::checksum(&plaintext));
+    }
+}
+
+#[tokio::test]
+async fn corrupted_stream_is_invalid_data() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::new(dir.path());
+    let file = StoreFile::new(Uuid::new_v4(), None).with_codec(Codec::Zstd);
+    let saved = store.save(&file, Bytes::from(log_lines(1_000))).await.unwrap();
+
+    let file = file.with_blob(Some(saved.blob));
+    let path = dir.path().join(file.path());
+    let mut raw = tokio::fs::read(&path).await.unwrap();
+    raw.truncate(raw.len() / 2);
+    tokio::fs::write(&path, raw).await.unwrap();
+    assert_eq!(store.checksum(&file).await.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn incompressible_contents_are_stored_raw() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::new(dir.path());
+    let file = StoreFile::new(Uuid::new_v4(), Some("txt".to_string())).with_codec(Codec::Zstd);
+    let saved = store.save(&file, Bytes::from_static(b"hello")).await.unwrap();
+    assert_eq!(saved.codec, Codec::Identity);
+    assert_eq!(saved.stored_size, 5);
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn download_decompresses_or_passes_through(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let client = data.authorized();
+    let plaintext = log_lines(2_000);
+    let file_id = client.upload("server.log", plaintext.clone(), plaintext.len() as u64).await.unwrap();
+
+    let rec = query!("SELECT codec, size, stored_size FROM files WHERE id = $1", file_id).fetch_one(&pool).await.unwrap();
+    assert_eq!(rec.codec, "zstd");
+    assert_eq!(rec.size, plaintext.len() as i64);
+    assert!(rec.stored_size < rec.size);
+
+    let download = client.download(file_id).await.unwrap();
+    assert_eq!(download.content_length(), Some(plaintext.len() as u64));
+    assert_eq!(&download.bytes().await.unwrap()[..], &plaintext[..]);
+    let partial = client.download_range(file_id, 50_000..50_100).await.unwrap().bytes().await.unwrap();
+    assert_eq!(&partial[..], &plaintext[50_000..50_100]);
+
+    let res = reqwest::Client::new()
+        .get(data.api(&format!("/download/{file_id}")))
+        .basic_auth(KEY_ID, Some(KEY))
+        .header(ACCEPT_ENCODING, "gzip, zstd;q=0.9")
+        .send().await.unwrap();
+    assert_eq!(res.headers()[CONTENT_ENCODING], "zstd");
+    assert_eq!(res.content_length(), Some(rec.stored_size as u64));
+    let encoded = res.bytes().await.unwrap();
+    assert_eq!(zstd::decode_all(&encoded[..]).unwrap(), plaintext);
+
+    let res = reqwest::Client::new()
+        .get(data.api(&format!("/download/{file_id}")))
+        .basic_auth(KEY_ID, Some(KEY))
+        .header(ACCEPT_ENCODING, "zstd;q=0")
+        .send().await.unwrap();
+    assert!(res.headers().get(CONTENT_ENCODING).is_none());
+    assert_eq!(&res.bytes().await.unwrap()[..], &plaintext[..]);
+}
+
+#[traced_test]
+#[sqlx::test]
+async fn bucket_policy(pool: PgPool) {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::new(dir.path());
+    let mut transaction = pool.begin().await.unwrap();
+    let bucket_id = buckets::create_bucket(&mut transaction, "logs").await.unwrap();
+    transaction.commit().await.unwrap();
+    buckets::set_compression(&pool, bucket_id, Compression::Always).await.unwrap();
+
+    let mut transaction = pool.begin().await.unwrap();
+    let plain = files::save_file(&mut transaction, &store, bucket_id, "dump", None, Bytes::from(log_lines(100))).await.unwrap();
+    let image = files::save_file(&mut transaction, &store, bucket_id, "photo", Some("png".to_string()), Bytes::from(log_lines(200))).await.unwrap();
+    transaction.commit().await.unwrap();
+
+    for (file_id, codec) in [(plain, "zstd"), (image, "identity")] {
+        let rec = query!("SELECT codec FROM files WHERE id = $1", file_id).fetch_one(&pool).await.unwrap();
+        assert_eq!(rec.codec, codec);
+    }
+
+    let usage = buckets::usage(&pool, Some(bucket_id)).await.unwrap();
+    assert!(usage[0].stored_bytes < usage[0].bytes);
+}
@@ -0,0 +1,101 @@
+use axum::body::Bytes;
+use futures::TryStreamExt;
+use sqlx::{PgPool, query};
+use tracing_test::traced_test;
+use uuid::Uuid;
+use bucket_storage::{admin, buckets, files};
+use bucket_storage::store::{Store, StoreFile};
+use bucket_storage::store::crypto::MasterKey;
+
+mod tools;
+use crate::tools::AppData;
+
+fn contents(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+async fn read(store: &Store, file: &StoreFile, range: Option<std::ops::Range<u64>>) -> Vec<u8> {
+    let chunks: Vec<Bytes> = store.stream(file, range).await.unwrap().try_collect().await.unwrap();
+    chunks.concat()
+}
+
+#[tokio::test]
+async fn encrypted_ranges() {
+    let dir = tempfile::tempdir().unwrap();
+    let master_key = MasterKey::generate();
+    let store = Store::new(dir.path()).with_master_key(master_key.clone());
+    let plaintext = contents(200_000);
+    let file = StoreFile::new(Uuid::new_v4(), None).with_data_key(Some(store.new_data_key().unwrap()));
+    let saved = store.save(&file, Bytes::from(plaintext.clone())).await.unwrap();
+
+    let file = file.with_blob(Some(saved.blob));
+    let raw = tokio::fs::read(dir.path().join(file.path())).await.unwrap();
+    assert!(!raw.windows(64).any(|window| window == &plaintext[1000..1064]));
+
+    assert_eq!(read(&store, &file, None).await, plaintext);
+    for range in [0..1, 65_530..131_080, 131_072..200_000, 199_999..200_000, 10..u64::MAX] {
+        let end = range.end.min(plaintext.len() as u64) as usize;
+        assert_eq!(read(&store, &file, Some(range.clone())).await, &plaintext[range.start as usize..end]);
+    }
+
+    let other_key = Store::new(dir.path()).with_master_key(MasterKey::generate());
+    assert!(other_key.stream(&file, None).await.is_err());
+}
+
+#[tokio::test]
+async fn tampered_blob_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::new(dir.path()).with_master_key(MasterKey::generate());
+    let file = StoreFile::new(Uuid::new_v4(), Some("bin".to_string())).with_data_key(Some(store.new_data_key().unwrap()));
+    let saved = store.save(&file, Bytes::from(contents(70_000))).await.unwrap();
+
+    let file = file.with_blob(Some(saved.blob));
+    let path = dir.path().join(file.path());
+    let mut raw = tokio::fs::read(&path).await.unwrap();
+    raw.truncate(raw.len() - 100);
+    tokio::fs::write(&path, raw).await.unwrap();
+    assert!(store.checksum(&file).await.is_err());
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn download_encrypted_range(pool: PgPool) {
+    let data = AppData::with_store(pool, |dir| Store::new(dir).with_master_key(MasterKey::generate())).await;
+    let client = data.authorized();
+    let plaintext = contents(100_000);
+    let file_id = client.upload("data.bin", plaintext.clone(), plaintext.len() as u64).await.unwrap();
+
+    let blobs = Store::new(data.store_dir()).blobs().await.unwrap();
+    let raw = tokio::fs::read(data.store_dir().join(&blobs[0])).await.unwrap();
+    assert_ne!(raw, plaintext);
+
+    let full = client.download(file_id).await.unwrap().bytes().await.unwrap();
+    assert_eq!(&full[..], &plaintext[..]);
+    let partial = client.download_range(file_id, 65_000..70_000).await.unwrap();
+    assert_eq!(partial.content_length(), Some(5_000));
+    assert_eq!(&partial.bytes().await.unwrap()[..], &plaintext[65_000..70_000]);
+    assert!(client.download_range(file_id, 100_000..100_010).await.is_err());
+}
+
+#[traced_test]
+#[sqlx::test]
+async fn dedup_within_bucket_and_rotation(pool: PgPool) {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::new(dir.path()).with_master_key(MasterKey::generate());
+    let mut transaction = pool.begin().await.unwrap();
+    let first = buckets::create_bucket(&mut transaction, "first").await.unwrap();
+    let second = buckets::create_bucket(&mut transaction, "second").await.unwrap();
+    let a = files::save_file(&mut transaction, &store, first, "a", None, Bytes::from_static(b"same")).await.unwrap();
+    let b = files::save_file(&mut transaction, &store, first, "b", Some("txt".to_string()), Bytes::from_static(b"other")).await.unwrap();
+    let c = files::save_file(&mut transaction, &store, second, "c", None, Bytes::from_static(b"same")).await.unwrap();
+    transaction.commit().await.unwrap();
+    assert_ne!(a, c);
+    assert_ne!(a, b);
+    assert_eq!(query!("SELECT COUNT(*) AS \"count!\" FROM data_keys").fetch_one(&pool).await.unwrap().count, 2);
+
+    let new_key = MasterKey::generate();
+    assert_eq!(admin::rotate_master_key(&pool, &store, &new_key).await.unwrap(), 2);
+    let rotated = Store::new(dir.path()).with_master_key(new_key);
+    assert!(admin::fsck(&pool, &rotated).await.unwrap().is_clean());
+    assert_eq!(admin::fsck(&pool, &store).await.unwrap().corrupted.len(), 3);
+}
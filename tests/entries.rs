@@ -0,0 +1,70 @@
+use bucket_storage_client::Error;
+use reqwest::StatusCode;
+use sqlx::PgPool;
+use tracing_test::traced_test;
+use uuid::Uuid;
+use bucket_storage::store::Store;
+
+mod tools;
+use crate::tools::AppData;
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn rename_and_copy(pool: PgPool) {
+    let data = AppData::new(pool).await;
+    let store = Store::new(data.store_dir());
+    let client = data.authorized();
+    let file_id = client.upload("drafts/report.csv", "a,b", 3).await.unwrap();
+
+    client.rename(file_id, "final/report-2026.csv").await.unwrap();
+    let entries = client.list().await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].file_name(), "final/report-2026.csv");
+    assert!(matches!(client.rename(file_id, "final/report.txt").await, Err(Error::BadRequest(_))));
+    assert!(matches!(client.rename(file_id, "../report.csv").await, Err(Error::BadRequest(_))));
+    assert!(matches!(client.rename(Uuid::new_v4(), "report.csv").await, Err(Error::NotFound(_))));
+
+    let copy = client.copy(file_id, "backup/report.csv").await.unwrap();
+    assert_ne!(copy, file_id);
+    assert_eq!(client.list().await.unwrap().len(), 2);
+    assert_eq!(store.blobs().await.unwrap().len(), 1);
+    match client.rename(file_id, "backup/report.csv").await {
+        Err(Error::Api { status, .. }) => assert_eq!(status, StatusCode::CONFLICT),
+        other => panic!("Move onto an existing entry was accepted: {other:?}"),
+    }
+
+    // The blob is shared and stays until the last entry referencing it is gone
+    client.delete(file_id).await.unwrap();
+    assert_eq!(&client.download(copy).await.unwrap().bytes().await.unwrap()[..], b"a,b");
+    client.delete(copy).await.unwrap();
+    assert!(store.blobs().await.unwrap().is_empty());
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn copy_and_move_across_buckets(pool: PgPool) {
+    let data = AppData::new(pool).await;
+    let store = Store::new(data.store_dir());
+    let client = data.authorized();
+    let issued = data.client().issue_key().await.unwrap();
+    let other = data.client().with_credentials(issued.id, issued.key);
+    let file_id = client.upload("shared/notes.txt", "hello", 5).await.unwrap();
+
+    let copy = client.copy_to(file_id, &other, None).await.unwrap();
+    let entries = other.list().await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].id, copy);
+    assert_eq!(entries[0].file_name(), "shared/notes.txt");
+    assert_eq!(store.blobs().await.unwrap().len(), 1);
+
+    // Both buckets need valid credentials
+    let forged = data.client().with_credentials(issued.id, "wrong");
+    assert!(matches!(client.copy_to(file_id, &forged, None).await, Err(Error::BadRequest(_))));
+    assert!(matches!(forged.copy_to(file_id, &other, None).await, Err(Error::BadRequest(_))));
+
+    let moved = client.move_to(file_id, &other, Some("inbox/notes.txt".to_string())).await.unwrap();
+    assert!(client.list().await.unwrap().is_empty());
+    assert_eq!(other.list().await.unwrap().len(), 2);
+    assert_eq!(&other.download(moved).await.unwrap().bytes().await.unwrap()[..], b"hello");
+    assert!(matches!(client.download(file_id).await, Err(Error::NotFound(_))));
+}
@@ -0,0 +1,90 @@
+use async_compression::tokio::write::GzipEncoder;
+use async_zip::{Compression, ZipEntryBuilder};
+use async_zip::tokio::write::ZipFileWriter;
+use bucket_storage_client::Error;
+use reqwest::StatusCode;
+use sqlx::{PgPool, query};
+use tokio::io::AsyncWriteExt;
+use tokio_tar::{Builder, Header};
+use tracing_test::traced_test;
+
+mod tools;
+use crate::tools::AppData;
+
+async fn zip(members: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut zip = ZipFileWriter::with_tokio(Vec::new());
+    for (name, contents) in members {
+        let entry = ZipEntryBuilder::new(name.to_string().into(), Compression::Deflate);
+        zip.write_entry_whole(entry, contents).await.unwrap();
+    }
+    zip.close().await.unwrap().into_inner()
+}
+
+async fn tar_gz(members: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut tar = Builder::new(Vec::new());
+    for (name, contents) in members {
+        let mut header = Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        tar.append_data(&mut header, name, *contents).await.unwrap();
+    }
+    let mut gz = GzipEncoder::new(Vec::new());
+    gz.write_all(&tar.into_inner().await.unwrap()).await.unwrap();
+    gz.shutdown().await.unwrap();
+    gz.into_inner()
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn extract_zip_and_tar_gz(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let client = data.authorized();
+    client.upload("existing.txt", "shared", 6).await.unwrap();
+
+    let archive = zip(&[("docs/", b""), ("docs/a.txt", b"shared"), ("docs/sub/b.md", b"# b")]).await;
+    let ids = client.upload_archive("imports/bundle.zip", archive.clone(), archive.len() as u64).await.unwrap();
+    assert_eq!(ids.len(), 2);
+    let names = client.list_prefix("imports").await.unwrap().into_iter().map(|entry| entry.file_name()).collect::<Vec<_>>();
+    assert_eq!(names, ["imports/docs/a.txt", "imports/docs/sub/b.md"]);
+    assert_eq!(&client.download(ids[0]).await.unwrap().bytes().await.unwrap()[..], b"shared");
+    // Members go through the same deduplication as uploads
+    let files = query!(r#"SELECT COUNT(*) AS "count!" FROM files"#).fetch_one(&pool).await.unwrap().count;
+    assert_eq!(files, 2);
+
+    let archive = tar_gz(&[("./c.txt", b"c"), ("d/e", b"e")]).await;
+    let ids = client.upload_archive("bundle.tar.gz", archive.clone(), archive.len() as u64).await.unwrap();
+    assert_eq!(ids.len(), 2);
+    assert_eq!(client.list_prefix("d").await.unwrap()[0].file_name(), "d/e");
+    assert!(client.list().await.unwrap().iter().any(|entry| entry.file_name() == "c.txt"));
+
+    // Without asking for extraction the archive is stored as it is
+    let archive = zip(&[("f.txt", b"f")]).await;
+    client.upload("bundle.zip", archive.clone(), archive.len() as u64).await.unwrap();
+    assert!(client.list().await.unwrap().iter().any(|entry| entry.file_name() == "bundle.zip"));
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn reject_unsafe_archives(pool: PgPool) {
+    let data = AppData::new(pool).await;
+    let client = data.authorized();
+
+    let archive = zip(&[("fine.txt", b"fine"), ("../escape.txt", b"evil")]).await;
+    let res = client.upload_archive("bundle.zip", archive.clone(), archive.len() as u64).await;
+    assert!(matches!(res, Err(Error::BadRequest(_))));
+
+    let archive = zip(&[("/etc/passwd", b"evil")]).await;
+    let res = client.upload_archive("bundle.zip", archive.clone(), archive.len() as u64).await;
+    assert!(matches!(res, Err(Error::BadRequest(_))));
+
+    // Compresses about a thousand times, beyond the allowed ratio
+    let zeros = vec![0u8; 2 * 1024 * 1024];
+    let archive = zip(&[("zeros.bin", &zeros)]).await;
+    let res = client.upload_archive("bomb.zip", archive.clone(), archive.len() as u64).await;
+    assert!(matches!(res, Err(Error::Api { status: StatusCode::PAYLOAD_TOO_LARGE, .. })));
+
+    let res = client.upload_archive("broken.zip", "not a zip", 9).await;
+    assert!(matches!(res, Err(Error::BadRequest(_))));
+    // Nothing of a rejected archive is kept
+    assert!(client.list().await.unwrap().is_empty());
+}
@@ -1,4 +1,16 @@
-use axum::extract::Multipart;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use axum::Router;
+use axum::http::StatusCode;
+use bucket_storage_client::{Client, Error, RetryPolicy};
+use sqlx::PgPool;
+use tracing_test::traced_test;
+use uuid::Uuid;
+
+mod tools;
+use crate::tools::AppData;
 
 // #[tokio::test]
 // async fn upload() {
@@ -15,3 +27,62 @@ use axum::extract::Multipart;
 //
 //     }
 // }
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn upload_list_download(pool: PgPool) {
+    let data = AppData::new(pool).await;
+    let client = data.authorized();
+    let file_id = client.upload("notes.txt", "hello", 5).await.unwrap();
+
+    let entries = client.list().await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].id, file_id);
+    assert_eq!(entries[0].file_name(), "notes.txt");
+    assert_eq!(entries[0].size, 5);
+
+    let contents = client.download(file_id).await.unwrap().bytes().await.unwrap();
+    assert_eq!(&contents[..], b"hello");
+    let partial = client.download_range(file_id, 1..3).await.unwrap().bytes().await.unwrap();
+    assert_eq!(&partial[..], b"el");
+
+    let res = client.download(Uuid::new_v4()).await;
+    assert!(matches!(res, Err(Error::NotFound(_))));
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn upload_with_key_and_delete(pool: PgPool) {
+    let data = AppData::new(pool).await;
+    let client = data.authorized();
+    let upload_key = client.upload_key().await.unwrap();
+    let file_id = data.client().upload_with_key(upload_key.upload_id, "public.bin", vec![1u8, 2, 3], 3).await.unwrap();
+    assert_eq!(client.list().await.unwrap().len(), 1);
+
+    client.delete(file_id).await.unwrap();
+    assert!(client.list().await.unwrap().is_empty());
+    assert!(matches!(client.delete(file_id).await, Err(Error::BadRequest(_))));
+}
+
+#[tokio::test]
+async fn only_reads_are_retried() {
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let counter = attempts.clone();
+    let app = Router::new().fallback(move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+        async { StatusCode::SERVICE_UNAVAILABLE }
+    });
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+
+    let retry = RetryPolicy { max_retries: 2, initial_backoff: Duration::from_millis(1), max_backoff: Duration::from_millis(1) };
+    let client = Client::new(format!("http://{addr}")).with_retry(retry);
+    assert!(matches!(client.list().await, Err(Error::Server(_))));
+    assert_eq!(attempts.swap(0, Ordering::SeqCst), 3);
+
+    assert!(matches!(client.copy(Uuid::new_v4(), "copy.txt").await, Err(Error::Server(_))));
+    assert!(matches!(client.issue_key().await, Err(Error::Server(_))));
+    assert!(matches!(client.delete(Uuid::new_v4()).await, Err(Error::Server(_))));
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+}
@@ -0,0 +1,120 @@
+use std::time::Duration;
+use bucket_storage_client::Error;
+use reqwest::StatusCode;
+use sqlx::PgPool;
+use tracing_test::traced_test;
+use bucket_storage::buckets;
+use bucket_storage::files::paths::{normalize_path, normalize_prefix};
+
+mod tools;
+use crate::tools::{AppData, bucket_id};
+
+#[test]
+fn paths_are_validated() {
+    assert_eq!(normalize_path("reports/2026/q3.csv").unwrap(), "reports/2026/q3.csv");
+    assert_eq!(normalize_path("v1.2/notes").unwrap(), "v1.2/notes");
+    for path in ["", "/etc/passwd", "a//b", "a/", "../a", "a/./b", "a/../../b", "a\\..\\b", "a/\0b", &"a/".repeat(600)] {
+        assert!(normalize_path(path).is_err(), "{path:?} accepted");
+    }
+
+    assert_eq!(normalize_prefix("").unwrap(), "");
+    assert_eq!(normalize_prefix("/").unwrap(), "");
+    assert_eq!(normalize_prefix("reports").unwrap(), "reports/");
+    assert_eq!(normalize_prefix("reports/2026/").unwrap(), "reports/2026/");
+    assert!(normalize_prefix("reports/../..").is_err());
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn list_with_delimiter(pool: PgPool) {
+    let data = AppData::new(pool).await;
+    let client = data.authorized();
+    let q3 = client.upload("reports/2026/q3.csv", "a,b", 3).await.unwrap();
+    client.upload("reports/2026/q4.csv", "c,d", 3).await.unwrap();
+    client.upload("reports/2025.v2/summary", "old", 3).await.unwrap();
+    client.upload("reports/index.md", "# reports", 9).await.unwrap();
+    client.upload("notes.txt", "hello", 5).await.unwrap();
+    assert!(matches!(client.upload("../escape.txt", "x", 1).await, Err(Error::BadRequest(_))));
+
+    let root = client.folder("").await.unwrap();
+    assert_eq!(root.common_prefixes, ["reports/"]);
+    assert_eq!(root.entries.len(), 1);
+    assert_eq!(root.entries[0].file_name(), "notes.txt");
+
+    let reports = client.folder("reports").await.unwrap();
+    assert_eq!(reports.prefix, "reports/");
+    assert_eq!(reports.common_prefixes, ["reports/2025.v2/", "reports/2026/"]);
+    assert_eq!(reports.entries.len(), 1);
+    assert_eq!(reports.entries[0].extension.as_deref(), Some("md"));
+
+    let year = client.folder("reports/2026/").await.unwrap();
+    assert!(year.common_prefixes.is_empty());
+    assert_eq!(year.entries[0].id, q3);
+    assert_eq!(year.entries[0].name, "reports/2026/q3");
+    assert_eq!(client.folder("reports/2025.v2").await.unwrap().entries[0].extension, None);
+
+    assert_eq!(client.list_prefix("reports").await.unwrap().len(), 4);
+    assert_eq!(client.list().await.unwrap().len(), 5);
+    assert!(matches!(client.folder("a//b").await, Err(Error::BadRequest(_))));
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn move_and_delete_folders(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let client = data.authorized();
+    buckets::set_trash_retention(&pool, bucket_id(&pool).await, Duration::from_secs(3600)).await.unwrap();
+    let q3 = client.upload("reports/2026/q3.csv", "a,b", 3).await.unwrap();
+    client.upload("reports/2026/q4.csv", "c,d", 3).await.unwrap();
+    client.upload("reports-old/q1.csv", "e,f", 3).await.unwrap();
+
+    // Renaming keeps the folder in its parent, the sibling sharing the name prefix stays
+    assert_eq!(client.move_folder("reports", "archive").await.unwrap(), 2);
+    assert_eq!(client.folder("").await.unwrap().common_prefixes, ["archive/", "reports-old/"]);
+    assert_eq!(&client.download(q3).await.unwrap().bytes().await.unwrap()[..], b"a,b");
+
+    assert_eq!(client.move_folder("archive/2026", "2026").await.unwrap(), 2);
+    assert_eq!(client.folder("2026").await.unwrap().entries.len(), 2);
+    assert!(matches!(client.move_folder("archive", "other").await, Err(Error::NotFound(_))));
+    assert!(matches!(client.move_folder("", "other").await, Err(Error::BadRequest(_))));
+    assert!(matches!(client.move_folder("2026", "../up").await, Err(Error::BadRequest(_))));
+    // A valid folder too long to hold the moved paths
+    let long = format!("{}/{}", vec!["a".repeat(200); 5].join("/"), "b".repeat(15));
+    assert!(matches!(client.move_folder("2026", &long).await, Err(Error::BadRequest(_))));
+    let taken = client.upload("taken/q4.csv", "g,h", 3).await.unwrap();
+    match client.move_folder("2026", "taken").await {
+        Err(Error::Api { status, .. }) => assert_eq!(status, StatusCode::CONFLICT),
+        other => panic!("Move onto existing entries was accepted: {other:?}"),
+    }
+    assert_eq!(client.folder("2026").await.unwrap().entries.len(), 2);
+    client.delete(taken).await.unwrap();
+    client.purge(taken).await.unwrap();
+
+    assert_eq!(client.delete_folder("2026").await.unwrap(), 2);
+    assert_eq!(client.list().await.unwrap().len(), 1);
+    assert_eq!(client.trash().await.unwrap().len(), 2);
+    assert!(matches!(client.delete_folder("2026").await, Err(Error::NotFound(_))));
+    assert!(matches!(client.delete_folder("/").await, Err(Error::BadRequest(_))));
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn deleted_entries_stay_behind(pool: PgPool) {
+    buckets::set_versioning(&pool, bucket_id(&pool).await, true).await.unwrap();
+    let data = AppData::new(pool).await;
+    let client = data.authorized();
+    let deleted = client.upload("docs/a.txt", "old", 3).await.unwrap();
+    client.delete(deleted).await.unwrap();
+    client.upload("docs/b.txt", "b", 1).await.unwrap();
+    let live = client.upload("archive/a.txt", "new", 3).await.unwrap();
+
+    assert_eq!(client.move_folder("docs", "archive").await.unwrap(), 1);
+    let version = client.versions(deleted).await.unwrap().into_iter().find(|version| !version.deleted).unwrap();
+    client.restore_version(deleted, version.id).await.unwrap();
+
+    let mut paths = client.list().await.unwrap().iter().map(|entry| entry.file_name()).collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(paths, ["archive/a.txt", "archive/b.txt", "docs/a.txt"]);
+    assert_eq!(&client.download(live).await.unwrap().bytes().await.unwrap()[..], b"new");
+    assert_eq!(&client.download(deleted).await.unwrap().bytes().await.unwrap()[..], b"old");
+}
@@ -0,0 +1,124 @@
+use std::io::Cursor;
+use bucket_storage_client::{Error, ImageFit, ImageFormat, ImageQuery};
+use image::{ImageFormat as Encoding, Rgba, RgbaImage};
+use futures::future;
+use reqwest::StatusCode;
+use sqlx::{PgPool, query};
+use tracing_test::traced_test;
+use uuid::Uuid;
+use bucket_storage::admin;
+use bucket_storage::images::{MAX_DERIVATIVES, SIZES};
+use bucket_storage::store::{Store, StoreFile};
+
+mod tools;
+use crate::tools::AppData;
+
+fn png(width: u32, height: u32) -> Vec<u8> {
+    let image = RgbaImage::from_fn(width, height, |x, y| Rgba([(x % 256) as u8, (y % 256) as u8, 128, 255]));
+    let mut encoded = Cursor::new(Vec::new());
+    image.write_to(&mut encoded, Encoding::Png).unwrap();
+    encoded.into_inner()
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn resize_and_convert(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let client = data.authorized();
+    let source = png(400, 200);
+    let file = client.upload("photos/wide.png", source.clone(), source.len() as u64).await.unwrap();
+
+    let query = ImageQuery { width: Some(100), format: Some(ImageFormat::Webp), ..Default::default() };
+    let download = client.image(file, &query).await.unwrap();
+    assert_eq!(download.content_type(), Some("image/webp"));
+    let image = image::load_from_memory(&download.bytes().await.unwrap()).unwrap();
+    assert_eq!((image.width(), image.height()), (128, 64));
+
+    // The second request is served from the saved derivative
+    let again = client.image(file, &query).await.unwrap().bytes().await.unwrap();
+    assert_eq!(image::load_from_memory(&again).unwrap().width(), 128);
+    client.image(file, &ImageQuery { width: Some(120), ..query.clone() }).await.unwrap();
+    let derivatives = query!(r#"SELECT variant FROM derivatives"#).fetch_all(&pool).await.unwrap();
+    assert_eq!(derivatives.len(), 1);
+    assert_eq!(derivatives[0].variant, "128xauto-contain.webp");
+
+    let query = ImageQuery { width: Some(50), height: Some(50), fit: Some(ImageFit::Cover), ..Default::default() };
+    let download = client.image(file, &query).await.unwrap();
+    assert_eq!(download.content_type(), Some("image/png"));
+    let image = image::load_from_memory(&download.bytes().await.unwrap()).unwrap();
+    assert_eq!((image.width(), image.height()), (64, 64));
+
+    // Asking for no change serves the stored image without saving a copy
+    let original = client.image(file, &ImageQuery::default()).await.unwrap().bytes().await.unwrap();
+    assert_eq!(&original[..], &source[..]);
+    let derivatives = query!(r#"SELECT COUNT(*) AS "count!" FROM derivatives"#).fetch_one(&pool).await.unwrap().count;
+    assert_eq!(derivatives, 2);
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn reject_and_release(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let store = Store::new(data.store_dir());
+    let client = data.authorized();
+    let source = png(64, 64);
+    let file = client.upload("icon.png", source.clone(), source.len() as u64).await.unwrap();
+    let text = client.upload("notes.txt", "hello", 5).await.unwrap();
+
+    let res = client.image(text, &ImageQuery::default()).await;
+    assert!(matches!(res, Err(Error::Api { status: StatusCode::UNSUPPORTED_MEDIA_TYPE, .. })));
+    let res = client.image(file, &ImageQuery { width: Some(0), ..Default::default() }).await;
+    assert!(matches!(res, Err(Error::BadRequest(_))));
+    let res = client.image(file, &ImageQuery { width: Some(10), fit: Some(ImageFit::Fill), ..Default::default() }).await;
+    assert!(matches!(res, Err(Error::BadRequest(_))));
+    assert!(matches!(client.image(Uuid::new_v4(), &ImageQuery::default()).await, Err(Error::NotFound(_))));
+
+    for format in [ImageFormat::Jpeg, ImageFormat::Webp] {
+        client.image(file, &ImageQuery { width: Some(16), format: Some(format), ..Default::default() }).await.unwrap().bytes().await.unwrap();
+    }
+    assert_eq!(store.blobs().await.unwrap().len(), 4);
+
+    // Derivatives go away with their source
+    client.delete(file).await.unwrap();
+    let derivatives = query!(r#"SELECT COUNT(*) AS "count!" FROM derivatives"#).fetch_one(&pool).await.unwrap().count;
+    assert_eq!(derivatives, 0);
+    assert_eq!(store.blobs().await.unwrap().len(), 1);
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn concurrent_requests_respect_the_cap(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let client = data.authorized();
+    let source = png(40, 20);
+    let file = client.upload("small.png", source.clone(), source.len() as u64).await.unwrap();
+
+    let queries = SIZES[..6].iter()
+        .flat_map(|width| SIZES[..6].iter().map(|height| ImageQuery { width: Some(*width), height: Some(*height), fit: Some(ImageFit::Fill), ..Default::default() }))
+        .collect::<Vec<_>>();
+    assert!(queries.len() as i64 > MAX_DERIVATIVES);
+    let served = future::join_all(queries.iter().map(|query| client.image(file, query))).await;
+    assert!(served.into_iter().all(|res| res.is_ok()));
+    let derivatives = query!(r#"SELECT COUNT(*) AS "count!" FROM derivatives"#).fetch_one(&pool).await.unwrap().count;
+    assert_eq!(derivatives, MAX_DERIVATIVES);
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn repair_restores_derivatives(pool: PgPool) {
+    let data = AppData::with_store(pool.clone(), |dir| Store::with_roots(vec![dir.join("a"), dir.join("b")], 2)).await;
+    let store = Store::with_roots(vec![data.store_dir().join("a"), data.store_dir().join("b")], 2);
+    let client = data.authorized();
+    let source = png(400, 200);
+    let file = client.upload("wide.png", source.clone(), source.len() as u64).await.unwrap();
+    client.image(file, &ImageQuery { width: Some(100), ..Default::default() }).await.unwrap();
+
+    let rec = query!("SELECT id, blob FROM derivatives").fetch_one(&pool).await.unwrap();
+    let derivative = StoreFile::new(rec.id, None).with_blob(Some(rec.blob));
+    tokio::fs::remove_file(data.store_dir().join("a").join(derivative.path())).await.unwrap();
+
+    let report = admin::repair(&pool, &store).await.unwrap();
+    assert_eq!(report.checked, 2);
+    assert_eq!(report.restored, vec![rec.id]);
+    assert!(data.store_dir().join("a").join(derivative.path()).exists());
+}
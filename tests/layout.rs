@@ -0,0 +1,86 @@
+use std::time::Duration;
+use axum::body::Bytes;
+use futures::TryStreamExt;
+use sqlx::{PgPool, query};
+use tracing_test::traced_test;
+use uuid::Uuid;
+use bucket_storage::{admin, buckets, files};
+use bucket_storage::store::{Store, StoreFile};
+
+mod tools;
+use crate::tools::AppData;
+
+/// Puts the blob of `file_id` back at its flat `<id>.<extension>` path as it was stored before the sharded layout
+async fn flatten(pool: &PgPool, store_dir: &std::path::Path, file_id: Uuid) {
+    let rec = query!("SELECT extension, blob FROM files WHERE id = $1", file_id).fetch_one(pool).await.unwrap();
+    let sharded = StoreFile::new(file_id, rec.extension.clone()).with_blob(rec.blob);
+    let flat = StoreFile::new(file_id, rec.extension);
+    tokio::fs::rename(store_dir.join(sharded.path()), store_dir.join(flat.path())).await.unwrap();
+    query!("UPDATE files SET blob = NULL WHERE id = $1", file_id).execute(pool).await.unwrap();
+}
+
+#[traced_test]
+#[sqlx::test]
+async fn blobs_are_sharded_by_content(pool: PgPool) {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::new(dir.path());
+    let mut transaction = pool.begin().await.unwrap();
+    let bucket_id = buckets::create_bucket(&mut transaction, "layout").await.unwrap();
+    let file_id = files::save_file(&mut transaction, &store, bucket_id, "photo", Some("../png".to_string()), Bytes::from_static(b"pixels")).await.unwrap();
+    transaction.commit().await.unwrap();
+
+    let blob = query!("SELECT blob FROM files WHERE id = $1", file_id).fetch_one(&pool).await.unwrap().blob.unwrap();
+    assert_eq!(blob.len(), 64);
+    let blobs = store.blobs().await.unwrap();
+    assert_eq!(blobs, vec![[&blob[..2], &blob[2..4], &blob].iter().collect::<std::path::PathBuf>()]);
+    assert!(admin::fsck(&pool, &store).await.unwrap().is_clean());
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn relayout_while_serving(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let store = Store::new(data.store_dir());
+    let client = data.authorized();
+    let first = client.upload("first.txt", "first", 5).await.unwrap();
+    let second = client.upload("second", "second", 6).await.unwrap();
+    flatten(&pool, data.store_dir(), first).await;
+    flatten(&pool, data.store_dir(), second).await;
+    assert_eq!(&client.download(first).await.unwrap().bytes().await.unwrap()[..], b"first");
+
+    let open = store.stream(&StoreFile::new(first, Some("txt".to_string())), None).await.unwrap();
+    let report = admin::relayout(&pool, &store, 1, Duration::ZERO).await.unwrap();
+    assert_eq!(report.moved, 2);
+    assert!(report.missing.is_empty());
+
+    let chunks: Vec<Bytes> = open.try_collect().await.unwrap();
+    assert_eq!(chunks.concat(), b"first");
+    assert_eq!(&client.download(first).await.unwrap().bytes().await.unwrap()[..], b"first");
+    assert_eq!(&client.download(second).await.unwrap().bytes().await.unwrap()[..], b"second");
+    assert!(store.blobs().await.unwrap().iter().all(|path| path.components().count() == 3));
+    assert!(admin::fsck(&pool, &store).await.unwrap().is_clean());
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn shared_blob_survives_delete(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let store = Store::new(data.store_dir());
+    let client = data.authorized();
+    let first = client.upload("a.bin", "same", 4).await.unwrap();
+
+    // Two rows pointing at one address, as left behind by racing uploads
+    let copy = query!(r#"
+    INSERT INTO files (extension, checksum, size, stored_size, blob)
+    SELECT extension, checksum, size, stored_size, blob FROM files WHERE id = $1
+    RETURNING id
+    "#, first).fetch_one(&pool).await.unwrap().id;
+    query!(r#"
+    INSERT INTO bucket_files (name, bucket_id, file_id, entry_id)
+    SELECT 'b', bucket_id, $1, $1 FROM bucket_files WHERE file_id = $2
+    "#, copy, first).execute(&pool).await.unwrap();
+
+    client.delete(first).await.unwrap();
+    assert_eq!(store.blobs().await.unwrap().len(), 1);
+    assert_eq!(&client.download(copy).await.unwrap().bytes().await.unwrap()[..], b"same");
+}
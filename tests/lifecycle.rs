@@ -0,0 +1,66 @@
+use std::time::Duration;
+use bucket_storage_client::Error;
+use sqlx::{PgPool, query};
+use tracing_test::traced_test;
+use uuid::Uuid;
+use bucket_storage::{buckets, lifecycle};
+use bucket_storage::store::Store;
+
+mod tools;
+use crate::tools::{AppData, bucket_id};
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn rules_expire_matching_entries(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let store = Store::new(data.store_dir());
+    let client = data.authorized();
+    let bucket_id = bucket_id(&pool).await;
+    buckets::set_trash_retention(&pool, bucket_id, Duration::from_secs(3600)).await.unwrap();
+    let export = client.upload("export-1.csv", "a,b", 3).await.unwrap();
+    client.upload("export-1.txt", "ab", 2).await.unwrap();
+    client.upload("report.csv", "c,d", 3).await.unwrap();
+
+    lifecycle::add_rule(&pool, bucket_id, Some("export".to_string()), Some("csv".to_string()), 2).await.unwrap();
+    assert!(lifecycle::add_rule(&pool, bucket_id, None, None, 0).await.is_err());
+    assert!(lifecycle::add_rule(&pool, Uuid::new_v4(), None, None, 1).await.is_err());
+    assert!(lifecycle::apply(&pool, &store, false).await.unwrap().expired.is_empty());
+
+    query!("UPDATE bucket_files SET created_at = now() - interval '3 days'").execute(&pool).await.unwrap();
+    let report = lifecycle::apply(&pool, &store, true).await.unwrap();
+    assert_eq!(report.expired.len(), 1);
+    assert_eq!(report.expired[0].entry_id, export);
+    assert_eq!(client.list().await.unwrap().len(), 3);
+
+    // Expired entries go through the regular delete and can be restored from the trash
+    assert_eq!(lifecycle::apply(&pool, &store, false).await.unwrap().expired.len(), 1);
+    assert_eq!(client.list().await.unwrap().len(), 2);
+    assert_eq!(client.trash().await.unwrap()[0].id, export);
+    assert!(lifecycle::apply(&pool, &store, false).await.unwrap().expired.is_empty());
+
+    let rules = lifecycle::rules(&pool, Some(bucket_id)).await.unwrap();
+    assert_eq!(rules.len(), 1);
+    lifecycle::remove_rule(&pool, rules[0].id).await.unwrap();
+    assert!(lifecycle::rules(&pool, None).await.unwrap().is_empty());
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn expiry_set_at_upload(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let store = Store::new(data.store_dir());
+    let client = data.authorized();
+    let temporary = client.upload_expiring("tmp.bin", "tmp", 3, Duration::from_secs(1)).await.unwrap();
+    client.upload_expiring("later.bin", "later", 5, Duration::from_secs(3600)).await.unwrap();
+    client.upload("kept.bin", "kept", 4).await.unwrap();
+    match client.upload_expiring("never.bin", "never", 5, Duration::from_secs(u64::MAX)).await {
+        Err(Error::BadRequest(_)) => {}
+        other => panic!("Overflowing expiration was accepted: {other:?}"),
+    }
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let report = lifecycle::apply(&pool, &store, false).await.unwrap();
+    assert_eq!(report.expired.len(), 1);
+    assert_eq!(report.expired[0].entry_id, temporary);
+    assert_eq!(client.list().await.unwrap().len(), 2);
+}
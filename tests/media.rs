@@ -0,0 +1,120 @@
+use std::io::Cursor;
+use bucket_storage_client::{Error, MediaKind};
+use image::{ImageFormat, Rgb, RgbImage};
+use lopdf::{dictionary, Document, Object};
+use sqlx::PgPool;
+use tracing_test::traced_test;
+use bucket_storage::buckets;
+
+mod tools;
+use crate::tools::{AppData, bucket_id};
+
+/// JPEG with an APP1 segment holding the camera make as its only EXIF field
+fn photo(width: u32, height: u32) -> Vec<u8> {
+    let mut encoded = Cursor::new(Vec::new());
+    RgbImage::from_pixel(width, height, Rgb([200, 100, 50])).write_to(&mut encoded, ImageFormat::Jpeg).unwrap();
+    let encoded = encoded.into_inner();
+
+    // Big endian TIFF header, one IFD entry of type ASCII pointing past the IFD
+    let mut tiff = b"MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+    tiff.extend_from_slice(&[0x01, 0x0F, 0x00, 0x02, 0, 0, 0, 8, 0, 0, 0, 26]);
+    tiff.extend_from_slice(&[0, 0, 0, 0]);
+    tiff.extend_from_slice(b"TestCam\0");
+    let mut app1 = b"Exif\0\0".to_vec();
+    app1.extend_from_slice(&tiff);
+
+    let mut jpeg = encoded[..2].to_vec();
+    jpeg.extend_from_slice(&[0xFF, 0xE1]);
+    jpeg.extend_from_slice(&(app1.len() as u16 + 2).to_be_bytes());
+    jpeg.extend_from_slice(&app1);
+    jpeg.extend_from_slice(&encoded[2..]);
+    jpeg
+}
+
+fn pdf(pages: usize) -> Vec<u8> {
+    let mut document = Document::with_version("1.5");
+    let pages_id = document.new_object_id();
+    let kids = (0..pages).map(|_| document.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id }).into()).collect::<Vec<Object>>();
+    document.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => kids, "Count" => pages as i64 }));
+    let catalog_id = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+    document.trailer.set("Root", catalog_id);
+    let mut bytes = Vec::new();
+    document.save_to(&mut bytes).unwrap();
+    bytes
+}
+
+/// Mono 16 bit PCM of silence
+fn wav(sample_rate: u32, seconds: u32) -> Vec<u8> {
+    let data_len = sample_rate * 2 * seconds;
+    let mut wav = b"RIFF".to_vec();
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&[1, 0, 1, 0]);
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    wav.extend_from_slice(&[2, 0, 16, 0]);
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    wav.resize(wav.len() + data_len as usize, 0);
+    wav
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn extract_on_upload(pool: PgPool) {
+    let data = AppData::new(pool).await;
+    let client = data.authorized();
+    let large = photo(2400, 1600);
+    let large = client.upload("photos/large.jpg", large.clone(), large.len() as u64).await.unwrap();
+    let small = photo(800, 600);
+    client.upload("photos/small.jpg", small.clone(), small.len() as u64).await.unwrap();
+    let report = pdf(3);
+    let report = client.upload("report.pdf", report.clone(), report.len() as u64).await.unwrap();
+    let song = wav(8000, 2);
+    let song = client.upload("song.wav", song.clone(), song.len() as u64).await.unwrap();
+    let latin = b"caf\xe9 cr\xe8me br\xfbl\xe9e, d\xe9j\xe0 vu, na\xefve fa\xe7ade";
+    client.upload("notes.txt", &latin[..], latin.len() as u64).await.unwrap();
+    client.upload("data.bin", "opaque", 6).await.unwrap();
+
+    let media = client.media(large).await.unwrap().unwrap();
+    assert_eq!(media.kind, MediaKind::Image);
+    assert_eq!(media.format, "jpeg");
+    assert_eq!((media.width, media.height), (Some(2400), Some(1600)));
+    assert_eq!(media.exif.get("Make").map(String::as_str), Some("TestCam"));
+    assert_eq!(client.media(report).await.unwrap().unwrap().pages, Some(3));
+    assert_eq!(client.media(song).await.unwrap().unwrap().duration, Some(2.0));
+    assert_eq!(client.download(song).await.unwrap().media().unwrap().kind, MediaKind::Audio);
+
+    let entries = client.list().await.unwrap();
+    let media = |name: &str| entries.iter().find(|entry| entry.file_name() == name).unwrap().media.clone();
+    assert_eq!(media("notes.txt").unwrap().encoding.as_deref(), Some("windows-1252"));
+    assert!(media("data.bin").is_none());
+
+    let wide = client.list_media("kind=image,width>2000").await.unwrap();
+    assert_eq!(wide.iter().map(|entry| entry.id).collect::<Vec<_>>(), [large]);
+    assert_eq!(client.list_media("kind=image").await.unwrap().len(), 2);
+    assert_eq!(client.list_media("duration>=1.5").await.unwrap()[0].id, song);
+    for filter in ["width>big", "size>1", "kind>image", "kind=\"image\"", "width"] {
+        assert!(matches!(client.list_media(filter).await, Err(Error::BadRequest(_))), "{filter}");
+    }
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn strip_exif(pool: PgPool) {
+    buckets::set_strip_exif(&pool, bucket_id(&pool).await, true).await.unwrap();
+    let data = AppData::new(pool).await;
+    let client = data.authorized();
+    let original = photo(64, 48);
+    let file = client.upload("photo.jpg", original.clone(), original.len() as u64).await.unwrap();
+
+    let stored = client.download(file).await.unwrap().bytes().await.unwrap();
+    assert!(stored.len() < original.len());
+    assert!(!stored.windows(4).any(|window| window == b"Exif"));
+    let image = image::load_from_memory(&stored).unwrap();
+    assert_eq!((image.width(), image.height()), (64, 48));
+    let media = client.media(file).await.unwrap().unwrap();
+    assert_eq!(media.width, Some(64));
+    assert!(media.exif.is_empty());
+}
@@ -0,0 +1,65 @@
+use std::collections::BTreeMap;
+use bucket_storage_client::Error;
+use reqwest::multipart::{Form, Part};
+use sqlx::PgPool;
+use tracing_test::traced_test;
+use uuid::Uuid;
+
+mod tools;
+use crate::tools::{AppData, KEY, KEY_ID};
+
+fn pairs(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+    pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn upload_read_and_patch(pool: PgPool) {
+    let data = AppData::new(pool).await;
+    let client = data.authorized();
+    let file_id = client.upload_with_metadata("invoice.pdf", "pdf", 3, &pairs(&[("customer_id", "42"), ("Source", "api")])).await.unwrap();
+    client.upload_with_metadata("other.pdf", "other", 5, &pairs(&[("customer_id", "7")])).await.unwrap();
+
+    let expected = pairs(&[("customer_id", "42"), ("source", "api")]);
+    assert_eq!(client.metadata(file_id).await.unwrap(), expected);
+    assert_eq!(client.download(file_id).await.unwrap().metadata(), expected);
+    let tagged = client.list_tagged("customer_id", "42").await.unwrap();
+    assert_eq!(tagged.len(), 1);
+    assert_eq!(tagged[0].metadata, expected);
+    assert!(client.list_tagged("customer_id", "4").await.unwrap().is_empty());
+
+    let changes = BTreeMap::from([("source".to_string(), None), ("stage".to_string(), Some("final".to_string()))]);
+    let updated = client.update_metadata(file_id, &changes).await.unwrap();
+    assert_eq!(updated, pairs(&[("customer_id", "42"), ("stage", "final")]));
+    assert_eq!(client.list_tagged("stage", "final").await.unwrap()[0].id, file_id);
+
+    let invalid = BTreeMap::from([("bad key".to_string(), Some("x".to_string()))]);
+    assert!(matches!(client.update_metadata(file_id, &invalid).await, Err(Error::BadRequest(_))));
+    let too_many = (0..40).map(|i| (format!("k{i}"), Some("v".to_string()))).collect();
+    assert!(matches!(client.update_metadata(file_id, &too_many).await, Err(Error::BadRequest(_))));
+    let too_long = BTreeMap::from([("note".to_string(), Some("x".repeat(2000)))]);
+    assert!(matches!(client.update_metadata(file_id, &too_long).await, Err(Error::BadRequest(_))));
+    assert!(matches!(client.update_metadata(Uuid::new_v4(), &changes).await, Err(Error::NotFound(_))));
+    assert!(matches!(client.metadata(Uuid::new_v4()).await, Err(Error::NotFound(_))));
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn multipart_fields(pool: PgPool) {
+    let data = AppData::new(pool).await;
+    let client = data.authorized();
+    let form = Form::new()
+        .part("file", Part::bytes(b"a".to_vec()).file_name("a.txt"))
+        .text("x-meta-batch", "7")
+        .part("file", Part::bytes(b"b".to_vec()).file_name("b.txt"));
+    let res = reqwest::Client::new().post(data.api("/upload"))
+        .basic_auth(KEY_ID, Some(KEY))
+        .header("X-Meta-Source", "import")
+        .multipart(form)
+        .send().await.unwrap();
+    assert!(res.status().is_success());
+
+    let entries = client.list_tagged("batch", "7").await.unwrap();
+    assert_eq!(entries.len(), 2);
+    assert!(entries.iter().all(|entry| entry.metadata == pairs(&[("batch", "7"), ("source", "import")])));
+}
@@ -0,0 +1,116 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use reqwest::{Method, StatusCode};
+use serde_json::Value;
+use sqlx::PgPool;
+use tracing_test::traced_test;
+use utoipa::OpenApi;
+use utoipa::openapi::PathItemType;
+use uuid::Uuid;
+use bucket_storage::openapi::ApiDoc;
+
+mod tools;
+use crate::tools::AppData;
+
+fn method(item: &PathItemType) -> Method {
+    match item {
+        PathItemType::Get => Method::GET,
+        PathItemType::Post => Method::POST,
+        PathItemType::Put => Method::PUT,
+        PathItemType::Delete => Method::DELETE,
+        PathItemType::Patch => Method::PATCH,
+        PathItemType::Head => Method::HEAD,
+        PathItemType::Options => Method::OPTIONS,
+        PathItemType::Trace => Method::TRACE,
+        PathItemType::Connect => Method::CONNECT,
+    }
+}
+
+/// Replaces every `{param}` segment with a random id, all path parameters are UUIDs
+fn concrete_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| if segment.starts_with('{') { Uuid::new_v4().to_string() } else { segment.to_string() })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Every `.route(path, method(handler)…)` of the server sources as a method and a path in OpenAPI syntax
+fn routed(dir: &Path, routes: &mut HashSet<(Method, String)>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            routed(&path, routes);
+            continue;
+        }
+        for line in fs::read_to_string(&path).unwrap().lines() {
+            let Some((_, route)) = line.split_once(".route(\"") else {
+                continue;
+            };
+            let (route_path, handlers) = route.split_once('"').unwrap();
+            let route_path = route_path.split('/')
+                .map(|segment| match segment.strip_prefix(':') {
+                    Some(param) => format!("{{{param}}}"),
+                    None => segment.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            let methods = handlers.split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '('))
+                .filter_map(|call| call.split_once('('))
+                .filter_map(|(name, _)| Method::from_bytes(name.to_ascii_uppercase().as_bytes()).ok())
+                .filter(|method| [Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::PATCH, Method::HEAD].contains(method));
+            for method in methods {
+                routes.insert((method, route_path.clone()));
+            }
+        }
+    }
+}
+
+#[test]
+fn routes_are_documented() {
+    let mut routes = HashSet::new();
+    routed(&Path::new(env!("CARGO_MANIFEST_DIR")).join("src"), &mut routes);
+    assert!(routes.len() > 1);
+
+    let spec = ApiDoc::openapi();
+    let documented: HashSet<(Method, String)> = spec.paths.paths.iter()
+        .flat_map(|(path, item)| item.operations.keys().map(|operation| (method(operation), path.clone())))
+        .collect();
+    for (method, path) in routes {
+        // The document itself is not part of the API it describes
+        if path == "/openapi.json" {
+            continue;
+        }
+        assert!(documented.contains(&(method.clone(), path.clone())), "{method} {path} is routed but not documented");
+    }
+}
+
+#[traced_test]
+#[sqlx::test]
+async fn documented_routes_exist(pool: PgPool) {
+    let data = AppData::new(pool).await;
+    let client = reqwest::Client::new();
+    let spec = ApiDoc::openapi();
+    assert!(!spec.paths.paths.is_empty());
+
+    for (path, item) in &spec.paths.paths {
+        for operation in item.operations.keys() {
+            let method = method(operation);
+            let res = client.request(method.clone(), data.api(&concrete_path(path))).send().await.unwrap();
+            let status = res.status();
+            let body = res.text().await.unwrap();
+            assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{path} is not routed for {method}");
+            assert!(!(status == StatusCode::NOT_FOUND && body == "Not found"), "{path} is not routed");
+        }
+    }
+}
+
+#[traced_test]
+#[sqlx::test]
+async fn served_spec_matches(pool: PgPool) {
+    let data = AppData::new(pool).await;
+    let served: Value = reqwest::get(data.api("/openapi.json")).await.unwrap().json().await.unwrap();
+    assert_eq!(served, serde_json::to_value(ApiDoc::openapi()).unwrap());
+    assert_eq!(served["components"]["securitySchemes"]["basic"]["scheme"], "basic");
+    assert!(served["components"]["schemas"]["ErrorResponse"].is_object());
+}
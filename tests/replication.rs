@@ -0,0 +1,122 @@
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use axum::body::Bytes;
+use futures::TryStreamExt;
+use sqlx::{PgPool, query};
+use tracing_test::traced_test;
+use uuid::Uuid;
+use bucket_storage::{admin, scrub};
+use bucket_storage::store::{Store, StoreFile};
+use bucket_storage::store::crypto::MasterKey;
+use bucket_storage::store::replication::{weighted_choice, Repair};
+
+mod tools;
+use crate::tools::AppData;
+
+async fn read(store: &Store, file: &StoreFile) -> Vec<u8> {
+    let chunks: Vec<Bytes> = store.stream(file, None).await.unwrap().try_collect().await.unwrap();
+    chunks.concat()
+}
+
+/// Roots of `store` holding a copy of the blob of `file`
+fn holders(store: &Store, file: &StoreFile) -> Vec<PathBuf> {
+    store.roots().iter().filter(|root| root.join(file.path()).exists()).cloned().collect()
+}
+
+#[tokio::test]
+async fn reads_fall_back_and_repair_restores_replicas() {
+    let dir = tempfile::tempdir().unwrap();
+    let roots = vec![dir.path().join("a"), dir.path().join("b"), dir.path().join("c")];
+    let store = Store::with_roots(roots, 2);
+    store.setup().await.unwrap();
+    let file = StoreFile::new(Uuid::new_v4(), Some("txt".to_string()));
+    let saved = store.save(&file, Bytes::from_static(b"mirrored")).await.unwrap();
+    let file = file.with_blob(Some(saved.blob));
+    assert_eq!(holders(&store, &file).len(), 2);
+    assert_eq!(store.repair(&file).await.unwrap(), Repair::Healthy);
+
+    let replicas = holders(&store, &file);
+    tokio::fs::remove_file(replicas[0].join(file.path())).await.unwrap();
+    assert_eq!(read(&store, &file).await, b"mirrored");
+    assert_eq!(store.repair(&file).await.unwrap(), Repair::Restored { copies: 1, removed: 0 });
+    assert_eq!(holders(&store, &file).len(), 2);
+
+    let replicas = holders(&store, &file);
+    tokio::fs::write(replicas[0].join(file.path()), b"bitflip!").await.unwrap();
+    assert_eq!(store.repair(&file).await.unwrap(), Repair::Restored { copies: 1, removed: 1 });
+    assert_eq!(holders(&store, &file).len(), 2);
+    assert_eq!(read(&store, &file).await, b"mirrored");
+    assert_eq!(store.blobs().await.unwrap().len(), 1);
+
+    for root in holders(&store, &file) {
+        tokio::fs::write(root.join(file.path()), b"bitflip!").await.unwrap();
+    }
+    assert_eq!(store.repair(&file).await.unwrap(), Repair::Lost);
+    assert_eq!(store.stream(&file, None).await.err().unwrap().kind(), ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn reads_skip_corrupted_replicas() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::with_roots(vec![dir.path().join("a"), dir.path().join("b")], 2).with_master_key(MasterKey::generate());
+    store.setup().await.unwrap();
+    let file = StoreFile::new(Uuid::new_v4(), None).with_data_key(Some(store.new_data_key().unwrap()));
+    let saved = store.save(&file, Bytes::from_static(b"mirrored")).await.unwrap();
+    let file = file.with_blob(Some(saved.blob));
+
+    // The first root is read first, its replica would fail decryption partway through the stream
+    let first = dir.path().join("a").join(file.path());
+    let mut flipped = tokio::fs::read(&first).await.unwrap();
+    *flipped.last_mut().unwrap() ^= 1;
+    tokio::fs::write(&first, flipped).await.unwrap();
+    // Range reads are not verified
+    assert!(store.stream(&file, Some(0..4)).await.unwrap().try_collect::<Vec<Bytes>>().await.is_err());
+    assert_eq!(read(&store, &file).await, b"mirrored");
+}
+
+#[test]
+fn placement_is_weighted() {
+    assert!(weighted_choice(&[0, 0], 1).is_err());
+    assert!(weighted_choice(&[5, 0], 2).is_err());
+    assert_eq!(weighted_choice(&[0, 7, 0], 1).unwrap(), vec![1]);
+
+    let mut chosen = vec![0; 2];
+    for _ in 0..1000 {
+        let picks = weighted_choice(&[1, 9], 1).unwrap();
+        chosen[picks[0]] += 1;
+    }
+    assert!(chosen[1] > chosen[0] * 4, "Placement ignores weights: {chosen:?}");
+
+    let mut picks = weighted_choice(&[1, 1, 1], 3).unwrap();
+    picks.sort();
+    assert_eq!(picks, vec![0, 1, 2]);
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn download_survives_lost_replica(pool: PgPool) {
+    let data = AppData::with_store(pool.clone(), |dir| Store::with_roots(vec![dir.join("a"), dir.join("b")], 2)).await;
+    let store = Store::with_roots(vec![data.store_dir().join("a"), data.store_dir().join("b")], 2);
+    let client = data.authorized();
+    let file_id = client.upload("data.txt", "replicated", 10).await.unwrap();
+
+    let rec = query!("SELECT extension, blob FROM files WHERE id = $1", file_id).fetch_one(&pool).await.unwrap();
+    let file = StoreFile::new(file_id, rec.extension).with_blob(rec.blob);
+    assert_eq!(holders(&store, &file).len(), 2);
+    tokio::fs::remove_file(data.store_dir().join("a").join(file.path())).await.unwrap();
+    assert_eq!(&client.download(file_id).await.unwrap().bytes().await.unwrap()[..], b"replicated");
+
+    let report = admin::repair(&pool, &store).await.unwrap();
+    assert_eq!(report.checked, 1);
+    assert_eq!(report.restored, vec![file_id]);
+    assert!(report.lost.is_empty());
+    assert_eq!(holders(&store, &file).len(), 2);
+    assert!(admin::fsck(&pool, &store).await.unwrap().is_clean());
+
+    // The scrubber checks every replica and repairs the first root from the second one
+    tokio::fs::write(data.store_dir().join("a").join(file.path()), b"bitflipped").await.unwrap();
+    let report = scrub::scrub(&pool, &store, 1024 * 1024).await.unwrap();
+    assert!(report.corrupted.is_empty());
+    assert_eq!(holders(&store, &file).len(), 2);
+    assert_eq!(&client.download(file_id).await.unwrap().bytes().await.unwrap()[..], b"replicated");
+}
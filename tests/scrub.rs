@@ -0,0 +1,114 @@
+use std::time::{Duration, Instant};
+use bucket_storage_client::Error;
+use reqwest::StatusCode;
+use sqlx::{PgPool, query};
+use tracing_test::traced_test;
+use bucket_storage::scrub;
+use bucket_storage::store::{Store, StoreFile};
+
+mod tools;
+use crate::tools::AppData;
+
+const RATE: u64 = 64 * 1024 * 1024;
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn corrupted_files_are_refused(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let store = Store::new(data.store_dir());
+    let client = data.authorized();
+    let good = client.upload("good.txt", "good", 4).await.unwrap();
+    let bad = client.upload("bad.txt", "bad", 3).await.unwrap();
+
+    let report = scrub::scrub(&pool, &store, RATE).await.unwrap();
+    assert_eq!(report.checked, 2);
+    assert_eq!(report.bytes, 7);
+    assert!(report.corrupted.is_empty());
+    let status = scrub::status(&pool).await.unwrap();
+    assert_eq!(status.unverified, 0);
+    assert!(status.oldest_verification.is_some());
+
+    let rec = query!("SELECT extension, blob FROM files WHERE id = $1", bad).fetch_one(&pool).await.unwrap();
+    let path = data.store_dir().join(StoreFile::new(bad, rec.extension).with_blob(rec.blob).path());
+    tokio::fs::write(&path, b"bit").await.unwrap();
+
+    let report = scrub::scrub(&pool, &store, RATE).await.unwrap();
+    assert_eq!(report.corrupted, vec![bad]);
+    assert_eq!(scrub::status(&pool).await.unwrap().corrupted, vec![bad]);
+    match client.download(bad).await {
+        Err(Error::Api { status, .. }) => assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY),
+        other => panic!("Corrupted file was not refused: {:?}", other.map(|_| ())),
+    }
+    assert_eq!(&client.download(good).await.unwrap().bytes().await.unwrap()[..], b"good");
+
+    tokio::fs::write(&path, b"bad").await.unwrap();
+    let report = scrub::scrub(&pool, &store, RATE).await.unwrap();
+    assert_eq!(report.repaired, vec![bad]);
+    assert!(scrub::status(&pool).await.unwrap().corrupted.is_empty());
+    assert_eq!(&client.download(bad).await.unwrap().bytes().await.unwrap()[..], b"bad");
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn uploading_intact_contents_heals_corrupted_files(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let store = Store::new(data.store_dir());
+    let client = data.authorized();
+    let bad = client.upload("bad.txt", "bad", 3).await.unwrap();
+
+    let rec = query!("SELECT extension, blob FROM files WHERE id = $1", bad).fetch_one(&pool).await.unwrap();
+    let path = data.store_dir().join(StoreFile::new(bad, rec.extension).with_blob(rec.blob).path());
+    tokio::fs::write(&path, b"bit").await.unwrap();
+    assert_eq!(scrub::scrub(&pool, &store, RATE).await.unwrap().corrupted, vec![bad]);
+
+    let again = client.upload("again.txt", "bad", 3).await.unwrap();
+    assert!(scrub::status(&pool).await.unwrap().corrupted.is_empty());
+    assert_eq!(&client.download(again).await.unwrap().bytes().await.unwrap()[..], b"bad");
+    assert_eq!(&client.download(bad).await.unwrap().bytes().await.unwrap()[..], b"bad");
+    assert!(scrub::scrub(&pool, &store, RATE).await.unwrap().corrupted.is_empty());
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn scrub_is_rate_limited(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let store = Store::new(data.store_dir());
+    let contents = vec![1u8; 100_000];
+    data.authorized().upload("data.bin", contents, 100_000).await.unwrap();
+
+    let started = Instant::now();
+    let report = scrub::scrub(&pool, &store, 400_000).await.unwrap();
+    assert_eq!(report.bytes, 100_000);
+    assert!(started.elapsed() >= Duration::from_millis(240));
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn unreadable_files_do_not_stop_the_pass(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let store = Store::new(data.store_dir());
+    let client = data.authorized();
+    let unreadable = client.upload("unreadable.txt", "unreadable", 10).await.unwrap();
+    let other = client.upload("other.txt", "other", 5).await.unwrap();
+
+    let rec = query!("SELECT extension, blob FROM files WHERE id = $1", unreadable).fetch_one(&pool).await.unwrap();
+    let path = data.store_dir().join(StoreFile::new(unreadable, rec.extension).with_blob(rec.blob).path());
+    tokio::fs::remove_file(&path).await.unwrap();
+    tokio::fs::create_dir(&path).await.unwrap();
+
+    let report = scrub::scrub(&pool, &store, RATE).await.unwrap();
+    assert_eq!(report.checked, 2);
+    assert_eq!(report.failed, vec![unreadable]);
+    assert!(report.corrupted.is_empty());
+    let status = scrub::status(&pool).await.unwrap();
+    assert_eq!(status.unverified, 0);
+    assert_eq!(status.failed, vec![unreadable]);
+    let rec = query!("SELECT verified_at FROM files WHERE id = $1", other).fetch_one(&pool).await.unwrap();
+    assert!(rec.verified_at.is_some());
+
+    tokio::fs::remove_dir(&path).await.unwrap();
+    tokio::fs::write(&path, b"unreadable").await.unwrap();
+    let report = scrub::scrub(&pool, &store, RATE).await.unwrap();
+    assert!(report.failed.is_empty());
+    assert!(scrub::status(&pool).await.unwrap().failed.is_empty());
+}
@@ -0,0 +1,62 @@
+use std::collections::BTreeMap;
+use bucket_storage_client::Error;
+use bucket_storage_client::models::SearchQuery;
+use sqlx::{PgPool, query};
+use time::OffsetDateTime;
+use tracing_test::traced_test;
+
+mod tools;
+use crate::tools::AppData;
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn filter_rank_and_paginate(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let client = data.authorized();
+    let report = client.upload("reports/q3_report.csv", "a,b,c", 5).await.unwrap();
+    client.upload("reports/q3.csv", "a", 1).await.unwrap();
+    client.upload("archive/old-report.txt", "old report", 10).await.unwrap();
+    let tagged = client.upload_with_metadata("report.pdf", "pdf pdf pdf", 11, &BTreeMap::from([("customer_id".to_string(), "42".to_string())])).await.unwrap();
+    client.upload("photo.png", "png", 3).await.unwrap();
+
+    let search = |query: SearchQuery| { let client = &client; async move { client.search(&query).await.unwrap() } };
+
+    let results = search(SearchQuery { q: Some("REPORT".to_string()), ..Default::default() }).await;
+    assert_eq!(results.total, 4);
+    // The closest path ranks first
+    assert_eq!(results.hits[0].entry.id, tagged);
+    assert!(results.hits.windows(2).all(|pair| pair[0].score >= pair[1].score));
+
+    // `_` matches only itself
+    let results = search(SearchQuery { q: Some("q3_".to_string()), ..Default::default() }).await;
+    assert_eq!(results.total, 1);
+    assert_eq!(results.hits[0].entry.id, report);
+
+    let results = search(SearchQuery { extension: Some("csv".to_string()), min_size: Some(2), ..Default::default() }).await;
+    assert_eq!(results.total, 1);
+    let results = search(SearchQuery { max_size: Some(3), ..Default::default() }).await;
+    assert_eq!(results.total, 2);
+    let results = search(SearchQuery { q: Some("report".to_string()), tag: Some("customer_id:42".to_string()), ..Default::default() }).await;
+    assert_eq!(results.hits.len(), 1);
+    assert_eq!(results.hits[0].entry.metadata["customer_id"], "42");
+
+    query!("UPDATE bucket_files SET created_at = now() - interval '10 days' WHERE entry_id = $1", report).execute(&pool).await.unwrap();
+    let week_ago = OffsetDateTime::now_utc() - time::Duration::days(7);
+    let results = search(SearchQuery { uploaded_before: Some(week_ago), ..Default::default() }).await;
+    assert_eq!(results.total, 1);
+    assert_eq!(results.hits[0].entry.id, report);
+    assert_eq!(search(SearchQuery { uploaded_after: Some(week_ago), ..Default::default() }).await.total, 4);
+
+    let first = search(SearchQuery { limit: Some(2), ..Default::default() }).await;
+    let second = search(SearchQuery { limit: Some(2), offset: Some(2), ..Default::default() }).await;
+    let third = search(SearchQuery { limit: Some(2), offset: Some(4), ..Default::default() }).await;
+    assert_eq!(first.total, 5);
+    assert_eq!((first.hits.len(), second.hits.len(), third.hits.len()), (2, 2, 1));
+    let mut ids: Vec<_> = first.hits.iter().chain(&second.hits).chain(&third.hits).map(|hit| hit.entry.id).collect();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), 5);
+
+    let invalid = client.search(&SearchQuery { limit: Some(0), ..Default::default() }).await;
+    assert!(matches!(invalid, Err(Error::BadRequest(_))));
+}
@@ -0,0 +1,75 @@
+use std::fs;
+use bucket_cli::sync::sync;
+use bucket_cli::transfer;
+use bucket_storage_client::FileEntry;
+use indicatif::{MultiProgress, ProgressDrawTarget};
+use sqlx::PgPool;
+use tracing_test::traced_test;
+use bucket_storage::buckets;
+
+mod tools;
+use crate::tools::{AppData, bucket_id};
+
+fn hidden() -> MultiProgress {
+    MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn sync_mirrors_nested_paths(pool: PgPool) {
+    let data = AppData::new(pool).await;
+    let client = data.authorized();
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("reports/2026")).unwrap();
+    fs::write(dir.path().join("notes.txt"), "notes").unwrap();
+    fs::write(dir.path().join("reports/2026/q3.csv"), "a,b").unwrap();
+    client.upload("stale.txt", "stale", 5).await.unwrap();
+
+    let report = sync(&client, dir.path(), false, 2, &hidden()).await.unwrap();
+    assert_eq!((report.uploaded, report.unchanged, report.deleted), (2, 0, 0));
+    let mut paths = client.list().await.unwrap().iter().map(FileEntry::file_name).collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(paths, ["notes.txt", "reports/2026/q3.csv", "stale.txt"]);
+
+    // Only the changed file is sent again, the entry missing locally goes away by its path
+    fs::write(dir.path().join("reports/2026/q3.csv"), "c,d").unwrap();
+    let report = sync(&client, dir.path(), true, 2, &hidden()).await.unwrap();
+    assert_eq!((report.uploaded, report.unchanged, report.deleted), (1, 1, 1));
+    let entries = client.list().await.unwrap();
+    assert_eq!(entries.len(), 2);
+    let q3 = entries.iter().find(|entry| entry.file_name() == "reports/2026/q3.csv").unwrap();
+    assert_eq!(&client.download(q3.id).await.unwrap().bytes().await.unwrap()[..], b"c,d");
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn sync_keeps_versioned_entries(pool: PgPool) {
+    buckets::set_versioning(&pool, bucket_id(&pool).await, true).await.unwrap();
+    let data = AppData::new(pool).await;
+    let client = data.authorized();
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("notes.txt"), "first").unwrap();
+    sync(&client, dir.path(), true, 2, &hidden()).await.unwrap();
+
+    // The upload lands on the same entry as a new version, which must not be deleted as the replaced one
+    fs::write(dir.path().join("notes.txt"), "second").unwrap();
+    assert_eq!(sync(&client, dir.path(), true, 2, &hidden()).await.unwrap().uploaded, 1);
+    let entries = client.list().await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(&client.download(entries[0].id).await.unwrap().bytes().await.unwrap()[..], b"second");
+    assert_eq!(client.versions(entries[0].id).await.unwrap().len(), 2);
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn download_creates_nested_directories(pool: PgPool) {
+    let data = AppData::new(pool).await;
+    let client = data.authorized();
+    let file_id = client.upload("reports/2026/q3.csv", "a,b", 3).await.unwrap();
+    let entry = client.list().await.unwrap().into_iter().find(|entry| entry.id == file_id).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join(entry.file_name());
+    transfer::download(&client, file_id, &target, transfer::bar(&hidden(), "q3.csv")).await.unwrap();
+    assert_eq!(fs::read(dir.path().join("reports/2026/q3.csv")).unwrap(), b"a,b");
+}
@@ -1,16 +1,29 @@
-use reqwest::Client;
+#![allow(dead_code)]
 use dotenv::dotenv;
-use sqlx::PgPool;
+use sqlx::{PgPool, query};
 use std::net::{SocketAddr, TcpListener};
-use bucket_storage::{app, AppState, Environment};
+use std::path::Path;
+use tempfile::TempDir;
+use uuid::Uuid;
+use bucket_storage::{app, AppState};
+use bucket_storage::store::Store;
+use bucket_storage::webhooks::WebhookConfig;
+use bucket_storage_client::Client;
 
+/// Credentials of the `bucket_keys` fixture
+pub const KEY_ID: &str = "195ea586-110f-454a-a7e6-87bbec64c41c";
+pub const KEY: &str = "ee014d6f-5798-44b0-9186-f68f3261146e";
 
-async fn spawn_app(pool: PgPool) -> SocketAddr {
+/// Bucket the fixture key belongs to
+pub async fn bucket_id(pool: &PgPool) -> Uuid {
+    query!("SELECT bucket_id FROM bucket_keys WHERE id = $1", Uuid::parse_str(KEY_ID).unwrap()).fetch_one(pool).await.unwrap().bucket_id
+}
+
+async fn spawn_app(app_state: AppState) -> SocketAddr {
     dotenv().ok();
 
     let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
     let addr = listener.local_addr().unwrap();
-    let app_state = AppState::custom(pool).await;
 
     tokio::spawn(async move {
         axum::Server::from_tcp(listener)
@@ -25,23 +38,49 @@ async fn spawn_app(pool: PgPool) -> SocketAddr {
 
 pub struct AppData {
     pub addr: SocketAddr,
+    store_dir: TempDir,
 }
 
 impl AppData {
     pub async fn new(pool: PgPool) -> Self {
+        Self::with_store(pool, |dir| Store::new(dir)).await
+    }
+
+    /// Spawns the app with a store configured by `store` on a temporary directory
+    pub async fn with_store(pool: PgPool, store: impl FnOnce(&Path) -> Store) -> Self {
+        let store_dir = tempfile::tempdir().expect("Failed to create store directory");
+        Self {
+            addr: spawn_app(AppState::custom(pool, store(store_dir.path())).await).await,
+            store_dir,
+        }
+    }
+
+    /// Spawns the app with webhooks configured by `webhooks`, test receivers listen on loopback
+    pub async fn with_webhooks(pool: PgPool, webhooks: WebhookConfig) -> Self {
+        let store_dir = tempfile::tempdir().expect("Failed to create store directory");
+        let app_state = AppState::custom(pool, Store::new(store_dir.path())).await.with_webhooks(webhooks);
         Self {
-            addr: spawn_app(pool).await,
+            addr: spawn_app(app_state).await,
+            store_dir,
         }
     }
 
+    pub fn store_dir(&self) -> &Path {
+        self.store_dir.path()
+    }
+
+    /// Client without credentials
     pub fn client(&self) -> Client {
-        Client::builder()
-            .build()
-            .expect("Failed to build reqwest client")
+        Client::new(format!("http://{}", self.addr))
+    }
+
+    /// Client authorized with the fixture key
+    pub fn authorized(&self) -> Client {
+        self.client().with_credentials(Uuid::parse_str(KEY_ID).unwrap(), KEY)
     }
 
     pub fn api(&self, uri: &str) -> String {
-        let mut url = format!("http://{}", self.addr);
+        let mut url = format!("http://{}/api", self.addr);
         if let Some(char) = uri.trim().chars().nth(0) {
             if char != '/' {
                url.push('/');
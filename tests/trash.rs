@@ -0,0 +1,84 @@
+use std::time::Duration;
+use bucket_storage_client::Error;
+use sqlx::{PgPool, query};
+use tracing_test::traced_test;
+use bucket_storage::{buckets, trash};
+use bucket_storage::store::Store;
+
+mod tools;
+use crate::tools::{AppData, bucket_id};
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn delete_and_restore(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let store = Store::new(data.store_dir());
+    let client = data.authorized();
+    buckets::set_trash_retention(&pool, bucket_id(&pool).await, Duration::from_secs(7 * 24 * 3600)).await.unwrap();
+    let file_id = client.upload("notes.txt", "hello", 5).await.unwrap();
+
+    client.delete(file_id).await.unwrap();
+    assert!(client.list().await.unwrap().is_empty());
+    assert!(matches!(client.download(file_id).await, Err(Error::NotFound(_))));
+    assert_eq!(store.blobs().await.unwrap().len(), 1);
+
+    let entries = client.trash().await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].id, file_id);
+    assert_eq!(entries[0].expires_at - entries[0].deleted_at, time::Duration::days(7));
+
+    client.restore(file_id).await.unwrap();
+    assert!(client.trash().await.unwrap().is_empty());
+    assert_eq!(&client.download(file_id).await.unwrap().bytes().await.unwrap()[..], b"hello");
+    assert!(matches!(client.restore(file_id).await, Err(Error::NotFound(_))));
+    assert!(matches!(client.purge(file_id).await, Err(Error::NotFound(_))));
+
+    client.delete(file_id).await.unwrap();
+    client.purge(file_id).await.unwrap();
+    assert!(client.trash().await.unwrap().is_empty());
+    assert!(store.blobs().await.unwrap().is_empty());
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn purge_after_retention(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let store = Store::new(data.store_dir());
+    let client = data.authorized();
+    buckets::set_trash_retention(&pool, bucket_id(&pool).await, Duration::from_secs(7 * 24 * 3600)).await.unwrap();
+    let expired = client.upload("old.txt", "old", 3).await.unwrap();
+    let kept = client.upload("new.txt", "new", 3).await.unwrap();
+
+    client.delete(expired).await.unwrap();
+    client.delete(kept).await.unwrap();
+    query!("UPDATE bucket_files SET trashed_at = now() - interval '8 days' WHERE entry_id = $1", expired).execute(&pool).await.unwrap();
+
+    // Past the retention the entry cannot be restored, even before the purger ran
+    assert!(matches!(client.restore(expired).await, Err(Error::NotFound(_))));
+    let report = trash::purge(&pool, &store).await.unwrap();
+    assert_eq!(report.entries, 1);
+    let entries = client.trash().await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].id, kept);
+    assert_eq!(store.blobs().await.unwrap().len(), 1);
+
+    // Shortening the retention expires entries already in the trash
+    buckets::set_trash_retention(&pool, bucket_id(&pool).await, Duration::from_secs(1)).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(trash::purge(&pool, &store).await.unwrap().entries, 1);
+    assert!(client.trash().await.unwrap().is_empty());
+    assert!(store.blobs().await.unwrap().is_empty());
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn trash_is_opt_in(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let store = Store::new(data.store_dir());
+    let client = data.authorized();
+
+    let file_id = client.upload("notes.txt", "hello", 5).await.unwrap();
+    client.delete(file_id).await.unwrap();
+    assert!(client.trash().await.unwrap().is_empty());
+    assert!(store.blobs().await.unwrap().is_empty());
+}
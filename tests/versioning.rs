@@ -0,0 +1,117 @@
+use axum::body::Bytes;
+use bucket_storage_client::{Client, Error};
+use sqlx::{PgPool, query};
+use tracing_test::traced_test;
+use uuid::Uuid;
+use bucket_storage::{buckets, files, versions};
+use bucket_storage::store::Store;
+
+mod tools;
+use crate::tools::{AppData, bucket_id};
+
+async fn enable_versioning(pool: &PgPool) {
+    let bucket_id = bucket_id(pool).await;
+    buckets::set_versioning(pool, bucket_id, true).await.unwrap();
+}
+
+async fn read(client: &Client, file_id: Uuid) -> Bytes {
+    client.download(file_id).await.unwrap().bytes().await.unwrap()
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn overwrite_list_and_restore(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let client = data.authorized();
+    enable_versioning(&pool).await;
+
+    let file_id = client.upload("notes.txt", "first", 5).await.unwrap();
+    assert_eq!(client.upload("notes.txt", "second", 6).await.unwrap(), file_id);
+    assert_ne!(client.upload("notes.md", "second", 6).await.unwrap(), file_id);
+    assert_eq!(&read(&client, file_id).await[..], b"second");
+    assert_eq!(client.list().await.unwrap().len(), 2);
+
+    let versions = client.versions(file_id).await.unwrap();
+    assert_eq!(versions.len(), 2);
+    assert!(versions[0].latest && !versions[1].latest);
+    assert_eq!(versions[1].size, Some(5));
+    let old = client.download_version(file_id, versions[1].id).await.unwrap().bytes().await.unwrap();
+    assert_eq!(&old[..], b"first");
+
+    let restored = client.restore_version(file_id, versions[1].id).await.unwrap();
+    assert_eq!(&read(&client, file_id).await[..], b"first");
+    let versions = client.versions(file_id).await.unwrap();
+    assert_eq!(versions.len(), 3);
+    assert_eq!(versions[0].id, restored);
+
+    assert!(matches!(client.download_version(file_id, Uuid::new_v4()).await, Err(Error::NotFound(_))));
+    assert!(matches!(client.versions(Uuid::new_v4()).await, Err(Error::NotFound(_))));
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn delete_leaves_marker(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let store = Store::new(data.store_dir());
+    let client = data.authorized();
+    enable_versioning(&pool).await;
+
+    let file_id = client.upload("report.bin", "v1", 2).await.unwrap();
+    client.upload("report.bin", "v2", 2).await.unwrap();
+    client.delete(file_id).await.unwrap();
+    assert!(matches!(client.download(file_id).await, Err(Error::NotFound(_))));
+    assert!(client.list().await.unwrap().is_empty());
+    assert!(client.delete(file_id).await.is_err());
+
+    let versions = client.versions(file_id).await.unwrap();
+    assert_eq!(versions.len(), 3);
+    assert!(versions[0].deleted && versions[0].latest);
+    assert!(matches!(client.restore_version(file_id, versions[0].id).await, Err(Error::BadRequest(_))));
+    assert_eq!(store.blobs().await.unwrap().len(), 2);
+
+    // Removing the marker makes the previous version the latest again
+    client.delete_version(file_id, versions[0].id).await.unwrap();
+    assert_eq!(&read(&client, file_id).await[..], b"v2");
+    assert_eq!(client.upload("report.bin", "v3", 2).await.unwrap(), file_id);
+
+    for version in client.versions(file_id).await.unwrap() {
+        client.delete_version(file_id, version.id).await.unwrap();
+    }
+    assert!(matches!(client.versions(file_id).await, Err(Error::NotFound(_))));
+    assert!(store.blobs().await.unwrap().is_empty());
+    assert_eq!(query!(r#"SELECT COUNT(*) AS "count!" FROM files"#).fetch_one(&pool).await.unwrap().count, 0);
+}
+
+#[traced_test]
+#[sqlx::test]
+async fn versions_share_deduplicated_files(pool: PgPool) {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::new(dir.path());
+    let mut transaction = pool.begin().await.unwrap();
+    let bucket_id = buckets::create_bucket(&mut transaction, "versions").await.unwrap();
+    transaction.commit().await.unwrap();
+    buckets::set_versioning(&pool, bucket_id, true).await.unwrap();
+
+    let mut transaction = pool.begin().await.unwrap();
+    let entry = files::save_file(&mut transaction, &store, bucket_id, "a", None, Bytes::from_static(b"same")).await.unwrap();
+    files::save_file(&mut transaction, &store, bucket_id, "a", None, Bytes::from_static(b"other")).await.unwrap();
+    files::save_file(&mut transaction, &store, bucket_id, "a", None, Bytes::from_static(b"same")).await.unwrap();
+    let copy = files::save_file(&mut transaction, &store, bucket_id, "b", None, Bytes::from_static(b"same")).await.unwrap();
+    transaction.commit().await.unwrap();
+    assert_ne!(entry, copy);
+    assert_eq!(store.blobs().await.unwrap().len(), 2);
+
+    // The blob stays while any version of any entry still references it
+    let mut transaction = pool.begin().await.unwrap();
+    let rows = query!("SELECT id FROM bucket_files WHERE entry_id = $1", entry).fetch_all(&mut transaction).await.unwrap();
+    let mut released = Vec::new();
+    for version in rows {
+        released.extend(versions::remove_version(&mut transaction, bucket_id, entry, version.id).await.unwrap());
+    }
+    transaction.commit().await.unwrap();
+    files::remove_released(&store, released).await;
+    assert_eq!(store.blobs().await.unwrap().len(), 1);
+
+    buckets::delete_bucket(&pool, &store, bucket_id).await.unwrap();
+    assert!(store.blobs().await.unwrap().is_empty());
+}
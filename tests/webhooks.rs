@@ -0,0 +1,225 @@
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::Router;
+use bucket_storage_client::{DeliveryQuery, DeliveryStatus, Error, WebhookEvent, WebhookPayload};
+use sqlx::{PgPool, query};
+use tracing_test::traced_test;
+use uuid::Uuid;
+use bucket_storage::{buckets, lifecycle, webhooks};
+use bucket_storage::store::Store;
+use bucket_storage::webhooks::{DeliveryClient, WebhookConfig};
+
+mod tools;
+use crate::tools::{AppData, bucket_id};
+
+/// Receivers listen on loopback, which webhooks only reach when private addresses are allowed
+const PRIVATE: WebhookConfig = WebhookConfig { allow_private: true };
+
+/// Endpoint recording the requests it receives and answering them with `status`
+#[derive(Default)]
+struct Receiver {
+    requests: Mutex<Vec<(HeaderMap, Bytes)>>,
+    status: AtomicU16,
+}
+
+impl Receiver {
+    fn spawn() -> (Arc<Self>, String) {
+        let receiver = Arc::new(Self { status: AtomicU16::new(200), ..Default::default() });
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let router = Router::new().route("/hook", post(receive)).with_state(receiver.clone());
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener).unwrap().serve(router.into_make_service()).await.unwrap()
+        });
+        (receiver, url)
+    }
+
+    /// Payloads received so far, checked against their signature
+    fn payloads(&self, secret: &str) -> Vec<WebhookPayload> {
+        self.requests.lock().unwrap().iter().map(|(headers, body)| {
+            let header = |name: &str| headers.get(name).unwrap().to_str().unwrap().to_string();
+            let timestamp = header(webhooks::TIMESTAMP_HEADER).parse().unwrap();
+            assert_eq!(header(webhooks::SIGNATURE_HEADER), format!("sha256={}", webhooks::sign(secret, timestamp, body)));
+            let payload: WebhookPayload = serde_json::from_slice(body).unwrap();
+            assert_eq!(header(webhooks::EVENT_HEADER), payload.event.as_str());
+            assert_eq!(header(webhooks::DELIVERY_HEADER), payload.id.to_string());
+            payload
+        }).collect()
+    }
+}
+
+async fn receive(State(receiver): State<Arc<Receiver>>, headers: HeaderMap, body: Bytes) -> StatusCode {
+    receiver.requests.lock().unwrap().push((headers, body));
+    StatusCode::from_u16(receiver.status.load(Ordering::SeqCst)).unwrap()
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn bucket_events(pool: PgPool) {
+    let data = AppData::with_webhooks(pool.clone(), PRIVATE).await;
+    let store = Store::new(data.store_dir());
+    let client = data.authorized();
+    let issued = data.client().issue_key().await.unwrap();
+    let other = data.client().with_credentials(issued.id, issued.key);
+    let http = DeliveryClient::new(PRIVATE);
+    let bucket_id = bucket_id(&pool).await;
+    let (receiver, url) = Receiver::spawn();
+
+    assert!(matches!(client.create_webhook("ftp://localhost/hook", &[WebhookEvent::ObjectCreated]).await, Err(Error::BadRequest(_))));
+    assert!(matches!(client.create_webhook(url.clone(), &[]).await, Err(Error::BadRequest(_))));
+    let all = [WebhookEvent::ObjectCreated, WebhookEvent::ObjectDeleted, WebhookEvent::ObjectExpired, WebhookEvent::QuotaWarning, WebhookEvent::ObjectCreated];
+    let webhook = client.create_webhook(url.clone(), &all).await.unwrap();
+    let secret = webhook.secret.unwrap();
+    assert_eq!(webhook.events.len(), 4);
+    let (_, other_url) = Receiver::spawn();
+    client.create_webhook(other_url, &[WebhookEvent::QuotaWarning]).await.unwrap();
+    let listed = client.webhooks().await.unwrap();
+    assert_eq!(listed.len(), 2);
+    assert!(listed.iter().all(|webhook| webhook.secret.is_none()));
+
+    buckets::set_quota(&pool, bucket_id, Some(10)).await.unwrap();
+    let report = client.upload("report.csv", "a,b", 3).await.unwrap();
+    let notes = client.upload("notes.txt", "hello world", 11).await.unwrap();
+    client.delete(notes).await.unwrap();
+    lifecycle::add_rule(&pool, bucket_id, None, Some("csv".to_string()), 1).await.unwrap();
+    query!("UPDATE bucket_files SET created_at = now() - interval '2 days'").execute(&pool).await.unwrap();
+    assert_eq!(lifecycle::apply(&pool, &store, false).await.unwrap().expired.len(), 1);
+
+    let delivered = webhooks::deliver(&pool, &http).await.unwrap();
+    assert_eq!((delivered.delivered, delivered.failed, delivered.dead), (6, 0, 0));
+    assert_eq!(webhooks::deliver(&pool, &http).await.unwrap().delivered, 0);
+
+    let payloads = receiver.payloads(&secret);
+    assert_eq!(payloads.len(), 5);
+    assert!(payloads.iter().all(|payload| payload.bucket_id == bucket_id));
+    let data = |event: WebhookEvent| payloads.iter().filter(|payload| payload.event == event).map(|payload| payload.data.clone()).collect::<Vec<_>>();
+    let created = data(WebhookEvent::ObjectCreated);
+    assert_eq!(created.len(), 2);
+    assert!(created.iter().any(|data| data["entryId"] == report.to_string() && data["size"] == 3 && data["extension"] == "csv"));
+    assert_eq!(data(WebhookEvent::QuotaWarning)[0]["usedBytes"], 14);
+    assert_eq!(data(WebhookEvent::ObjectDeleted)[0]["name"], "notes");
+    assert_eq!(data(WebhookEvent::ObjectExpired)[0]["entryId"], report.to_string());
+
+    // The warning is sent once until the bucket drops below the threshold
+    client.upload("more.bin", "more data", 9).await.unwrap();
+    webhooks::deliver(&pool, &http).await.unwrap();
+    let quota_warnings = receiver.payloads(&secret).iter().filter(|payload| payload.event == WebhookEvent::QuotaWarning).count();
+    assert_eq!(quota_warnings, 1);
+    let used_bytes = || async { query!("SELECT used_bytes FROM buckets WHERE id = $1", bucket_id).fetch_one(&pool).await.unwrap().used_bytes };
+    assert_eq!(used_bytes().await, 9);
+    buckets::set_quota(&pool, bucket_id, None).await.unwrap();
+    let uncounted = client.upload("uncounted.txt", "uncounted", 9).await.unwrap();
+    assert_eq!(used_bytes().await, 0);
+    buckets::set_quota(&pool, bucket_id, Some(100)).await.unwrap();
+    assert_eq!(used_bytes().await, 18);
+
+    // Copies and restores add objects without an upload
+    buckets::set_trash_retention(&pool, bucket_id, Duration::from_secs(3600)).await.unwrap();
+    let copy = client.copy(uncounted, "copy.txt").await.unwrap();
+    client.delete(uncounted).await.unwrap();
+    client.restore(uncounted).await.unwrap();
+    webhooks::deliver(&pool, &http).await.unwrap();
+    let created = receiver.payloads(&secret).into_iter().filter(|payload| payload.event == WebhookEvent::ObjectCreated).map(|payload| payload.data).collect::<Vec<_>>();
+    assert!(created.iter().any(|data| data["entryId"] == copy.to_string() && data["name"] == "copy"));
+    assert!(created.iter().any(|data| data["entryId"] == uncounted.to_string()));
+
+    // Moves into another bucket remove the object from this one and add it to the other
+    let (other_receiver, other_url) = Receiver::spawn();
+    let other_secret = other.create_webhook(other_url, &[WebhookEvent::ObjectCreated]).await.unwrap().secret.unwrap();
+    let received = receiver.payloads(&secret).len();
+    let moved = client.move_to(copy, &other, Some("moved.txt".to_string())).await.unwrap();
+    client.rename(uncounted, "renamed.txt").await.unwrap();
+    webhooks::deliver(&pool, &http).await.unwrap();
+    let payloads = receiver.payloads(&secret);
+    assert_eq!(payloads.len(), received + 1);
+    assert_eq!(payloads[received].event, WebhookEvent::ObjectDeleted);
+    assert_eq!(payloads[received].data["entryId"], copy.to_string());
+    assert_eq!(payloads[received].data["name"], "copy");
+    let created = other_receiver.payloads(&other_secret);
+    assert_eq!(created.len(), 1);
+    assert_eq!(created[0].event, WebhookEvent::ObjectCreated);
+    assert_eq!(created[0].data["entryId"], moved.to_string());
+    assert_eq!(created[0].data["name"], "moved");
+
+    client.delete_webhook(webhook.id).await.unwrap();
+    assert!(matches!(client.delete_webhook(webhook.id).await, Err(Error::NotFound(_))));
+    client.upload("ignored.bin", "x", 1).await.unwrap();
+    assert_eq!(webhooks::deliver(&pool, &http).await.unwrap().delivered, 0);
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn retries_and_dead_letters(pool: PgPool) {
+    let data = AppData::with_webhooks(pool.clone(), PRIVATE).await;
+    let client = data.authorized();
+    let http = DeliveryClient::new(PRIVATE);
+    let (receiver, url) = Receiver::spawn();
+    receiver.status.store(500, Ordering::SeqCst);
+    let webhook = client.create_webhook(url, &[WebhookEvent::ObjectCreated]).await.unwrap();
+    client.upload("file.txt", "hello", 5).await.unwrap();
+
+    let report = webhooks::deliver(&pool, &http).await.unwrap();
+    assert_eq!((report.delivered, report.failed), (0, 1));
+    // Failed deliveries wait for their backoff
+    assert_eq!(webhooks::deliver(&pool, &http).await.unwrap().failed, 0);
+    let delivery = client.webhook_deliveries(webhook.id, &DeliveryQuery::default()).await.unwrap().pop().unwrap();
+    assert_eq!((delivery.status, delivery.attempts, delivery.last_status_code), (DeliveryStatus::Pending, 1, Some(500)));
+    assert!(delivery.next_attempt_at > delivery.created_at);
+
+    for _ in 1..webhooks::MAX_ATTEMPTS {
+        query!("UPDATE webhook_deliveries SET next_attempt_at = now()").execute(&pool).await.unwrap();
+        webhooks::deliver(&pool, &http).await.unwrap();
+    }
+    let dead = client.webhook_deliveries(webhook.id, &DeliveryQuery { status: Some(DeliveryStatus::Dead), ..Default::default() }).await.unwrap();
+    assert_eq!(dead.len(), 1);
+    assert_eq!(dead[0].attempts, webhooks::MAX_ATTEMPTS);
+    assert_eq!(receiver.requests.lock().unwrap().len(), webhooks::MAX_ATTEMPTS as usize);
+    query!("UPDATE webhook_deliveries SET next_attempt_at = now()").execute(&pool).await.unwrap();
+    assert_eq!(webhooks::deliver(&pool, &http).await.unwrap().failed, 0);
+
+    receiver.status.store(204, Ordering::SeqCst);
+    let retried = client.retry_delivery(webhook.id, delivery.id).await.unwrap();
+    assert_eq!((retried.status, retried.attempts), (DeliveryStatus::Pending, 0));
+    assert_eq!(webhooks::deliver(&pool, &http).await.unwrap().delivered, 1);
+    let delivered = client.webhook_deliveries(webhook.id, &DeliveryQuery::default()).await.unwrap().pop().unwrap();
+    assert_eq!((delivered.status, delivered.last_status_code), (DeliveryStatus::Delivered, Some(204)));
+    assert!(delivered.delivered_at.is_some());
+    assert_eq!(delivered.payload.id, delivery.id);
+    assert!(matches!(client.retry_delivery(webhook.id, delivery.id).await, Err(Error::NotFound(_))));
+
+    assert!(matches!(client.retry_delivery(webhook.id, Uuid::new_v4()).await, Err(Error::NotFound(_))));
+    assert!(matches!(client.webhook_deliveries(Uuid::new_v4(), &DeliveryQuery::default()).await, Err(Error::NotFound(_))));
+    let query = DeliveryQuery { limit: Some(0), ..Default::default() };
+    assert!(matches!(client.webhook_deliveries(webhook.id, &query).await, Err(Error::BadRequest(_))));
+}
+
+#[traced_test]
+#[sqlx::test(fixtures("buckets","bucket_keys"))]
+async fn private_addresses(pool: PgPool) {
+    let data = AppData::new(pool.clone()).await;
+    let client = data.authorized();
+    let (receiver, url) = Receiver::spawn();
+
+    for url in [url.as_str(), "http://localhost/hook", "http://169.254.169.254/latest", "http://10.1.2.3/", "http://[::1]/", "http://[::ffff:192.168.0.1]/", "http://100.64.0.1/"] {
+        assert!(matches!(client.create_webhook(url, &[WebhookEvent::ObjectCreated]).await, Err(Error::BadRequest(_))), "{url}");
+    }
+    assert!(matches!(client.create_webhook("http://nonexistent.invalid/", &[WebhookEvent::ObjectCreated]).await, Err(Error::BadRequest(_))));
+
+    // Webhooks registered while private addresses were allowed are still not sent to them
+    let bucket_id = bucket_id(&pool).await;
+    for url in [url.clone(), url.replace("127.0.0.1", "localhost")] {
+        query!("INSERT INTO webhooks (bucket_id, url, secret, events) VALUES ($1, $2, 'secret', '{object.created}')", bucket_id, url).execute(&pool).await.unwrap();
+    }
+    client.upload("file.txt", "hello", 5).await.unwrap();
+    let report = webhooks::deliver(&pool, &DeliveryClient::new(WebhookConfig::default())).await.unwrap();
+    assert_eq!((report.delivered, report.failed), (0, 2));
+    assert!(receiver.requests.lock().unwrap().is_empty());
+    let errors = query!(r#"SELECT last_error AS "last_error!" FROM webhook_deliveries"#).fetch_all(&pool).await.unwrap();
+    assert!(errors.iter().all(|rec| rec.last_error.contains("public address")), "{:?}", errors.iter().map(|rec| &rec.last_error).collect::<Vec<_>>());
+}
@@ -0,0 +1,144 @@
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use axum::body::Bytes;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tokio::fs::File;
+use tokio::io::AsyncWrite;
+use tokio::sync::Notify;
+use tracing_test::traced_test;
+use uuid::Uuid;
+use bucket_storage::{admin, buckets, files};
+use bucket_storage::store::{write_atomic_with, Store, StoreFile};
+
+async fn temp_files(root: &Path) -> Vec<u64> {
+    let mut sizes = Vec::new();
+    let Ok(mut entries) = tokio::fs::read_dir(root.join("tmp")).await else {
+        return sizes;
+    };
+    while let Some(entry) = entries.next_entry().await.unwrap() {
+        sizes.push(entry.metadata().await.unwrap().len());
+    }
+    sizes
+}
+
+const OFFSET: usize = 64 * 1024;
+
+/// Writes through to the temp file until [`OFFSET`] bytes went out, then fails or never makes progress again
+struct Interrupted {
+    file: File,
+    remaining: usize,
+    fail: bool,
+    reached: Arc<Notify>,
+}
+
+impl Interrupted {
+    fn new(file: File, fail: bool, reached: Arc<Notify>) -> Self {
+        Self { file, remaining: OFFSET, fail, reached }
+    }
+}
+
+impl AsyncWrite for Interrupted {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        if self.remaining == 0 {
+            // Everything before the offset is on disk by the time the interruption is observed
+            ready!(Pin::new(&mut self.file).poll_flush(cx))?;
+            self.reached.notify_one();
+            return match self.fail {
+                true => Poll::Ready(Err(io::Error::other("No space left on device"))),
+                false => Poll::Pending,
+            };
+        }
+        let len = buf.len().min(self.remaining);
+        let written = ready!(Pin::new(&mut self.file).poll_write(cx, &buf[..len]))?;
+        self.remaining -= written;
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.file).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.file).poll_shutdown(cx)
+    }
+}
+
+fn blob_file(contents: &[u8]) -> StoreFile {
+    let blob = format!("{:x}", Sha256::digest(contents));
+    StoreFile::new(Uuid::new_v4(), None).with_blob(Some(blob))
+}
+
+#[tokio::test]
+async fn interrupted_save_leaves_no_blob() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::new(dir.path());
+    store.setup().await.unwrap();
+    let contents = Bytes::from(vec![7u8; 4 * OFFSET]);
+    let file = blob_file(&contents);
+
+    // Stall the write at a known offset, then drop it like a crashing process would
+    let reached = Arc::new(Notify::new());
+    let path = dir.path().join(file.path());
+    let save = write_atomic_with(dir.path(), &path, &contents, |temp| {
+        Interrupted::new(temp, false, reached.clone())
+    });
+    tokio::select! {
+        _ = save => panic!("Write finished before it was interrupted"),
+        _ = reached.notified() => {}
+    }
+    assert_eq!(temp_files(dir.path()).await, vec![OFFSET as u64]);
+
+    assert!(!store.exists(&file).await.unwrap());
+    assert!(store.blobs().await.unwrap().is_empty());
+    assert!(store.stream(&file, None).await.is_err());
+
+    store.setup().await.unwrap();
+    assert!(temp_files(dir.path()).await.is_empty());
+
+    let saved = store.save(&file, contents.clone()).await.unwrap();
+    assert_eq!(Some(saved.blob), file.blob);
+    assert_eq!(store.checksum(&file).await.unwrap(), bucket_storage::store::checksum(&contents));
+}
+
+#[tokio::test]
+async fn failed_write_removes_its_temp_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::new(dir.path());
+    store.setup().await.unwrap();
+    let contents = Bytes::from(vec![7u8; 4 * OFFSET]);
+    let file = blob_file(&contents);
+
+    let reached = Arc::new(Notify::new());
+    let err = write_atomic_with(dir.path(), &dir.path().join(file.path()), &contents, |temp| {
+        Interrupted::new(temp, true, reached.clone())
+    }).await.unwrap_err();
+    assert_eq!(err.to_string(), "No space left on device");
+
+    assert!(temp_files(dir.path()).await.is_empty());
+    assert!(!store.exists(&file).await.unwrap());
+    assert!(store.blobs().await.unwrap().is_empty());
+}
+
+#[traced_test]
+#[sqlx::test]
+async fn setup_sweeps_leftover_temp_files(pool: PgPool) {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::new(dir.path());
+    let mut transaction = pool.begin().await.unwrap();
+    let bucket_id = buckets::create_bucket(&mut transaction, "writes").await.unwrap();
+    files::save_file(&mut transaction, &store, bucket_id, "kept", None, Bytes::from_static(b"kept")).await.unwrap();
+    transaction.commit().await.unwrap();
+
+    tokio::fs::write(dir.path().join("tmp").join(Uuid::new_v4().to_string()), b"trunc").await.unwrap();
+    assert!(admin::gc(&pool, &store, true).await.unwrap().orphaned_blobs.is_empty());
+    assert_eq!(temp_files(dir.path()).await.len(), 1);
+
+    store.setup().await.unwrap();
+    assert!(temp_files(dir.path()).await.is_empty());
+    assert_eq!(store.sweep_temp().await.unwrap(), 0);
+    assert!(admin::fsck(&pool, &store).await.unwrap().is_clean());
+}